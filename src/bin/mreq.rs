@@ -3,6 +3,8 @@
  * together a request timeline
  */
 
+use std::io::IsTerminal;
+use std::io::Write;
 use std::process;
 
 /* Name of this program (used for error messages) */
@@ -13,34 +15,180 @@ const EXIT_FAILURE : i32 = 1;
 const EXIT_USAGE : i32 = 2;
 
 extern crate manta_mreq;
+use manta_mreq::MantaChromeTraceRenderer;
+use manta_mreq::MantaGanttRenderer;
+use manta_mreq::MantaJsonRenderer;
 use manta_mreq::MantaLogParserInput;
-use manta_mreq::mri_dump;
+use manta_mreq::MantaRenderer;
+use manta_mreq::MantaRequestFilter;
+use manta_mreq::MantaRequestInfo;
+use manta_mreq::MantaTextRenderer;
+use manta_mreq::MantaZipkinRenderer;
+use manta_mreq::MRI_STDIN_FILENAME;
 use manta_mreq::mri_parse_files;
 
 fn main()
 {
     let argv : Vec<String> = std::env::args().collect();
-    if argv.len() != 2 {
+    let mut filenames = Vec::new();
+    let mut filter = MantaRequestFilter::default();
+    let mut correct_skew = false;
+    let mut renderer : Box<dyn MantaRenderer> = Box::new(MantaTextRenderer);
+    let mut zipkin_filename = None;
+    let mut output_given = false;
+    let mut gantt = false;
+    let mut log_scale = false;
+
+    let mut args = argv.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--request-id" => {
+                filter.mrf_request_id = Some(option_value(&mut args));
+            },
+            "--url" => {
+                filter.mrf_url = Some(option_value(&mut args));
+            },
+            "--time" => {
+                let value = option_value(&mut args);
+                match value.parse() {
+                    Ok(t) => filter.mrf_time = Some(t),
+                    Err(e) => {
+                        eprintln!("{}: --time \"{}\": {}", ARG0, value, e);
+                        usage();
+                    }
+                }
+            },
+            "--correct-skew" => correct_skew = true,
+            "--zipkin" => zipkin_filename = Some(option_value(&mut args)),
+            "--gantt" => gantt = true,
+            "--log-scale" => log_scale = true,
+            "--output" => {
+                let value = option_value(&mut args);
+                output_given = true;
+                match value.as_str() {
+                    "text" => renderer = Box::new(MantaTextRenderer),
+                    "json" => renderer = Box::new(MantaJsonRenderer),
+                    "chrome-trace" => renderer =
+                        Box::new(MantaChromeTraceRenderer),
+                    _ => {
+                        eprintln!("{}: --output \"{}\": expected \"text\", \
+                            \"json\", or \"chrome-trace\"", ARG0, value);
+                        usage();
+                    }
+                }
+            },
+            _ if arg.starts_with('-') && arg != MRI_STDIN_FILENAME => usage(),
+            _ => filenames.push(arg.to_string())
+        }
+    }
+
+    if gantt && output_given {
+        eprintln!("{}: --gantt cannot be combined with --output", ARG0);
         usage();
     }
+    if log_scale && !gantt {
+        eprintln!("{}: --log-scale requires --gantt", ARG0);
+        usage();
+    }
+    if gantt {
+        renderer = Box::new(MantaGanttRenderer {
+            mgr_width : terminal_width(),
+            mgr_log_scale : log_scale
+        });
+    }
+
+    //
+    // With no files, we read standard input, as long as it's not a terminal
+    // (in which case the user more likely needs the usage message).
+    //
+    if filenames.is_empty() {
+        if std::io::stdin().is_terminal() {
+            usage();
+        }
+        filenames.push(String::from(MRI_STDIN_FILENAME));
+    }
 
     let input = MantaLogParserInput {
-        mli_muskie_filename: argv[1].to_string()
+        mli_filenames: filenames,
+        mli_filter: filter,
+        mli_correct_skew: correct_skew
+    };
+
+    let mli = match mri_parse_files(&input) {
+        Ok(mli) => mli,
+        Err(error) => fatal(error.to_string())
     };
 
-    match mri_parse_files(&input) {
-        Ok(mli) => mri_dump(&mli),
-        Err(error) => fatal(error)
+    let stdout = std::io::stdout();
+    if let Err(error) = renderer.render(&mli, &mut stdout.lock()) {
+        fatal(format!("writing report: {}", error));
+    }
+
+    if let Some(filename) = zipkin_filename {
+        if let Err(error) = write_zipkin(&mli, &filename) {
+            fatal(format!("{}: {}", filename, error));
+        }
     }
 }
 
-fn usage()
+fn write_zipkin(mli : &MantaRequestInfo, filename : &str)
+    -> std::io::Result<()>
 {
-    eprintln!("usage: {} MUSKIE_LOG", ARG0);
+    let mut out = std::io::BufWriter::new(std::fs::File::create(filename)?);
+    MantaZipkinRenderer.render(mli, &mut out)?;
+    out.flush()
+}
+
+fn option_value<'a, I>(args : &mut I)
+    -> String
+    where I : Iterator<Item = &'a String>
+{
+    match args.next() {
+        Some(value) => value.to_string(),
+        None => usage()
+    }
+}
+
+fn usage() -> !
+{
+    eprintln!("usage: {} [--request-id REQUEST_ID] [--url URL] [--time TIME] \
+        [--correct-skew]", ARG0);
+    eprintln!("           [--output text|json|chrome-trace | --gantt \
+        [--log-scale]]");
+    eprintln!("           [--zipkin FILE] [LOG_FILE...]");
+    eprintln!();
+    eprintln!("LOG_FILE \"-\" (or no LOG_FILE at all) reads standard input.");
+    eprintln!("If the Muskie logs contain more than one audit entry, use \
+        --request-id,");
+    eprintln!("--url, and --time (an ISO 8601 timestamp) to select one.");
+    eprintln!("--correct-skew shifts client and load balancer events on the \
+        timeline");
+    eprintln!("to correct for their estimated clock skew relative to \
+        Muskie.");
+    eprintln!("--output json prints the report as a JSON document instead \
+        of text.");
+    eprintln!("--output chrome-trace prints the timelines in the Chrome trace \
+        event format");
+    eprintln!("(for Perfetto or chrome://tracing).");
+    eprintln!("--gantt draws the timeline as a chart $COLUMNS wide (default \
+        80), with");
+    eprintln!("--log-scale using a logarithmic time axis.");
+    eprintln!("--zipkin also writes the request to FILE as Zipkin v2 JSON \
+        spans.");
     process::exit(EXIT_USAGE);
 }
 
-fn fatal(error : String)
+/*
+ * Returns the terminal width given by $COLUMNS, or else 80 columns.
+ */
+fn terminal_width()
+    -> usize
+{
+    std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()).filter(
+        |c| *c > 0).unwrap_or(80)
+}
+
+fn fatal(error : String) -> !
 {
     eprintln!("{}: {}", ARG0, error);
     process::exit(EXIT_FAILURE);
@@ -0,0 +1,440 @@
+/*
+ * src/date.rs: parsing HTTP "Date" header values
+ */
+
+//!
+//! # HTTP dates
+//!
+//! RFC 7231 (section 7.1.1.1) requires HTTP senders to generate dates in the
+//! RFC 1123 format:
+//!
+//! ```text
+//! Sun, 06 Nov 1994 08:49:37 GMT
+//! ```
+//!
+//! but requires recipients to also accept two obsolete formats: RFC 850
+//!
+//! ```text
+//! Sunday, 06-Nov-94 08:49:37 GMT
+//! ```
+//!
+//! and C's asctime() format (which is implicitly in GMT):
+//!
+//! ```text
+//! Sun Nov  6 08:49:37 1994
+//! ```
+//!
+//! Clients don't always follow the rules, so we additionally accept what RFC
+//! 2822 allows: a missing day of the week, numeric time zone offsets (e.g.,
+//! "-0700"), and the common North American zone names (e.g., "PDT").  As a last
+//! resort, we accept RFC 3339 timestamps (e.g., "1994-11-06T08:49:37Z").
+//!
+//! All of these formats have a resolution of one second, except for RFC 3339.
+//!
+//! A date can be well-formed and still be nonsense (e.g., from a client whose
+//! clock was never set, or one that mangled the year).  `mri_date_plausible()`
+//! checks that a date is reasonably close to when the request happened.
+//!
+
+use chrono::Datelike;
+use std::convert::TryFrom;
+
+///
+/// How far (in minutes) an HTTP date may be from the time Muskie spent
+/// processing the request before we decide that it's bogus rather than the
+/// result of clock skew.
+///
+pub const MRI_DATE_MAX_DISTANCE_MINUTES : i64 = 5;
+
+///
+/// Returns whether `when` is within `MRI_DATE_MAX_DISTANCE_MINUTES` of the
+/// interval from `start` to `end`.
+///
+pub fn mri_date_plausible(when : &chrono::DateTime<chrono::Utc>,
+    start : &chrono::DateTime<chrono::Utc>,
+    end : &chrono::DateTime<chrono::Utc>)
+    -> bool
+{
+    let slop = chrono::Duration::minutes(MRI_DATE_MAX_DISTANCE_MINUTES);
+    return *when >= *start - slop && *when <= *end + slop;
+}
+
+///
+/// Parses an HTTP date in any of the formats described in the module-level
+/// documentation.  On failure, returns a message describing the problem.
+///
+pub fn mri_parse_http_date(text : &str)
+    -> Result<chrono::DateTime<chrono::Utc>, String>
+{
+    let tokens : Vec<&str> = text.split_whitespace().collect();
+    if tokens.is_empty() {
+        return Err(String::from("empty date"));
+    }
+
+    //
+    // RFC 1123 and RFC 850 dates both start with a day of the week followed
+    // by a comma.  The day of the week is redundant, so we just make sure
+    // it's valid.  (RFC 2822 also allows it to be left out entirely.)
+    //
+    let mut rest = &tokens[..];
+    if let Some(wkday) = tokens[0].strip_suffix(',') {
+        if mri_date_weekday(wkday).is_none() {
+            return Err(format!("unrecognized day of the week: \"{}\"",
+                wkday));
+        }
+        rest = &tokens[1..];
+    } else if mri_date_weekday(tokens[0]).is_some() {
+        return mri_date_asctime(&tokens[1..]);
+    }
+
+    if rest.len() == 3 && rest[0].contains('-') {
+        return mri_date_rfc850(rest);
+    }
+
+    if rest.len() == 4 || rest.len() == 5 {
+        return mri_date_rfc1123(rest);
+    }
+
+    if tokens.len() == 1 {
+        return text.parse().map_err(|e : chrono::ParseError| format!(
+            "not an HTTP date or RFC 3339 timestamp: {}", e));
+    }
+
+    return Err(String::from("unrecognized date format"));
+}
+
+///
+/// Parses the part of an RFC 1123 date after the day of the week:
+/// "06 Nov 1994 08:49:37 GMT".  We also allow the zone to be missing, in which
+/// case it's assumed to be GMT.
+///
+fn mri_date_rfc1123(tokens : &[&str])
+    -> Result<chrono::DateTime<chrono::Utc>, String>
+{
+    let day = mri_date_number(tokens[0], "day")?;
+    let month = mri_date_month(tokens[1])?;
+    let year = mri_date_year(tokens[2])?;
+    let zone = tokens.get(4).cloned().unwrap_or("GMT");
+    return mri_date_assemble(year, month, day, tokens[3], zone);
+}
+
+///
+/// Parses the part of an RFC 850 date after the day of the week:
+/// "06-Nov-94 08:49:37 GMT".
+///
+fn mri_date_rfc850(tokens : &[&str])
+    -> Result<chrono::DateTime<chrono::Utc>, String>
+{
+    let parts : Vec<&str> = tokens[0].split('-').collect();
+    if parts.len() != 3 {
+        return Err(format!("expected day-month-year, but found \"{}\"",
+            tokens[0]));
+    }
+
+    let day = mri_date_number(parts[0], "day")?;
+    let month = mri_date_month(parts[1])?;
+    let year = mri_date_year(parts[2])?;
+
+    //
+    // RFC 7231 says that a two-digit year that appears to be more than 50
+    // years in the future is the most recent year in the past that had the
+    // same last two digits.
+    //
+    let year = if parts[2].len() != 2 { year } else {
+        let current = chrono::Utc::now().year();
+        let mut full = current - current % 100 + year;
+        if full > current + 50 {
+            full -= 100;
+        }
+        full
+    };
+
+    return mri_date_assemble(year, month, day, tokens[1], tokens[2]);
+}
+
+///
+/// Parses the part of an asctime() date after the day of the week:
+/// "Nov  6 08:49:37 1994".
+///
+fn mri_date_asctime(tokens : &[&str])
+    -> Result<chrono::DateTime<chrono::Utc>, String>
+{
+    if tokens.len() != 4 {
+        return Err(String::from("unrecognized date format"));
+    }
+
+    let month = mri_date_month(tokens[0])?;
+    let day = mri_date_number(tokens[1], "day")?;
+    let year = mri_date_year(tokens[3])?;
+    return mri_date_assemble(year, month, day, tokens[2], "GMT");
+}
+
+///
+/// Puts together a timestamp from its parsed date and the unparsed time of day
+/// ("08:49:37") and zone ("GMT", "PDT", or "-0700").
+///
+fn mri_date_assemble(year : i32, month : u32, day : u32, time : &str,
+    zone : &str)
+    -> Result<chrono::DateTime<chrono::Utc>, String>
+{
+    let date = chrono::NaiveDate::from_ymd_opt(year, month, day).ok_or_else(
+        || format!("invalid date: {:04}-{:02}-{:02}", year, month, day))?;
+    let time = chrono::NaiveTime::parse_from_str(time, "%H:%M:%S").map_err(
+        |e| format!("invalid time of day \"{}\": {}", time, e))?;
+    let offset = mri_date_zone(zone)?;
+    let local = chrono::NaiveDateTime::new(date, time);
+    let utc = local.checked_sub_signed(chrono::Duration::seconds(
+        i64::from(offset))).ok_or_else(|| format!(
+        "date out of range: {} {}", local, zone))?;
+    return Ok(chrono::DateTime::from_utc(utc, chrono::Utc));
+}
+
+///
+/// Returns the offset from UTC (in seconds) of time zone `zone`, which may be a
+/// name or a numeric offset like "-0700" or "+05:30".
+///
+fn mri_date_zone(zone : &str)
+    -> Result<i32, String>
+{
+    let hours = match zone.to_ascii_uppercase().as_str() {
+        "GMT" | "UT" | "UTC" | "Z" => Some(0),
+        "EDT" => Some(-4),
+        "EST" | "CDT" => Some(-5),
+        "CST" | "MDT" => Some(-6),
+        "MST" | "PDT" => Some(-7),
+        "PST" => Some(-8),
+        _ => None
+    };
+    if let Some(hours) = hours {
+        return Ok(hours * 3600);
+    }
+
+    let sign = match zone.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Err(format!("unrecognized time zone: \"{}\"", zone))
+    };
+    let digits = zone[1..].replace(':', "");
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("unrecognized time zone offset: \"{}\"", zone));
+    }
+
+    let hh : i32 = digits[0..2].parse().unwrap();
+    let mm : i32 = digits[2..4].parse().unwrap();
+    if hh > 23 || mm > 59 {
+        return Err(format!("unrecognized time zone offset: \"{}\"", zone));
+    }
+
+    return Ok(sign * (hh * 3600 + mm * 60));
+}
+
+/// Returns the month number (1-12) for an abbreviated month name.
+fn mri_date_month(name : &str)
+    -> Result<u32, String>
+{
+    let months = [ "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug",
+        "sep", "oct", "nov", "dec" ];
+    let lower = name.to_ascii_lowercase();
+    match months.iter().position(|m| *m == lower) {
+        Some(i) => Ok(i as u32 + 1),
+        None => Err(format!("unrecognized month: \"{}\"", name))
+    }
+}
+
+///
+/// Returns the day of the week for a day name, which may be abbreviated (as
+/// in RFC 1123 and asctime() dates) or not (as in RFC 850 dates).
+///
+fn mri_date_weekday(name : &str)
+    -> Option<chrono::Weekday>
+{
+    let days = [
+        ("mon", "monday", chrono::Weekday::Mon),
+        ("tue", "tuesday", chrono::Weekday::Tue),
+        ("wed", "wednesday", chrono::Weekday::Wed),
+        ("thu", "thursday", chrono::Weekday::Thu),
+        ("fri", "friday", chrono::Weekday::Fri),
+        ("sat", "saturday", chrono::Weekday::Sat),
+        ("sun", "sunday", chrono::Weekday::Sun),
+    ];
+    let lower = name.to_ascii_lowercase();
+    return days.iter().find(|(short, long, _)| lower == *short ||
+        lower == *long).map(|(_, _, day)| *day);
+}
+
+fn mri_date_number(text : &str, what : &str)
+    -> Result<u32, String>
+{
+    if text.is_empty() || !text.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("invalid {}: \"{}\"", what, text));
+    }
+
+    return text.parse().map_err(|_| format!("invalid {}: \"{}\"", what,
+        text));
+}
+
+///
+/// Parses the year `text`, which must fit in a signed 32-bit integer (chrono
+/// rejects years well before that).
+///
+fn mri_date_year(text : &str)
+    -> Result<i32, String>
+{
+    let year = mri_date_number(text, "year")?;
+    return i32::try_from(year).map_err(|_| format!("year out of range: {}",
+        year));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn parse(text : &str)
+        -> chrono::DateTime<chrono::Utc>
+    {
+        return mri_parse_http_date(text).unwrap_or_else(
+            |e| panic!("\"{}\": {}", text, e));
+    }
+
+    fn utc(year : i32, month : u32, day : u32, h : u32, m : u32, s : u32)
+        -> chrono::DateTime<chrono::Utc>
+    {
+        return chrono::Utc.ymd(year, month, day).and_hms(h, m, s);
+    }
+
+    #[test]
+    fn rfc1123()
+    {
+        let expected = utc(1994, 11, 6, 8, 49, 37);
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse("sun, 6 nov 1994 08:49:37 gmt"), expected);
+        assert_eq!(parse("06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37"), expected);
+        assert_eq!(parse("  Sun,  06 Nov 1994  08:49:37 GMT "), expected);
+    }
+
+    #[test]
+    fn rfc850()
+    {
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"),
+            utc(1994, 11, 6, 8, 49, 37));
+        assert_eq!(parse("Thursday, 09-May-19 21:34:23 GMT"),
+            utc(2019, 5, 9, 21, 34, 23));
+        assert_eq!(parse("Thursday, 09-May-2019 21:34:23 GMT"),
+            utc(2019, 5, 9, 21, 34, 23));
+
+        //
+        // Two-digit years up to 50 years from now are in this century (or the
+        // next one); those further out are in the past.
+        //
+        let current = chrono::Utc::now().year();
+        for (ahead, expected) in [ (50, current + 50), (51, current - 49) ].
+            iter() {
+            let date = format!("Monday, 01-Jan-{:02} 00:00:00 GMT",
+                (current + ahead) % 100);
+            assert_eq!(parse(&date).year(), *expected, "{}", date);
+        }
+    }
+
+    #[test]
+    fn asctime()
+    {
+        assert_eq!(parse("Sun Nov  6 08:49:37 1994"),
+            utc(1994, 11, 6, 8, 49, 37));
+        assert_eq!(parse("Thu May 9 21:34:23 2019"),
+            utc(2019, 5, 9, 21, 34, 23));
+    }
+
+    #[test]
+    fn zones()
+    {
+        let expected = utc(2019, 5, 9, 21, 34, 23);
+        assert_eq!(parse("Thu, 09 May 2019 21:34:23 UTC"), expected);
+        assert_eq!(parse("Thu, 09 May 2019 21:34:23 UT"), expected);
+        assert_eq!(parse("Thu, 09 May 2019 21:34:23 Z"), expected);
+        assert_eq!(parse("Thu, 09 May 2019 14:34:23 PDT"), expected);
+        assert_eq!(parse("Thu, 09 May 2019 13:34:23 PST"), expected);
+        assert_eq!(parse("Thu, 09 May 2019 17:34:23 EDT"), expected);
+        assert_eq!(parse("Thu, 09 May 2019 16:34:23 cdt"), expected);
+        assert_eq!(parse("Thu, 09 May 2019 14:34:23 -0700"), expected);
+        assert_eq!(parse("Fri, 10 May 2019 03:04:23 +05:30"), expected);
+        assert_eq!(parse("Thu, 09 May 2019 21:34:23 +0000"), expected);
+
+        assert!(mri_parse_http_date("Thu, 09 May 2019 21:34:23 XYZ").is_err());
+        assert!(mri_parse_http_date("Thu, 09 May 2019 21:34:23 +07").is_err());
+        assert!(mri_parse_http_date("Thu, 09 May 2019 21:34:23 +2400").
+            is_err());
+    }
+
+    #[test]
+    fn rfc3339()
+    {
+        assert_eq!(parse("2019-05-09T21:34:23Z"), utc(2019, 5, 9, 21, 34, 23));
+        assert_eq!(parse("2019-05-09T14:34:23.250-07:00"),
+            chrono::Utc.ymd(2019, 5, 9).and_hms_milli(21, 34, 23, 250));
+        assert!(mri_parse_http_date("2019-05-09").is_err());
+    }
+
+    #[test]
+    fn invalid()
+    {
+        for text in [
+            "",
+            "   ",
+            "yesterday",
+            "Thu, 09 May 2019",
+            "Thx, 09 May 2019 21:34:23 GMT",
+            "Thu, 09 Mai 2019 21:34:23 GMT",
+            "Thu, 31 Feb 2019 21:34:23 GMT",
+            "Thu, 09 May 2019 25:34:23 GMT",
+            "Thu, 09 May -19 21:34:23 GMT",
+            "Thursday, 09-May 21:34:23 GMT",
+            "Thu May 9 2019",
+        ].iter() {
+            assert!(mri_parse_http_date(text).is_err(), "\"{}\"", text);
+        }
+    }
+
+    ///
+    /// Years that chrono can't represent, or that can't be represented once
+    /// the zone's offset is applied, are errors rather than crashes.
+    ///
+    #[test]
+    fn out_of_range()
+    {
+        assert_eq!(mri_parse_http_date("Fri, 31 Dec 262143 23:00:00 -0700"),
+            Err(String::from("date out of range: +262143-12-31 23:00:00 \
+            -0700")));
+        assert_eq!(mri_parse_http_date("Mon, 01 Jan -262144 01:00:00 +0200"),
+            Err(String::from("invalid year: \"-262144\"")));
+        assert_eq!(mri_parse_http_date("Thu, 09 May 4294967295 21:34:23 GMT"),
+            Err(String::from("year out of range: 4294967295")));
+        assert_eq!(mri_parse_http_date("Thu May 9 21:34:23 2147483648"),
+            Err(String::from("year out of range: 2147483648")));
+        assert_eq!(mri_parse_http_date("Thu, 09 May 2147483647 21:34:23 GMT"),
+            Err(String::from("invalid date: 2147483647-05-09")));
+        assert_eq!(parse("Fri, 31 Dec 262143 16:00:00 -0700"),
+            utc(262143, 12, 31, 23, 0, 0));
+    }
+
+    #[test]
+    fn plausible()
+    {
+        let start = utc(2019, 5, 9, 21, 34, 23);
+        let end = utc(2019, 5, 9, 21, 36, 51);
+        assert!(mri_date_plausible(&start, &start, &end));
+        assert!(mri_date_plausible(&utc(2019, 5, 9, 21, 29, 23), &start,
+            &end));
+        assert!(mri_date_plausible(&utc(2019, 5, 9, 21, 41, 51), &start,
+            &end));
+        assert!(!mri_date_plausible(&utc(2019, 5, 9, 21, 29, 22), &start,
+            &end));
+        assert!(!mri_date_plausible(&utc(2019, 5, 9, 21, 41, 52), &start,
+            &end));
+        assert!(!mri_date_plausible(&parse("Sunday, 06-Nov-94 08:49:37 GMT"),
+            &start, &end));
+        assert!(!mri_date_plausible(&parse("Thu, 09 May 262142 21:34:23 GMT"),
+            &start, &end));
+    }
+}
@@ -0,0 +1,123 @@
+/*
+ * src/diagnostics.rs: non-fatal problems found while analyzing a request
+ */
+
+//!
+//! # Diagnostics
+//!
+//! Many problems with the input don't prevent us from reporting on a request,
+//! but they may make parts of the report less trustworthy (e.g., a client
+//! "Date" header that we can't parse, or response headers that don't agree
+//! with the request headers).  Rather than printing these as we find them,
+//! parsers and analysis steps push a `MantaDiagnostic` onto a
+//! `MantaDiagnostics` collector, which is attached to the request information
+//! and reported along with everything else.
+//!
+
+use std::fmt;
+
+///
+/// Describes how much a diagnostic should concern the user.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MantaDiagnosticSeverity {
+    /// something unusual that's unlikely to affect the report
+    Note,
+    /// something that may make parts of the report wrong or incomplete
+    Warning,
+}
+
+impl fmt::Display for MantaDiagnosticSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            MantaDiagnosticSeverity::Note => "note",
+            MantaDiagnosticSeverity::Warning => "warning",
+        })
+    }
+}
+
+///
+/// Describes one non-fatal problem.  `mdi_code` is a short, stable identifier
+/// for the kind of problem (e.g., "client-date-invalid") that consumers can
+/// match on.  `mdi_source` identifies the input field that the problem relates
+/// to, when there is one (e.g., "req.headers.date").
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MantaDiagnostic {
+    #[serde(rename = "severity")]   pub mdi_severity : MantaDiagnosticSeverity,
+    #[serde(rename = "code")]       pub mdi_code : String,
+    #[serde(rename = "message")]    pub mdi_message : String,
+    #[serde(rename = "source")]     pub mdi_source : Option<String>,
+}
+
+impl fmt::Display for MantaDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} [{}]: {}", self.mdi_severity, self.mdi_code,
+            self.mdi_message)?;
+        if let Some(ref source) = self.mdi_source {
+            write!(f, " (from {})", source)?;
+        }
+        Ok(())
+    }
+}
+
+///
+/// Collects diagnostics in the order they were found.
+///
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(transparent)]
+pub struct MantaDiagnostics {
+    mds_items : Vec<MantaDiagnostic>
+}
+
+impl MantaDiagnostics {
+    pub fn new()
+        -> MantaDiagnostics
+    {
+        return MantaDiagnostics { mds_items : Vec::new() };
+    }
+
+    ///
+    /// Records a diagnostic.  `source` identifies the input field involved, if
+    /// any.
+    ///
+    pub fn push(&mut self, severity : MantaDiagnosticSeverity, code : &str,
+        source : Option<&str>, message : String)
+    {
+        self.mds_items.push(MantaDiagnostic {
+            mdi_severity : severity,
+            mdi_code : String::from(code),
+            mdi_message : message,
+            mdi_source : source.map(String::from)
+        });
+    }
+
+    /// Records a diagnostic with severity `Warning`.
+    pub fn warn(&mut self, code : &str, source : Option<&str>,
+        message : String)
+    {
+        self.push(MantaDiagnosticSeverity::Warning, code, source, message);
+    }
+
+    /// Records a diagnostic with severity `Note`.
+    pub fn note(&mut self, code : &str, source : Option<&str>,
+        message : String)
+    {
+        self.push(MantaDiagnosticSeverity::Note, code, source, message);
+    }
+
+    /// Returns the diagnostics recorded so far, in the order they were found.
+    pub fn items(&self)
+        -> &[MantaDiagnostic]
+    {
+        return &self.mds_items;
+    }
+
+    /// Returns whether any diagnostics have been recorded.
+    pub fn is_empty(&self)
+        -> bool
+    {
+        return self.mds_items.is_empty();
+    }
+}
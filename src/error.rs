@@ -0,0 +1,224 @@
+/*
+ * src/error.rs: errors reported by this library
+ */
+
+//!
+//! # Errors
+//!
+//! Fallible operations in this library return a `MantaError`, which pairs a
+//! `MantaErrorKind` describing what went wrong with the input (and the line
+//! within it) where the problem was found, when we know them.  Consumers that
+//! want to handle particular failures should match on `MantaError::kind()`.
+//! Consumers that just want to report the failure can use the `Display`
+//! implementation, which includes the input name and line number.
+//!
+
+use std::error::Error;
+use std::fmt;
+
+///
+/// Describes an error encountered while reading inputs or putting together
+/// information about a request.  See module-level documentation for details.
+///
+#[derive(Debug)]
+pub struct MantaError {
+    mer_kind : MantaErrorKind,
+    mer_input : Option<String>,
+    mer_line : Option<u64>,
+}
+
+///
+/// Identifies the specific problem described by a `MantaError`.
+///
+#[derive(Debug)]
+pub enum MantaErrorKind {
+    ///
+    /// An input could not be opened or read.  This includes inputs that are
+    /// not valid UTF-8 or that exceed our limits on line length or input size
+    /// (both reported with kind `std::io::ErrorKind::InvalidData`).
+    ///
+    Io(std::io::Error),
+
+    /// A JSON record was not syntactically valid.
+    JsonSyntax(serde_json::Error),
+
+    /// A line in a line-oriented log (e.g., haproxy or Mako) was malformed.
+    Syntax(String),
+
+    /// A required field was missing.  The field is identified by its JSON path
+    /// (e.g., "req.caller.login").
+    MissingField(String),
+
+    ///
+    /// A field had a value that we don't support.  The first value identifies
+    /// the field (by JSON path, for JSON records) and the second describes the
+    /// problem.
+    ///
+    InvalidValue(String, String),
+
+    /// A bunyan record had a version (the "v" field) other than 0.
+    UnsupportedBunyanVersion(u64),
+
+    /// We could not determine the format of some inputs, or we were not given
+    /// the inputs we need.  The message describes what we found.
+    UnrecognizedFormat(String),
+
+    ///
+    /// The inputs did not identify exactly one request, or we could not
+    /// associate the other inputs with the request we found.  The message
+    /// describes why.
+    ///
+    Selection(String),
+}
+
+impl MantaError {
+    /// Returns a new error of the given kind without any location information.
+    pub fn new(kind : MantaErrorKind)
+        -> MantaError
+    {
+        return MantaError {
+            mer_kind : kind,
+            mer_input : None,
+            mer_line : None
+        };
+    }
+
+    ///
+    /// Records that this error was found in input `name`.  If the error
+    /// already identifies an input (e.g., because it was found inside an
+    /// archive), that's left alone.
+    ///
+    pub fn in_input(mut self, name : &str)
+        -> MantaError
+    {
+        if self.mer_input.is_none() {
+            self.mer_input = Some(String::from(name));
+        }
+
+        return self;
+    }
+
+    ///
+    /// Records that this error was found at 1-based line `line` of its input.
+    /// If the error already identifies a line, that's left alone.
+    ///
+    pub fn at_line(mut self, line : u64)
+        -> MantaError
+    {
+        if self.mer_line.is_none() {
+            self.mer_line = Some(line);
+        }
+
+        return self;
+    }
+
+    /// Returns what went wrong.
+    pub fn kind(&self)
+        -> &MantaErrorKind
+    {
+        return &self.mer_kind;
+    }
+
+    /// Returns the name of the input in which the problem was found, if known.
+    pub fn input(&self)
+        -> Option<&str>
+    {
+        return self.mer_input.as_deref();
+    }
+
+    /// Returns the line of the input at which the problem was found, if known.
+    pub fn line(&self)
+        -> Option<u64>
+    {
+        return self.mer_line;
+    }
+}
+
+impl From<MantaErrorKind> for MantaError {
+    fn from(kind : MantaErrorKind) -> MantaError {
+        MantaError::new(kind)
+    }
+}
+
+impl fmt::Display for MantaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref input) = self.mer_input {
+            write!(f, "\"{}\": ", input)?;
+        }
+
+        if let Some(line) = self.mer_line {
+            write!(f, "line {}: ", line)?;
+        }
+
+        write!(f, "{}", self.mer_kind)
+    }
+}
+
+impl fmt::Display for MantaErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MantaErrorKind::Io(e) => write!(f, "{}", e),
+            MantaErrorKind::JsonSyntax(e) => write!(f, "invalid JSON: {}", e),
+            MantaErrorKind::Syntax(message) => write!(f, "{}", message),
+            MantaErrorKind::MissingField(path) => {
+                write!(f, "missing required field \"{}\"", path)
+            },
+            MantaErrorKind::InvalidValue(path, message) => {
+                write!(f, "field \"{}\": {}", path, message)
+            },
+            MantaErrorKind::UnsupportedBunyanVersion(v) => {
+                write!(f, "expected bunyan version 0, but found {}", v)
+            },
+            MantaErrorKind::UnrecognizedFormat(message) => {
+                write!(f, "{}", message)
+            },
+            MantaErrorKind::Selection(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for MantaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self.mer_kind {
+            MantaErrorKind::Io(ref e) => Some(e),
+            MantaErrorKind::JsonSyntax(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+///
+/// Converts an error from deserializing a JSON record into the corresponding
+/// `MantaError`.  serde reports a missing field at the path of the object that
+/// should have contained it, so we append the field's name to that path.
+///
+pub fn mri_json_error(error : serde_path_to_error::Error<serde_json::Error>)
+    -> MantaError
+{
+    let path = error.path().to_string();
+    let inner = error.into_inner();
+    if inner.classify() != serde_json::error::Category::Data {
+        return MantaError::new(MantaErrorKind::JsonSyntax(inner));
+    }
+
+    //
+    // serde_json includes the position in the message, but that's meaningless
+    // when deserializing from an already-parsed value.
+    //
+    let message = inner.to_string();
+    let message = match message.find(" at line ") {
+        Some(i) => String::from(&message[0..i]),
+        None => message
+    };
+
+    let missing = message.strip_prefix("missing field `").and_then(
+        |rest| rest.strip_suffix('`'));
+    if let Some(field) = missing {
+        let path = if path == "." { String::from(field) } else {
+            format!("{}.{}", path, field)
+        };
+        return MantaError::new(MantaErrorKind::MissingField(path));
+    }
+
+    return MantaError::new(MantaErrorKind::InvalidValue(path, message));
+}
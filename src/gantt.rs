@@ -0,0 +1,329 @@
+/*
+ * src/gantt.rs: ASCII Gantt chart of a request's timeline
+ */
+
+//!
+//! # Gantt chart
+//!
+//! The timeline in the text report lists each event's start time and
+//! duration, which makes it hard to see proportions (e.g., that one handler
+//! took nearly all of a request's time).  `MantaGanttRenderer` draws the
+//! overall timeline as a chart instead: one row per event, with a bar showing
+//! when the event started and how long it took, all on the same time axis.
+//! Events of a subtimeline (e.g., the Muskie handlers) are indented under the
+//! event that contains them, just as they are in the text report.
+//!
+//! With `mgr_log_scale`, the time axis is logarithmic in the time since the
+//! start of the request.  This gives the early part of the request more room,
+//! which helps when one long event (usually a data transfer near the end)
+//! would otherwise squeeze everything before it into a few columns.
+//!
+
+use std::io;
+use std::io::Write;
+
+use super::render::MantaRenderer;
+use super::timeline::Timeline;
+use super::timeline::TimelineEvent;
+use super::MantaRequestInfo;
+
+/// narrowest chart area we'll draw, regardless of the requested width
+const MRI_GANTT_MIN_CHART : usize = 10;
+///
+/// narrowest and widest we'll make the column of event labels (which is also
+/// limited to a third of the requested width, to leave room for the chart)
+///
+const MRI_GANTT_MIN_LABEL : usize = 12;
+const MRI_GANTT_MAX_LABEL : usize = 40;
+/// width of everything on each row other than the label and the chart
+const MRI_GANTT_FIXED : usize = 2 + 6 + 1 + 6 + 1 + 1;
+
+///
+/// Renders the overall timeline of a request as an ASCII Gantt chart.  See
+/// the module-level documentation.
+///
+#[derive(Clone, Debug)]
+pub struct MantaGanttRenderer {
+    /// total width of the chart, in columns (usually the terminal's width)
+    pub mgr_width : usize,
+    /// use a logarithmic time axis instead of a linear one
+    pub mgr_log_scale : bool,
+}
+
+impl Default for MantaGanttRenderer {
+    fn default() -> Self {
+        MantaGanttRenderer {
+            mgr_width : 80,
+            mgr_log_scale : false
+        }
+    }
+}
+
+impl MantaRenderer for MantaGanttRenderer {
+    fn render(&self, mri : &MantaRequestInfo, out : &mut dyn Write)
+        -> io::Result<()>
+    {
+        let timeline = &mri.mri_timeline_overall;
+        let min_duration = chrono::Duration::milliseconds(1);
+
+        let mut rows = Vec::new();
+        let nskipped = mri_gantt_rows(timeline, min_duration, 0, &mut rows);
+
+        let label_max = (self.mgr_width / 3).clamp(MRI_GANTT_MIN_LABEL,
+            MRI_GANTT_MAX_LABEL);
+        let label_width = rows.iter().map(|r| r.mgw_label.chars().count()).
+            fold(MRI_GANTT_MIN_LABEL, std::cmp::max).min(label_max);
+        let chart_width = std::cmp::max(MRI_GANTT_MIN_CHART,
+            self.mgr_width.saturating_sub(MRI_GANTT_FIXED + label_width));
+        let axis = MantaGanttAxis {
+            mga_total_ms : mri_gantt_ms(timeline.total_elapsed()),
+            mga_width : chart_width,
+            mga_log : self.mgr_log_scale
+        };
+
+        writeln!(out, "GANTT CHART: starts at {}, {} ms total\n  ({} time \
+            axis, in milliseconds since the start)\n",
+            timeline.wall_start().format("%FT%T.%3fZ"),
+            timeline.total_elapsed().num_milliseconds(),
+            if self.mgr_log_scale { "log" } else { "linear" })?;
+
+        let blank = "";
+        writeln!(out, "  {:>6} {:>6} {:label_width$} {}", "rSTART", "ELAPSD",
+            "EVENT", axis.labels(), label_width = label_width)?;
+        writeln!(out, "  {:6} {:6} {:label_width$} {}", blank, blank, blank,
+            axis.ruler(), label_width = label_width)?;
+
+        for row in &rows {
+            let start_ms = mri_gantt_ms(row.mgw_start -
+                timeline.wall_start());
+            let duration_ms = mri_gantt_ms(row.mgw_duration);
+            writeln!(out, "  {:6} {:>6} {:label_width$} {}",
+                (row.mgw_start - timeline.wall_start()).num_milliseconds(),
+                if row.mgw_kind == '|' { String::from("-") } else {
+                    row.mgw_duration.num_milliseconds().to_string() },
+                mri_gantt_fit(&row.mgw_label, label_width),
+                axis.bar(start_ms, duration_ms, row.mgw_kind).trim_end(),
+                label_width = label_width)?;
+        }
+
+        writeln!(out)?;
+        if nskipped > 0 {
+            writeln!(out, "  NOTE: {} timeline event{} with duration less \
+                than {} ms {} not shown above.", nskipped,
+                if nskipped == 1 { "" } else { "s" },
+                min_duration.num_milliseconds(),
+                if nskipped == 1 { "was" } else { "were" })?;
+        }
+        writeln!(out, "  # event   = subtimeline   . unaccounted   \
+            | event with no duration")?;
+        return Ok(());
+    }
+}
+
+///
+/// One row of the chart.
+///
+struct MantaGanttRow {
+    /// label, indented for the depth of the subtimeline it's in
+    mgw_label : String,
+    mgw_start : chrono::DateTime<chrono::Utc>,
+    mgw_duration : chrono::Duration,
+    /// character used to draw the bar (see the legend)
+    mgw_kind : char,
+}
+
+///
+/// Appends to `rows` a row for each event of `timeline` (and its
+/// subtimelines, recursively), leaving out events that took some time but
+/// less than `min_duration`, as the text report's timeline does.  Returns the
+/// number of events left out.
+///
+fn mri_gantt_rows(timeline : &Timeline, min_duration : chrono::Duration,
+    depth : usize, rows : &mut Vec<MantaGanttRow>)
+    -> u16
+{
+    let mut nskipped = 0;
+
+    for event in timeline.events() {
+        if !event.duration().is_zero() && event.duration() < min_duration {
+            nskipped += 1;
+            continue;
+        }
+
+        rows.push(MantaGanttRow {
+            mgw_label : format!("{:width$}{}", "", event.label(),
+                width = depth * 4),
+            mgw_start : event.wall_start(),
+            mgw_duration : event.duration(),
+            mgw_kind : mri_gantt_kind(event)
+        });
+
+        if let Some(subtimeline) = event.subtimeline() {
+            nskipped += mri_gantt_rows(subtimeline, min_duration, depth + 1,
+                rows);
+        }
+    }
+
+    return nskipped;
+}
+
+fn mri_gantt_kind(event : &TimelineEvent)
+    -> char
+{
+    return if event.subtimeline().is_some() {
+        '='
+    } else if event.is_gap() {
+        '.'
+    } else if event.duration().is_zero() {
+        '|'
+    } else {
+        '#'
+    };
+}
+
+///
+/// Maps times (in milliseconds since the start of the timeline) to columns of
+/// the chart.
+///
+struct MantaGanttAxis {
+    mga_total_ms : f64,
+    mga_width : usize,
+    mga_log : bool,
+}
+
+impl MantaGanttAxis {
+    ///
+    /// Returns the position of time `ms` on the chart, from 0 (the start of
+    /// the timeline) to the chart's width (the end).
+    ///
+    fn position(&self, ms : f64)
+        -> f64
+    {
+        if self.mga_total_ms <= 0.0 {
+            return 0.0;
+        }
+
+        let fraction = if self.mga_log {
+            (1.0 + ms.max(0.0)).ln() / (1.0 + self.mga_total_ms).ln()
+        } else {
+            ms / self.mga_total_ms
+        };
+        return fraction.clamp(0.0, 1.0) * self.mga_width as f64;
+    }
+
+    /// Inverse of `position()`: returns the time at column `column`.
+    fn time(&self, column : usize)
+        -> f64
+    {
+        let fraction = column as f64 / self.mga_width as f64;
+        return if self.mga_log {
+            (fraction * (1.0 + self.mga_total_ms).ln()).exp() - 1.0
+        } else {
+            fraction * self.mga_total_ms
+        };
+    }
+
+    ///
+    /// Returns the bar for an event starting at `start_ms` and lasting
+    /// `duration_ms`, drawn with `kind`.  Every event gets at least one
+    /// column, so that short events are still visible.
+    ///
+    fn bar(&self, start_ms : f64, duration_ms : f64, kind : char)
+        -> String
+    {
+        let last = self.mga_width - 1;
+        let first = (self.position(start_ms).floor() as usize).min(last);
+        let end = if kind == '|' {
+            first + 1
+        } else {
+            (self.position(start_ms + duration_ms).ceil() as usize).clamp(
+                first + 1, self.mga_width)
+        };
+
+        let mut bar = String::with_capacity(self.mga_width);
+        for column in 0..self.mga_width {
+            bar.push(if column >= first && column < end { kind } else { ' ' });
+        }
+        return bar;
+    }
+
+    /// Returns the columns at which the axis is labeled.
+    fn ticks(&self)
+        -> Vec<usize>
+    {
+        let nticks = std::cmp::max(1, self.mga_width / 16);
+        return (0..=nticks).map(|i| i * (self.mga_width - 1) / nticks).
+            collect();
+    }
+
+    ///
+    /// Returns a line labeling the ticks with their times, leaving out any
+    /// label that would run into the previous one.
+    ///
+    fn labels(&self)
+        -> String
+    {
+        let mut line = String::new();
+        for column in self.ticks() {
+            //
+            // The last column ends at the end of the timeline, so that's the
+            // time we show for it.
+            //
+            let time = if column == self.mga_width - 1 {
+                self.mga_total_ms
+            } else {
+                self.time(column)
+            };
+            let label = format!("{:.0}", time);
+            let at = if column + label.len() > self.mga_width {
+                self.mga_width.saturating_sub(label.len())
+            } else {
+                column
+            };
+
+            if at < line.len() + (if line.is_empty() { 0 } else { 1 }) {
+                continue;
+            }
+            line.push_str(&format!("{:width$}{}", "", label,
+                width = at - line.len()));
+        }
+        return line;
+    }
+
+    /// Returns a line marking the ticks along the axis.
+    fn ruler(&self)
+        -> String
+    {
+        let ticks = self.ticks();
+        return (0..self.mga_width).map(|column| if ticks.contains(&column) {
+            '+' } else { '-' }).collect();
+    }
+}
+
+///
+/// Returns `duration` as a (possibly fractional) number of milliseconds.
+///
+fn mri_gantt_ms(duration : chrono::Duration)
+    -> f64
+{
+    return match duration.num_microseconds() {
+        Some(us) => us as f64 / 1000.0,
+        None => duration.num_milliseconds() as f64
+    };
+}
+
+///
+/// Returns `text` truncated to `width` columns, marking truncation with "..".
+///
+fn mri_gantt_fit(text : &str, width : usize)
+    -> String
+{
+    if text.chars().count() <= width {
+        return String::from(text);
+    }
+
+    let mut fitted : String = text.chars().take(width.saturating_sub(2)).
+        collect();
+    fitted.push_str("..");
+    return fitted;
+}
@@ -0,0 +1,198 @@
+/*
+ * src/handlers.rs: catalog of Muskie request handlers
+ */
+
+//!
+//! # Muskie handlers
+//!
+//! Muskie processes each request by running a chain of restify handlers, and
+//! its audit log entry records how long each one took (in `req.timers`).
+//! There are dozens of these, and their names don't mean much on their own,
+//! so we keep a catalog that assigns each known handler to a
+//! `MantaHandlerPhase`.  The timeline groups consecutive handlers in the same
+//! phase together.
+//!
+//! Restify names handlers that were registered as anonymous functions
+//! "handler-N", where N is the handler's position in the chain.  These can't
+//! be cataloged by name, since the same name may refer to different handlers
+//! on different routes.
+//!
+
+///
+/// Describes what part of processing a request a handler is involved with.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MantaHandlerPhase {
+    /// parsing the request and setting up state for the other handlers
+    Setup,
+    /// identifying the caller and verifying the request's signature
+    Authentication,
+    /// checking that the caller is allowed to make the request
+    Authorization,
+    /// looking up, checking, or saving object and directory metadata
+    Metadata,
+    /// selecting storage nodes and moving data to or from them
+    Storage,
+    /// preparing the response to the client
+    Response,
+}
+
+impl std::fmt::Display for MantaHandlerPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            MantaHandlerPhase::Setup => "setup",
+            MantaHandlerPhase::Authentication => "authentication",
+            MantaHandlerPhase::Authorization => "authorization",
+            MantaHandlerPhase::Metadata => "metadata",
+            MantaHandlerPhase::Storage => "storage",
+            MantaHandlerPhase::Response => "response",
+        })
+    }
+}
+
+///
+/// The catalog of known handlers.  When Muskie gains a handler, it should be
+/// added here.  (mreq reports handlers that aren't in the catalog.)
+///
+const MRI_HANDLER_CATALOG : &[(&str, MantaHandlerPhase)] = &[
+    ("earlySetup", MantaHandlerPhase::Setup),
+    ("parseDate", MantaHandlerPhase::Setup),
+    ("parseQueryString", MantaHandlerPhase::Setup),
+    ("enforceSSL", MantaHandlerPhase::Setup),
+    ("ensureDependencies", MantaHandlerPhase::Setup),
+    ("setup", MantaHandlerPhase::Setup),
+    ("storageContext", MantaHandlerPhase::Setup),
+    ("parseArguments", MantaHandlerPhase::Setup),
+
+    ("checkIfPresigned", MantaHandlerPhase::Authentication),
+    ("_authSetup", MantaHandlerPhase::Authentication),
+    ("preSignedUrl", MantaHandlerPhase::Authentication),
+    ("checkAuthzScheme", MantaHandlerPhase::Authentication),
+    ("parseAuthTokenHandler", MantaHandlerPhase::Authentication),
+    ("signatureHandler", MantaHandlerPhase::Authentication),
+    ("parseKeyId", MantaHandlerPhase::Authentication),
+    ("loadCaller", MantaHandlerPhase::Authentication),
+    ("verifySignature", MantaHandlerPhase::Authentication),
+    ("parseHttpAuthToken", MantaHandlerPhase::Authentication),
+    ("loadOwner", MantaHandlerPhase::Authentication),
+    ("getActiveRoles", MantaHandlerPhase::Authentication),
+
+    ("gatherContext", MantaHandlerPhase::Authorization),
+    ("authorize", MantaHandlerPhase::Authorization),
+
+    ("getMetadata", MantaHandlerPhase::Metadata),
+    ("checkIfMatch", MantaHandlerPhase::Metadata),
+    ("checkIfNoneMatch", MantaHandlerPhase::Metadata),
+    ("checkIfModified", MantaHandlerPhase::Metadata),
+    ("checkIfUnmodified", MantaHandlerPhase::Metadata),
+    ("ensureEntryExists", MantaHandlerPhase::Metadata),
+    ("assertMetadata", MantaHandlerPhase::Metadata),
+    ("ensureNotRoot", MantaHandlerPhase::Metadata),
+    ("ensureNotDirectory", MantaHandlerPhase::Metadata),
+    ("ensureParent", MantaHandlerPhase::Metadata),
+    ("enforceDirectoryCount", MantaHandlerPhase::Metadata),
+    ("getDirectoryCount", MantaHandlerPhase::Metadata),
+    ("getDirectory", MantaHandlerPhase::Metadata),
+    ("saveMetadata", MantaHandlerPhase::Metadata),
+    ("deletePointer", MantaHandlerPhase::Metadata),
+    ("mkdir", MantaHandlerPhase::Metadata),
+    ("rmdir", MantaHandlerPhase::Metadata),
+
+    ("findSharks", MantaHandlerPhase::Storage),
+    ("startSharkStreams", MantaHandlerPhase::Storage),
+    ("sharkStreams", MantaHandlerPhase::Storage),
+    ("streamFromSharks", MantaHandlerPhase::Storage),
+
+    ("negotiateContent", MantaHandlerPhase::Response),
+    ("verifyRange", MantaHandlerPhase::Response),
+];
+
+///
+/// Returns the phase of the handler called `name`, or `None` if it's not in
+/// the catalog.
+///
+pub fn mri_handler_phase(name : &str)
+    -> Option<MantaHandlerPhase>
+{
+    return MRI_HANDLER_CATALOG.iter().find(|(n, _)| *n == name).map(
+        |(_, phase)| *phase);
+}
+
+///
+/// Returns whether `name` is the name restify gives an anonymous handler.
+/// See the module-level documentation.
+///
+pub fn mri_handler_anonymous(name : &str)
+    -> bool
+{
+    return match name.strip_prefix("handler-") {
+        Some(n) => !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()),
+        None => false
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::latency::mri_latency_phase_category;
+    use super::super::latency::MantaLatencyCategory;
+    use super::*;
+
+    ///
+    /// Before the catalog existed, the latency breakdown categorized these
+    /// handlers by name.  Each must still be in the catalog, in a phase that
+    /// puts it in the same category.
+    ///
+    #[test]
+    fn catalog_covers_latency_categories()
+    {
+        let expected = [
+            ("_authSetup", MantaLatencyCategory::Authentication),
+            ("checkAuthzScheme", MantaLatencyCategory::Authentication),
+            ("parseAuthTokenHandler", MantaLatencyCategory::Authentication),
+            ("signatureHandler", MantaLatencyCategory::Authentication),
+            ("parseKeyId", MantaLatencyCategory::Authentication),
+            ("loadCaller", MantaLatencyCategory::Authentication),
+            ("verifySignature", MantaLatencyCategory::Authentication),
+            ("parseHttpAuthToken", MantaLatencyCategory::Authentication),
+            ("loadOwner", MantaLatencyCategory::Authentication),
+            ("getActiveRoles", MantaLatencyCategory::Authentication),
+            ("gatherContext", MantaLatencyCategory::Authentication),
+            ("authorize", MantaLatencyCategory::Authentication),
+            ("checkIfPresigned", MantaLatencyCategory::Authentication),
+            ("preSignedUrl", MantaLatencyCategory::Authentication),
+            ("getMetadata", MantaLatencyCategory::Metadata),
+            ("getDirectoryCount", MantaLatencyCategory::Metadata),
+            ("getDirectory", MantaLatencyCategory::Metadata),
+            ("ensureEntryExists", MantaLatencyCategory::Metadata),
+            ("assertMetadata", MantaLatencyCategory::Metadata),
+            ("ensureParent", MantaLatencyCategory::Metadata),
+            ("ensureNotRoot", MantaLatencyCategory::Metadata),
+            ("ensureNotDirectory", MantaLatencyCategory::Metadata),
+            ("enforceDirectoryCount", MantaLatencyCategory::Metadata),
+            ("saveMetadata", MantaLatencyCategory::Metadata),
+            ("deletePointer", MantaLatencyCategory::Metadata),
+            ("mkdir", MantaLatencyCategory::Metadata),
+            ("rmdir", MantaLatencyCategory::Metadata),
+            ("findSharks", MantaLatencyCategory::Storage),
+            ("startSharkStreams", MantaLatencyCategory::Storage),
+            ("sharkStreams", MantaLatencyCategory::Storage),
+            ("streamFromSharks", MantaLatencyCategory::Storage),
+        ];
+
+        for (name, category) in expected.iter() {
+            let phase = mri_handler_phase(name);
+            assert!(phase.is_some(), "handler \"{}\" is not cataloged", name);
+            assert_eq!(mri_latency_phase_category(phase), *category,
+                "handler \"{}\" has the wrong category", name);
+        }
+    }
+
+    #[test]
+    fn anonymous_handlers()
+    {
+        assert!(mri_handler_anonymous("handler-3"));
+        assert!(!mri_handler_anonymous("handler-"));
+        assert!(!mri_handler_anonymous("handler-x"));
+        assert!(!mri_handler_anonymous("getMetadata"));
+    }
+}
@@ -0,0 +1,225 @@
+/*
+ * src/json.rs: machine-readable report of a request
+ */
+
+//!
+//! # JSON output
+//!
+//! `MantaJsonRenderer` emits everything we know about a request as a single
+//! JSON object, for consumption by other programs.  The top-level object
+//! contains:
+//!
+//! * `version`: the version of this document's format (currently 1).  This
+//!   will be incremented when a change is made that could break existing
+//!   consumers (e.g., a property is removed or changes type).  New properties
+//!   may be added without changing the version.
+//! * `request_id`: the request id, or null if Muskie didn't report one
+//! * `muskie`: the Muskie audit log entry (see `MuskieAuditInfo`), including
+//!   the server, request and response details and headers, handler timers,
+//!   object metadata, storage nodes contacted, error, and data transfer sizes
+//! * `muskie_events`: other records Muskie logged for the request
+//! * `client`: the client's view of the request, or null if no client log was
+//!   provided
+//! * `load_balancer`: the load balancer's log entry, or null
+//! * `storage_nodes`: the storage node access log entry for each storage node
+//!   in `muskie.sharks_contacted` (in the same order), or null for each one
+//!   whose access log entry wasn't provided
+//! * `clock_skew`: estimated skew of each other source's clock relative to
+//!   Muskie's, and `skew_corrected`: whether the timelines were corrected for
+//!   it
+//! * `latency`: the critical path and where the request's time went
+//! * `timelines`: the `overall` timeline, the `muskie` handler timeline, and
+//!   the `storage_nodes` timeline (null if no storage nodes were contacted)
+//! * `diagnostics`: non-fatal problems found in the input
+//!
+//! Throughout the document, wall-clock times are ISO 8601 strings in UTC with
+//! microsecond precision, and durations are numbers of milliseconds (which may
+//! be fractional) in properties whose names end in "_ms".  Each timeline has
+//! `start` and `end` times, its `lanes`, and its `events`.  Each event has a
+//! `label`, `start` time (along with the `earliest_start` and `latest_start`
+//! it could have had, and the `provenance` of that time), its start relative
+//! to the start of the timeline containing it (`relative_start_ms`), its
+//! `duration_ms`, and its `lane`, `source`, and nested `timeline` (each of
+//! which may be null).  Events with `gap` set represent time not accounted for
+//! by any other event.
+//!
+
+use std::io;
+use std::io::Write;
+
+use serde::Serializer;
+
+use super::diagnostics::MantaDiagnostics;
+use super::latency::MantaLatencyBreakdown;
+use super::latency::MantaLatencyCategory;
+use super::log_client::ClientRequestInfo;
+use super::log_haproxy::HaproxyLogEntry;
+use super::log_mako::MakoLogEntry;
+use super::log_muskie::MuskieAuditInfo;
+use super::log_muskie::MuskieLogEvent;
+use super::render::MantaRenderer;
+use super::skew::MantaClockSkew;
+use super::timeline::Timeline;
+use super::MantaRequestInfo;
+
+/// version of the JSON document format (see module-level documentation)
+pub const MRI_JSON_VERSION : u32 = 1;
+
+#[derive(Serialize)]
+struct MantaJsonReport<'a> {
+    #[serde(rename = "version")]        mjr_version : u32,
+    #[serde(rename = "request_id")]     mjr_request_id : Option<String>,
+    #[serde(rename = "muskie")]         mjr_muskie : &'a MuskieAuditInfo,
+    #[serde(rename = "muskie_events")]
+    mjr_muskie_events : &'a [MuskieLogEvent],
+    #[serde(rename = "client")]
+    mjr_client : Option<&'a ClientRequestInfo>,
+    #[serde(rename = "load_balancer")]
+    mjr_load_balancer : Option<&'a HaproxyLogEntry>,
+    #[serde(rename = "storage_nodes")]
+    mjr_storage_nodes : &'a [Option<MakoLogEntry>],
+    #[serde(rename = "clock_skew")]     mjr_clock_skew : &'a [MantaClockSkew],
+    #[serde(rename = "skew_corrected")] mjr_skew_corrected : bool,
+    #[serde(rename = "latency")]
+    mjr_latency : &'a MantaLatencyBreakdown,
+    #[serde(rename = "timelines")]      mjr_timelines : MantaJsonTimelines<'a>,
+    #[serde(rename = "diagnostics")]
+    mjr_diagnostics : &'a MantaDiagnostics,
+}
+
+#[derive(Serialize)]
+struct MantaJsonTimelines<'a> {
+    #[serde(rename = "overall")]        mjt_overall : &'a Timeline,
+    #[serde(rename = "muskie")]         mjt_muskie : &'a Timeline,
+    #[serde(rename = "storage_nodes")]
+    mjt_storage_nodes : Option<&'a Timeline>,
+}
+
+///
+/// Renders the JSON document describing a request.  See the module-level
+/// documentation.
+///
+pub struct MantaJsonRenderer;
+
+impl MantaRenderer for MantaJsonRenderer {
+    fn render(&self, mri : &MantaRequestInfo, out : &mut dyn Write)
+        -> io::Result<()>
+    {
+        return mri_dump_json(out, mri);
+    }
+}
+
+fn mri_dump_json(out : &mut dyn Write, mri : &MantaRequestInfo)
+    -> io::Result<()>
+{
+    let report = MantaJsonReport {
+        mjr_version : MRI_JSON_VERSION,
+        mjr_request_id : mri.mri_muskie.request_id(),
+        mjr_muskie : &mri.mri_muskie,
+        mjr_muskie_events : &mri.mri_muskie_events,
+        mjr_client : mri.mri_client.as_ref(),
+        mjr_load_balancer : mri.mri_haproxy.as_ref(),
+        mjr_storage_nodes : &mri.mri_mako,
+        mjr_clock_skew : &mri.mri_clock_skew,
+        mjr_skew_corrected : mri.mri_skew_corrected,
+        mjr_latency : &mri.mri_latency,
+        mjr_timelines : MantaJsonTimelines {
+            mjt_overall : &mri.mri_timeline_overall,
+            mjt_muskie : &mri.mri_timeline_muskie,
+            mjt_storage_nodes : mri.mri_timeline_sharks.as_ref()
+        },
+        mjr_diagnostics : &mri.mri_diagnostics
+    };
+
+    //
+    // None of these types can fail to serialize: they contain no maps with
+    // non-string keys and no values that JSON can't represent.  So any error
+    // here came from writing to `out`.
+    //
+    serde_json::to_writer_pretty(&mut *out, &report)?;
+    writeln!(out)?;
+    return Ok(());
+}
+
+///
+/// Serializes a wall-clock time for the JSON report.  Use this with
+/// `#[serde(serialize_with = ...)]`.
+///
+pub fn mri_json_time<S>(time : &chrono::DateTime<chrono::Utc>, serializer : S)
+    -> Result<S::Ok, S::Error>
+    where S : Serializer
+{
+    return serializer.serialize_str(
+        &time.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string());
+}
+
+/// Like `mri_json_time()`, but for an optional time.
+pub fn mri_json_time_opt<S>(time : &Option<chrono::DateTime<chrono::Utc>>,
+    serializer : S)
+    -> Result<S::Ok, S::Error>
+    where S : Serializer
+{
+    return match time {
+        Some(t) => mri_json_time(t, serializer),
+        None => serializer.serialize_none()
+    };
+}
+
+/// Like `mri_json_time()`, but for a list of times.
+pub fn mri_json_times<S>(times : &[chrono::DateTime<chrono::Utc>],
+    serializer : S)
+    -> Result<S::Ok, S::Error>
+    where S : Serializer
+{
+    return serializer.collect_seq(times.iter().map(
+        |t| t.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string()));
+}
+
+///
+/// Serializes a duration for the JSON report as a (possibly fractional) number
+/// of milliseconds.  Use this with `#[serde(serialize_with = ...)]`.
+///
+pub fn mri_json_duration<S>(duration : &chrono::Duration, serializer : S)
+    -> Result<S::Ok, S::Error>
+    where S : Serializer
+{
+    return match duration.num_microseconds() {
+        Some(us) => serializer.serialize_f64(us as f64 / 1000.0),
+        None => serializer.serialize_i64(duration.num_milliseconds())
+    };
+}
+
+/// Like `mri_json_duration()`, but for an optional duration.
+pub fn mri_json_duration_opt<S>(duration : &Option<chrono::Duration>,
+    serializer : S)
+    -> Result<S::Ok, S::Error>
+    where S : Serializer
+{
+    return match duration {
+        Some(d) => mri_json_duration(d, serializer),
+        None => serializer.serialize_none()
+    };
+}
+
+///
+/// Serializes the per-category totals of a latency breakdown as a list of
+/// objects with properties `category` and `duration_ms`.
+///
+pub fn mri_json_categories<S>(
+    categories : &[(MantaLatencyCategory, chrono::Duration)], serializer : S)
+    -> Result<S::Ok, S::Error>
+    where S : Serializer
+{
+    #[derive(Serialize)]
+    struct Category<'a> {
+        #[serde(rename = "category")]
+        mjc_category : &'a MantaLatencyCategory,
+        #[serde(rename = "duration_ms", serialize_with = "mri_json_duration")]
+        mjc_duration : &'a chrono::Duration,
+    }
+
+    return serializer.collect_seq(categories.iter().map(|(c, d)| Category {
+        mjc_category : c,
+        mjc_duration : d
+    }));
+}
@@ -0,0 +1,306 @@
+/*
+ * src/latency.rs: attributing a request's latency to what it was waiting on
+ */
+
+//!
+//! # Latency attribution
+//!
+//! The timeline shows everything we know happened during a request, but many
+//! of those things overlap (e.g., the load balancer is waiting on Muskie the
+//! whole time Muskie is running its handlers), so adding up event durations
+//! doesn't say where the time went.  Instead, we compute the request's
+//! critical path: at each moment between the start and end of the overall
+//! timeline, we attribute the time to the most recently started event still
+//! in progress, since that's the innermost thing the request was waiting on.
+//! Moments when no event was in progress are attributed based on the events on
+//! either side (e.g., time between two load balancer events was spent in the
+//! load balancer).
+//!
+//! Each stretch of the critical path is assigned a `MantaLatencyCategory`,
+//! and the breakdown rolls up the time in each category.
+//!
+
+use super::handlers::mri_handler_phase;
+use super::handlers::MantaHandlerPhase;
+use super::timeline::Timeline;
+use super::timeline::TimelineEvent;
+
+///
+/// Describes broadly what a request was doing during some part of its
+/// critical path.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MantaLatencyCategory {
+    /// receiving, queueing, and forwarding the request in the load balancer
+    LoadBalancer,
+    /// identifying and authorizing the caller
+    Authentication,
+    /// looking up or saving metadata
+    Metadata,
+    /// selecting storage nodes and moving data to or from them
+    Storage,
+    /// other work done by Muskie
+    OtherMuskie,
+    /// finishing sending the response to the client
+    ClientTransfer,
+    /// time that no event accounts for
+    Unaccounted,
+}
+
+impl std::fmt::Display for MantaLatencyCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            MantaLatencyCategory::LoadBalancer => "load balancer",
+            MantaLatencyCategory::Authentication => "authentication",
+            MantaLatencyCategory::Metadata => "metadata",
+            MantaLatencyCategory::Storage => "storage",
+            MantaLatencyCategory::OtherMuskie => "other muskie processing",
+            MantaLatencyCategory::ClientTransfer => "client transfer",
+            MantaLatencyCategory::Unaccounted => "unaccounted",
+        })
+    }
+}
+
+///
+/// Describes one stretch of the critical path, during which the request was
+/// waiting on a single event (or on nothing we know about).
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct MantaPathSegment {
+    #[serde(rename = "label")]      pub mps_label : String,
+    #[serde(rename = "category")]   pub mps_category : MantaLatencyCategory,
+    #[serde(rename = "start", serialize_with = "super::json::mri_json_time")]
+    pub mps_start : chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "duration_ms",
+        serialize_with = "super::json::mri_json_duration")]
+    pub mps_duration : chrono::Duration,
+}
+
+///
+/// Summarizes where a request's time went.  `mlb_path` is the critical path,
+/// in order, and `mlb_categories` is the total time in each category that
+/// appears on it, largest first.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct MantaLatencyBreakdown {
+    #[serde(rename = "total_ms",
+        serialize_with = "super::json::mri_json_duration")]
+    pub mlb_total : chrono::Duration,
+    #[serde(rename = "critical_path")]
+    pub mlb_path : Vec<MantaPathSegment>,
+    #[serde(rename = "categories",
+        serialize_with = "super::json::mri_json_categories")]
+    pub mlb_categories : Vec<(MantaLatencyCategory, chrono::Duration)>,
+}
+
+impl MantaLatencyBreakdown {
+    ///
+    /// Returns `part` as a percentage of the request's total time.
+    ///
+    pub fn percent(&self, part : chrono::Duration)
+        -> f64
+    {
+        let total = self.mlb_total.num_microseconds().unwrap_or(0);
+        if total <= 0 {
+            return 0.0;
+        }
+
+        return 100.0 * part.num_microseconds().unwrap_or(0) as f64 /
+            total as f64;
+    }
+
+    ///
+    /// Returns the segment of the critical path that took the longest, if
+    /// there is one.
+    ///
+    pub fn largest(&self)
+        -> Option<&MantaPathSegment>
+    {
+        return self.mlb_path.iter().fold(None,
+            |best : Option<&MantaPathSegment>, s| match best {
+                Some(b) if b.mps_duration >= s.mps_duration => Some(b),
+                _ => Some(s)
+            });
+    }
+}
+
+///
+/// Computes the critical path of `timeline` (including its subtimelines) and
+/// rolls up its time by category.  See the module-level documentation.
+///
+pub fn mri_latency_breakdown(timeline : &Timeline)
+    -> MantaLatencyBreakdown
+{
+    let mut events = Vec::new();
+    mri_latency_flatten(timeline, &mut events);
+
+    //
+    // Only events that take time can be on the critical path.  Gap
+    // pseudo-events are treated like any other time not covered by an event,
+    // since we want to attribute them based on their surroundings.
+    //
+    let busy : Vec<&TimelineEvent> = events.iter().filter(
+        |e| !e.is_gap() && !e.duration().is_zero()).cloned().collect();
+
+    //
+    // We consider the time between each consecutive pair of times at which
+    // any event started or ended.  Point events matter here too, since they
+    // determine how we attribute time when nothing else is going on.
+    //
+    let start = timeline.wall_start();
+    let end = timeline.wall_end();
+    let mut times = vec![ start, end ];
+    for event in &events {
+        times.push(event.wall_start().clamp(start, end));
+        times.push(event.wall_end().clamp(start, end));
+    }
+    times.sort();
+    times.dedup();
+
+    //
+    // Unaccounted time after Muskie has finished with the request (and before
+    // the load balancer or client has) is the response making its way to the
+    // client.
+    //
+    let muskie_end = events.iter().filter(|e| e.source() == Some("muskie")).
+        map(|e| e.wall_end()).max();
+
+    let mut path : Vec<MantaPathSegment> = Vec::new();
+    for window in times.windows(2) {
+        let (from, to) = (window[0], window[1]);
+        let waiting = busy.iter().filter(|e| e.wall_start() <= from &&
+            e.wall_end() >= to).fold(None,
+            |best : Option<&&TimelineEvent>, e| match best {
+                Some(b) if b.wall_start() > e.wall_start() => Some(b),
+                _ => Some(e)
+            });
+
+        let (label, category) = match waiting {
+            Some(e) => (e.label(), mri_latency_category(e)),
+            None => {
+                let before = events.iter().filter(|e| !e.is_gap() &&
+                    e.wall_end() <= from).max_by_key(|e| e.wall_end());
+                let after = events.iter().find(|e| !e.is_gap() &&
+                    e.wall_start() >= to);
+                let gap = events.iter().find(|e| e.is_gap() &&
+                    e.wall_start() <= from && e.wall_end() >= to);
+                let label = match (gap, before, after) {
+                    (Some(g), _, _) => g.label(),
+                    (None, Some(b), Some(a)) => format!(
+                        "between \"{}\" and \"{}\"", b.label(), a.label()),
+                    _ => String::from("unaccounted")
+                };
+                (label, mri_latency_hole_category(before.cloned(),
+                    after.cloned(), from, muskie_end))
+            }
+        };
+
+        if let Some(last) = path.last_mut() {
+            if last.mps_label == label && last.mps_category == category {
+                last.mps_duration = last.mps_duration + (to - from);
+                continue;
+            }
+        }
+
+        path.push(MantaPathSegment {
+            mps_label : label,
+            mps_category : category,
+            mps_start : from,
+            mps_duration : to - from
+        });
+    }
+
+    let mut categories : Vec<(MantaLatencyCategory, chrono::Duration)> =
+        Vec::new();
+    for segment in &path {
+        match categories.iter_mut().find(|(c, _)| *c == segment.mps_category) {
+            Some((_, total)) => *total = *total + segment.mps_duration,
+            None => categories.push((segment.mps_category,
+                segment.mps_duration))
+        }
+    }
+    categories.sort_by(|(c1, d1), (c2, d2)| d2.cmp(d1).then(c1.cmp(c2)));
+
+    return MantaLatencyBreakdown {
+        mlb_total : end - start,
+        mlb_path : path,
+        mlb_categories : categories
+    };
+}
+
+///
+/// Appends to `events` the events of `timeline` that aren't subtimelines,
+/// replacing each subtimeline with its own events (recursively).
+///
+fn mri_latency_flatten<'a>(timeline : &'a Timeline,
+    events : &mut Vec<&'a TimelineEvent>)
+{
+    for event in timeline.events() {
+        match event.subtimeline() {
+            Some(subtimeline) => mri_latency_flatten(subtimeline, events),
+            None => events.push(event)
+        }
+    }
+}
+
+///
+/// Returns the category for time spent in `event`.  Muskie handlers are
+/// categorized by their phase (see the handlers module).
+///
+fn mri_latency_category(event : &TimelineEvent)
+    -> MantaLatencyCategory
+{
+    match event.source() {
+        Some("load balancer") => return MantaLatencyCategory::LoadBalancer,
+        Some("muskie") => (),
+        _ => return MantaLatencyCategory::Unaccounted
+    }
+
+    return mri_latency_phase_category(mri_handler_phase(&event.label()));
+}
+
+///
+/// Returns the category for time spent in a Muskie handler in phase `phase`
+/// (or in a handler whose phase is unknown).
+///
+pub fn mri_latency_phase_category(phase : Option<MantaHandlerPhase>)
+    -> MantaLatencyCategory
+{
+    return match phase {
+        Some(MantaHandlerPhase::Authentication) |
+            Some(MantaHandlerPhase::Authorization) =>
+            MantaLatencyCategory::Authentication,
+        Some(MantaHandlerPhase::Metadata) => MantaLatencyCategory::Metadata,
+        Some(MantaHandlerPhase::Storage) => MantaLatencyCategory::Storage,
+        _ => MantaLatencyCategory::OtherMuskie
+    };
+}
+
+///
+/// Returns the category for time starting at `when` during which no event was
+/// in progress.  `before` is the event that most recently ended and `after` is
+/// the next event to start, if any.  `muskie_end` is when Muskie finished with
+/// the request.
+///
+fn mri_latency_hole_category(before : Option<&TimelineEvent>,
+    after : Option<&TimelineEvent>, when : chrono::DateTime<chrono::Utc>,
+    muskie_end : Option<chrono::DateTime<chrono::Utc>>)
+    -> MantaLatencyCategory
+{
+    let source_before = before.and_then(|e| e.source());
+    let source_after = after.and_then(|e| e.source());
+
+    if muskie_end.is_some_and(|t| when >= t) &&
+        (source_after == Some("load balancer") ||
+        source_after == Some("client")) {
+        return MantaLatencyCategory::ClientTransfer;
+    }
+
+    if source_before == Some("load balancer") &&
+        source_after == Some("load balancer") {
+        return MantaLatencyCategory::LoadBalancer;
+    }
+
+    return MantaLatencyCategory::Unaccounted;
+}
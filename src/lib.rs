@@ -2,27 +2,198 @@
  * src/lib.rs: entry point for library use
  */
 
+#![allow(clippy::needless_return)]
+#![allow(clippy::println_empty_string)]
+#![allow(clippy::print_literal)]
+#![allow(clippy::write_literal)]
+
+extern crate bzip2_rs;
 extern crate chrono;
+extern crate flate2;
 extern crate serde;
-#[cfg_attr(test, macro_use)]
 extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_path_to_error;
+extern crate tar;
+extern crate xz2;
 
+mod date;
+mod diagnostics;
+mod error;
+mod gantt;
+mod handlers;
+mod json;
+mod latency;
+mod log_client;
 mod log_common;
+mod log_haproxy;
+mod log_mako;
 mod log_muskie;
+mod render;
+mod skew;
 mod timeline;
+mod trace;
+mod zipkin;
+
+use std::io::BufRead;
 
-pub use log_common::mri_read_file;
+use date::mri_date_plausible;
+use date::MRI_DATE_MAX_DISTANCE_MINUTES;
+use skew::mri_skew_for;
+use skew::MRI_SKEW_CLIENT;
+use skew::MRI_SKEW_LOAD_BALANCER;
+
+pub use date::mri_parse_http_date;
+pub use diagnostics::MantaDiagnostic;
+pub use diagnostics::MantaDiagnosticSeverity;
+pub use diagnostics::MantaDiagnostics;
+pub use error::MantaError;
+pub use error::MantaErrorKind;
+pub use gantt::MantaGanttRenderer;
+pub use handlers::mri_handler_phase;
+pub use handlers::MantaHandlerPhase;
+pub use json::MantaJsonRenderer;
+pub use json::MRI_JSON_VERSION;
+pub use latency::mri_latency_breakdown;
+pub use latency::MantaLatencyBreakdown;
+pub use latency::MantaLatencyCategory;
+pub use latency::MantaPathSegment;
+pub use log_client::mri_client_entries;
+pub use log_client::mri_client_entry;
+pub use log_client::mri_parse_client_file;
+pub use log_client::mri_client_request;
+pub use log_client::ClientLogEntry;
+pub use log_client::ClientRequestInfo;
+pub use log_common::mri_detect_format;
+pub use log_common::mri_open_file;
+pub use log_common::mri_visit_file;
+pub use log_common::LogLine;
+pub use log_common::LogLineReader;
+pub use log_common::MRI_STDIN_FILENAME;
+pub use log_common::MRI_MAX_FILE_BYTES;
+pub use log_common::MRI_MAX_LINE_BYTES;
+pub use log_common::MantaLogFormat;
+pub use log_haproxy::mri_parse_haproxy_file;
+pub use log_haproxy::mri_haproxy_entries;
+pub use log_haproxy::mri_haproxy_entry;
+pub use log_haproxy::mri_haproxy_match;
+pub use log_haproxy::HaproxyLogEntry;
+pub use log_mako::mri_parse_mako_file;
+pub use log_mako::mri_mako_entries;
+pub use log_mako::mri_mako_entry;
+pub use log_mako::mri_mako_match;
+pub use log_mako::mri_mako_related;
+pub use log_mako::MakoLogEntry;
 pub use log_muskie::mri_parse_muskie_file;
 pub use log_muskie::mri_audit_entry;
+pub use log_muskie::mri_muskie_timer;
 pub use log_muskie::MuskieAuditInfo;
+pub use log_muskie::MuskieAuditSharkContacted;
+pub use log_muskie::MuskieHeaders;
+pub use log_muskie::MuskieLog;
+pub use log_muskie::MuskieLogEntry;
+pub use log_muskie::MuskieLogEvent;
+pub use log_muskie::MuskieLogRecord;
+pub use log_muskie::MuskieValue;
+pub use render::MantaRenderer;
+pub use render::MantaTextRenderer;
+pub use skew::mri_estimate_skew;
+pub use skew::MantaClockSkew;
+pub use timeline::Timeline;
+pub use timeline::TimelineBuilder;
+pub use timeline::TimelineEvent;
+pub use timeline::TimelineProvenance;
+pub use trace::MantaChromeTraceRenderer;
+pub use zipkin::MantaZipkinRenderer;
+
+///
+/// Holes in a timeline at least this long (in milliseconds) are shown as
+/// "unaccounted" pseudo-events.  Shorter ones are still counted in the time
+/// accounting summary.
+///
+pub(crate) const MRI_GAP_THRESHOLD_MS : i64 = 10;
 
 /*
- * Represents validated end-user input.
+ * Represents validated end-user input.  The input files may be in any order and
+ * any of the formats described by `MantaLogFormat`.  At least one Muskie log is
+ * required.  If the Muskie logs contain more than one audit entry, `mli_filter`
+ * must select exactly one of them.
  */
 pub struct MantaLogParserInput {
-    pub mli_muskie_filename : String
+    pub mli_filenames : Vec<String>,
+    pub mli_filter : MantaRequestFilter,
+    /// shift other sources' events on the timeline to correct for clock skew
+    pub mli_correct_skew : bool
+}
+
+/*
+ * Describes which request to report on when the input contains more than one.
+ * Every criterion that's specified must match.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct MantaRequestFilter {
+    /// request id (the "x-request-id" header)
+    pub mrf_request_id : Option<String>,
+    /// request URL, with or without the query string
+    pub mrf_url : Option<String>,
+    /// a time at which the request was being processed by Muskie
+    pub mrf_time : Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl MantaRequestFilter {
+    fn is_empty(&self)
+        -> bool
+    {
+        return self.mrf_request_id.is_none() && self.mrf_url.is_none() &&
+            self.mrf_time.is_none();
+    }
+
+    ///
+    /// Returns whether the raw log entry `mle` matches the request id and URL
+    /// criteria.  These can be checked before validating the entry, which lets
+    /// us skip validating the vast majority of entries in a large log.
+    ///
+    fn matches_entry(&self, mle : &MuskieLogEntry)
+        -> bool
+    {
+        if let Some(ref wanted) = self.mrf_request_id {
+            if mle.request_id().as_ref() != Some(wanted) {
+                return false;
+            }
+        }
+
+        if let Some(ref wanted) = self.mrf_url {
+            let url = match mle.mle_request {
+                Some(ref req) => &req.mle_req_url,
+                None => return false
+            };
+            let path = url.split('?').next().unwrap_or(url);
+            if url != wanted && (wanted.contains('?') || path != wanted) {
+                return false;
+            }
+        }
+
+        return true;
+    }
+
+    ///
+    /// Returns whether the validated audit entry `mai` matches the time
+    /// criterion.  Users commonly have times with only one-second resolution,
+    /// so we allow a second of slop on either side of the request.
+    ///
+    fn matches_audit(&self, mai : &MuskieAuditInfo)
+        -> bool
+    {
+        if let Some(ref when) = self.mrf_time {
+            let slop = chrono::Duration::seconds(1);
+            if *when < mai.wall_start() - slop || *when > mai.mai_time + slop {
+                return false;
+            }
+        }
+
+        return true;
+    }
 }
 
 /*
@@ -31,220 +202,682 @@ pub struct MantaLogParserInput {
  */
 pub struct MantaRequestInfo {
     mri_muskie : MuskieAuditInfo,
+    /// other records Muskie logged for this request, in the order logged
+    mri_muskie_events : Vec<MuskieLogEvent>,
+    mri_client : Option<ClientRequestInfo>,
+    mri_haproxy : Option<HaproxyLogEntry>,
+    /// Mako log entries matching each of `mri_muskie.mai_sharks_contacted`
+    mri_mako : Vec<Option<MakoLogEntry>>,
     mri_timeline_overall : timeline::Timeline,
     mri_timeline_muskie : timeline::Timeline,
-    mri_timeline_sharks : Option<timeline::Timeline>
+    mri_timeline_sharks : Option<timeline::Timeline>,
+    /// critical path of the overall timeline and where its time went
+    mri_latency : MantaLatencyBreakdown,
+    /// estimated skew of each other source's clock relative to Muskie's
+    mri_clock_skew : Vec<MantaClockSkew>,
+    /// whether the timeline was adjusted using `mri_clock_skew`
+    mri_skew_corrected : bool,
+    /// non-fatal problems found while putting this information together
+    mri_diagnostics : MantaDiagnostics
+}
+
+impl MantaRequestInfo {
+    ///
+    /// Returns the non-fatal problems found while reading the inputs and
+    /// analyzing the request.
+    ///
+    pub fn diagnostics(&self)
+        -> &MantaDiagnostics
+    {
+        return &self.mri_diagnostics;
+    }
 }
 
 pub fn mri_parse_files(mli : &MantaLogParserInput)
-    -> Result<MantaRequestInfo, String>
+    -> Result<MantaRequestInfo, MantaError>
 {
-    let muskie_log = mri_parse_muskie_file(&mli.mli_muskie_filename)?;
-    let muskie_entry = muskie_log.muskie_entries[0].clone();
-    let audit_entry = mri_audit_entry(&muskie_entry)?;
+    if mli.mli_filenames.iter().filter(|f| *f == MRI_STDIN_FILENAME).count() >
+        1 {
+        return Err(MantaError::new(MantaErrorKind::UnrecognizedFormat(
+            String::from("standard input (\"-\") may only be specified \
+            once"))));
+    }
+
+    //
+    // Each input file may contain several logs (if it's an archive), and
+    // compressed inputs can only be read from the beginning, so we make two
+    // passes over the inputs.  The first pass determines the format of every
+    // log and selects the audit entry from the Muskie logs.  The second pass
+    // reads the other logs, which we can only filter once we know which
+    // request we're looking for.  This includes the non-audit records from
+    // the Muskie logs, unless the user gave us the request id up front.
+    // Standard input can only be read once, so we keep all of the entries from
+    // any other logs found there during the first pass and filter them
+    // afterwards.
+    //
+    let mut detected = Vec::new();
+    let mut need_second_pass = vec![false; mli.mli_filenames.len()];
+    let mut selection = MuskieAuditSelection::new(&mli.mli_filter);
+    let mut others = MantaOtherEntries::default();
+    for (i, filename) in mli.mli_filenames.iter().enumerate() {
+        let is_stdin = filename == MRI_STDIN_FILENAME;
+        mri_visit_file(filename, &mut |name, reader| {
+            let mut lines = LogLineReader::new(name, reader);
+            let format = mri_detect_format(&mut lines)?;
+            detected.push((String::from(name), format));
+            match format {
+                Some(MantaLogFormat::Muskie) => {
+                    let wanted = mli.mli_filter.mrf_request_id.as_deref();
+                    if wanted.is_none() && !is_stdin {
+                        need_second_pass[i] = true;
+                    }
+                    selection.add_log(MuskieLog::new(lines), &mut |event| {
+                        if is_stdin || wanted.is_some() {
+                            others.add_muskie_event(event, wanted);
+                        }
+                    })?;
+                },
+                Some(f) if is_stdin => others.add_log(lines, f, None)?,
+                Some(_) => need_second_pass[i] = true,
+                None => ()
+            }
+            Ok(())
+        })?;
+    }
+
+    if detected.iter().any(|(_, format)| format.is_none()) {
+        return Err(MantaError::new(MantaErrorKind::UnrecognizedFormat(
+            format!("unable to determine the format of some input files:\n{}",
+            mri_describe_inputs(&detected)))));
+    }
+
+    if !detected.iter().any(|(_, f)| *f == Some(MantaLogFormat::Muskie)) {
+        return Err(MantaError::new(MantaErrorKind::UnrecognizedFormat(
+            format!("expected at least one Muskie log, but found none:\n{}",
+            mri_describe_inputs(&detected)))));
+    }
+
+    let mut diagnostics = MantaDiagnostics::new();
+    let audit_entry = selection.finish(&mut diagnostics)?;
+    mri_check_headers(&audit_entry, &mut diagnostics);
+
+    //
+    // The other logs may be large, so as we read them, we keep only the
+    // entries that could possibly describe this request.
+    //
+    let request_id = audit_entry.request_id();
+    let have_events = mli.mli_filter.mrf_request_id.is_some();
+    others.retain(&audit_entry);
+    for (i, filename) in mli.mli_filenames.iter().enumerate() {
+        if !need_second_pass[i] {
+            continue;
+        }
+
+        mri_visit_file(filename, &mut |name, reader| {
+            let mut lines = LogLineReader::new(name, reader);
+            match mri_detect_format(&mut lines)? {
+                Some(MantaLogFormat::Muskie) if have_events => Ok(()),
+                Some(MantaLogFormat::Muskie) => {
+                    //
+                    // Invalid records were already reported when we read
+                    // this log the first time.
+                    //
+                    for record in MuskieLog::new(lines) {
+                        match record {
+                            Ok(MuskieLogRecord::Other(event)) => {
+                                others.add_muskie_event(event,
+                                    request_id.as_deref());
+                            },
+                            Ok(MuskieLogRecord::Audit(_)) => (),
+                            Err(e) => match e.kind() {
+                                MantaErrorKind::JsonSyntax(_) => (),
+                                _ => return Err(e)
+                            }
+                        }
+                    }
+                    Ok(())
+                },
+                None => Ok(()),
+                Some(f) => others.add_log(lines, f, Some(&audit_entry))
+            }
+        })?;
+    }
+
+    let haproxy_entry = mri_haproxy_match(&others.moe_haproxy,
+        &audit_entry.mai_req_method, &audit_entry.mai_req_url,
+        &audit_entry.wall_start());
+    if others.moe_haproxy_logs && haproxy_entry.is_none() {
+        diagnostics.warn("haproxy-no-match", None, String::from(
+            "haproxy logs were given, but none of their entries matched the \
+            request"));
+    }
+
+    let nshow = 10;
+    let ninvalid = others.moe_client_invalid.len();
+    for error in others.moe_client_invalid.iter().take(nshow) {
+        diagnostics.warn("client-entry-invalid", None, format!(
+            "skipped client log entry: {}", error));
+    }
+    if ninvalid > nshow {
+        diagnostics.warn("client-entry-invalid", None, format!(
+            "skipped {} more invalid client log entries", ninvalid - nshow));
+    }
+
+    let client_info = match (others.moe_client_logs, &request_id) {
+        (false, _) => None,
+        (true, Some(request_id)) => {
+            let info = mri_client_request(&others.moe_client, request_id)?;
+            if info.is_none() {
+                diagnostics.warn("client-no-match", None, format!(
+                    "client logs were given, but none of their entries \
+                    were for request \"{}\"", request_id));
+            }
+            info
+        },
+        (true, None) => {
+            diagnostics.warn("client-no-match", None, String::from(
+                "client logs were given, but they can't be matched because \
+                the Muskie log entry has no \"x-request-id\" header"));
+            None
+        }
+    };
+
+    let mako_matched = match audit_entry.mai_sharks_contacted {
+        None => Vec::new(),
+        Some(ref sharks) => {
+            let matched = mri_mako_match(sharks,
+                audit_entry.mai_objectid.as_ref(), request_id.as_deref(),
+                &audit_entry.mai_time, &others.moe_mako);
+            if others.moe_mako_logs {
+                mri_check_mako(sharks, &matched, &mut diagnostics);
+            }
+            matched
+        }
+    };
+
+    let clock_skew = mri_estimate_skew(&audit_entry, haproxy_entry.as_ref(),
+        client_info.as_ref(), &mako_matched, &mut diagnostics);
+    let corrections : &[MantaClockSkew] = if mli.mli_correct_skew {
+        &clock_skew
+    } else {
+        &[]
+    };
+
     let (overall_timeline, muskie_timeline, shark_timeline) =
-        mri_timelines(&audit_entry)?;
+        mri_timelines(&audit_entry, &others.moe_muskie, haproxy_entry.as_ref(),
+            client_info.as_ref(), corrections, &mut diagnostics)?;
+    let overall_timeline = overall_timeline.with_gaps(
+        chrono::Duration::milliseconds(MRI_GAP_THRESHOLD_MS), &mri_gap_label);
+    let latency = mri_latency_breakdown(&overall_timeline);
 
     Ok(MantaRequestInfo {
         mri_muskie: audit_entry,
+        mri_muskie_events: others.moe_muskie,
+        mri_client: client_info,
+        mri_haproxy: haproxy_entry,
+        mri_mako: mako_matched,
         mri_timeline_overall: overall_timeline,
         mri_timeline_muskie: muskie_timeline,
         mri_timeline_sharks: shark_timeline,
+        mri_latency: latency,
+        mri_skew_corrected: mli.mli_correct_skew,
+        mri_clock_skew: clock_skew,
+        mri_diagnostics: diagnostics,
     })
 }
 
-pub fn mri_dump(mri : &MantaRequestInfo)
-{
-    let muskie_info = &mri.mri_muskie;
-    let remote_ip = &muskie_info.mai_remote_address_logical;
-    let dns_name = muskie_info.mai_req_headers["host"].as_string(); // XXX
-    let min_duration_option = Some(chrono::Duration::milliseconds(1));
-
-    // TODO add: whether client requested keep-alive and whether it got it
-    println!("MANTA CLIENT:");
-    println!("  remote IP:      {}", remote_ip);
-    println!("  Manta DNS name: {}", dns_name);
-    println!("    (inferred from client \"Host\" header)");
-    println!("  agent: {}",
-        muskie_info.mai_req_headers["user-agent"].as_string());
-    println!("");
-
-    // TODO Any information about the load balancer
-    // TODO Any information about mako instances
-
-    println!("WEBAPI SERVER:  ZONE {} PID {}", muskie_info.mai_hostname,
-        muskie_info.mai_pid);
-    // TODO add warning for missing x-server-name or x-server-name not matching
-    println!("");
-
-    // TODO handle cases of missing headers
-    // TODO warn if server request id differs from client's?
-    println!("REQUEST DETAILS:");
-    println!("  request id:       {}",
-        muskie_info.mai_response_headers["x-request-id"].as_string());
-    println!("  method:           {}", muskie_info.mai_req_method);
-    println!("  operation:        {}", muskie_info.mai_operation);
-    println!("  billable op:      {}", muskie_info.mai_billable_operation);
-    println!("  url:              {}", muskie_info.mai_req_url);
-    println!("  caller account:   {} ({})", muskie_info.mai_req_caller_login,
-        muskie_info.mai_req_caller_uuid);
-    println!("  caller privilege: {}",
-        if muskie_info.mai_req_caller_operator { "OPERATOR" }
-        else { "unprivileged account" });
-    println!("  owner account:    {}", muskie_info.mai_req_owner_uuid);
-    println!("  route:            {}", muskie_info.mai_route);
-    println!("");
-
-    println!("RESPONSE DETAILS:");
-    println!("  status code:     {}", muskie_info.mai_response_status_code);
-    println!("  muskie latency:  {} ms (calculated from timers)",
-        mri.mri_timeline_muskie.total_elapsed().num_milliseconds());
-    println!("  x-response-time: {} ms (\"x-response-time\" header)",
-        muskie_info.mai_response_headers["x-response-time"].as_i64());
-    println!("    (This is the latency-to-first-byte reported by the \
-        server.)");
-    println!("");
-
-    // TODO should probably include "headobject"?
-    if muskie_info.mai_route == "putobject" ||
-        muskie_info.mai_route == "getstorage" ||
-        muskie_info.mai_route == "deletestorage" {
-        mri_dump_object_metadata(&muskie_info);
-    }
-
-    if muskie_info.mai_route == "putobject" ||
-        muskie_info.mai_route == "getstorage" {
-        mri_dump_shark_info(&muskie_info);
-    }
-
-    match &muskie_info.mai_error {
-        None => println!("ERROR INFORMATION: no error found in log entry"),
-        Some(ref error) => {
-            println!("ERROR INFORMATION:");
-            println!("  name:    {}", error.mle_error_name);
-            println!("  message: {}", error.mle_error_message);
-            // XXX add verbose option to print stack too
+///
+/// Accumulates the entries from client, load balancer, and storage node logs,
+/// plus the non-audit records from Muskie logs.  When an audit entry (or
+/// request id) is given, only entries that could describe that request are
+/// kept.
+///
+#[derive(Default)]
+struct MantaOtherEntries {
+    moe_muskie : Vec<MuskieLogEvent>,
+    moe_haproxy_logs : bool,
+    moe_mako_logs : bool,
+    moe_client_logs : bool,
+    /// client log records that couldn't be used (and were skipped)
+    moe_client_invalid : Vec<MantaError>,
+    moe_haproxy : Vec<HaproxyLogEntry>,
+    moe_client : Vec<ClientLogEntry>,
+    moe_mako : Vec<MakoLogEntry>,
+}
+
+impl MantaOtherEntries {
+    fn add_log<R : BufRead>(&mut self, lines : LogLineReader<R>,
+        format : MantaLogFormat, audit : Option<&MuskieAuditInfo>)
+        -> Result<(), MantaError>
+    {
+        match format {
+            MantaLogFormat::Muskie => (),
+            MantaLogFormat::Haproxy => {
+                self.moe_haproxy_logs = true;
+                for entry in mri_haproxy_entries(lines) {
+                    let entry = entry?;
+                    if audit.is_none_or(|a| mri_haproxy_wanted(a, &entry)) {
+                        self.moe_haproxy.push(entry);
+                    }
+                }
+            },
+            MantaLogFormat::Client => {
+                //
+                // Client logs are usually written by hand-run commands and
+                // may be interleaved with other output, so we skip records we
+                // can't use rather than giving up on the whole log.
+                //
+                self.moe_client_logs = true;
+                for entry in mri_client_entries(lines) {
+                    let entry = match entry {
+                        Ok(entry) => entry,
+                        Err(e) => match e.kind() {
+                            MantaErrorKind::Io(_) => return Err(e),
+                            _ => {
+                                self.moe_client_invalid.push(e);
+                                continue;
+                            }
+                        }
+                    };
+                    if audit.is_none_or(|a| mri_client_wanted(a, &entry)) {
+                        self.moe_client.push(entry);
+                    }
+                }
+            },
+            MantaLogFormat::Mako => {
+                self.moe_mako_logs = true;
+                for entry in mri_mako_entries(lines) {
+                    let entry = entry?;
+                    if audit.is_none_or(|a| mri_mako_wanted(a, &entry)) {
+                        self.moe_mako.push(entry);
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn add_muskie_event(&mut self, event : MuskieLogEvent,
+        request_id : Option<&str>)
+    {
+        if request_id.is_none_or(|id| event.mlv_req_id == id) {
+            self.moe_muskie.push(event);
         }
     }
-    println!("");
-
-    // TODO check transfer-encoding here and emit warning in weird case.  See
-    // RFC 2616 4.3, though -- some methods don't allow bodies.  See 4.4 for how
-    // to know the body length.
-    println!("DATA TRANSFER:");
-    println!("  request headers:           {} bytes",
-        muskie_info.mai_req_header_length);
-    println!("  request content length:    {}",
-        match muskie_info.mai_req_headers.get("content-length") {
-            // TODO handle cases of wrong header value type (e.g., string here)
-            Some(header_value) => format!("{} bytes", header_value.as_i64()),
-            None => String::from("unspecified\n    (presumably streamed using \
-                chunked transfer encoding)")
-        });
-    println!("  response headers:          {} bytes",
-        muskie_info.mai_response_header_length);
-    println!("  response content length:   {}",
-        match muskie_info.mai_response_headers.get("content-length") {
-            // TODO handle cases of wrong header value type (e.g., string here)
-            Some(header_value) => format!("{} bytes", header_value.as_i64()),
-            None => String::from("unspecified\n    (presumably streamed using \
-                chunked transfer encoding)")
-        });
-    println!("  object bytes transferred:  {}",
-        match muskie_info.mai_bytes_transferred {
-            None => String::from("unknown"),
-            Some(b) => format!("{}", b)
-        });
-    println!("");
-
-    println!("OVERALL TIMELINE: starts at {}\n",
-        mri.mri_timeline_overall.wall_start().format("%FT%T.%3fZ"));
-    mri_dump_timeline(&mri.mri_timeline_overall, true,
-        &mri.mri_timeline_overall.wall_start(), min_duration_option, 0);
-
-    if let Some(ref shark_timeline) = mri.mri_timeline_sharks {
-        println!("STORAGE NODE-RELATED EVENTS:\n");
-        mri_dump_timeline(shark_timeline, true,
-            &mri.mri_timeline_overall.wall_start(), None, 0);
-    }
-
-    println!("TIMELINE HEADERS:\n");
-    println!("   rSTART   relative time (in milliseconds) since the first \
-        event\n            in the whole timeline\n");
-    println!("   rCURR    relative time (in milliseconds) since the first \
-        event\n            in the current subtimeline\n");
-    println!("   ELAPSD   elapsed time (in milliseconds) for this event");
+
+    ///
+    /// Discards entries that cannot describe the request described by `audit`.
+    ///
+    fn retain(&mut self, audit : &MuskieAuditInfo)
+    {
+        let request_id = audit.request_id();
+        self.moe_muskie.retain(|e| Some(&e.mlv_req_id) == request_id.as_ref());
+        self.moe_haproxy.retain(|e| mri_haproxy_wanted(audit, e));
+        self.moe_client.retain(|e| mri_client_wanted(audit, e));
+        self.moe_mako.retain(|e| mri_mako_wanted(audit, e));
+    }
+}
+
+fn mri_haproxy_wanted(audit : &MuskieAuditInfo, entry : &HaproxyLogEntry)
+    -> bool
+{
+    let mut parts = entry.hle_request_line.split(' ');
+    return parts.next() == Some(audit.mai_req_method.as_str()) &&
+        parts.next() == Some(audit.mai_req_url.as_str());
 }
 
-fn mri_dump_timeline(timeline : &timeline::Timeline, dump_header : bool,
-    base : &chrono::DateTime<chrono::Utc>,
-    min_duration_option : Option<chrono::Duration>, depth : u8)
-    -> u16
+fn mri_client_wanted(audit : &MuskieAuditInfo, entry : &ClientLogEntry)
+    -> bool
 {
-    if dump_header {
-        println!("  {:13} {:>6} {:>6} {:>6} {}", "WALL TIME",
-            "rSTART", "rCURR", "ELAPSD", "EVENT");
-    }
-
-    let mut nskipped = 0;
-
-    for event in timeline.events() {
-        if let Some(min_duration) = min_duration_option {
-            //
-            // TODO we're using 0 as a special value for important events that
-            // have no elapsed time, but this should probably be an option
-            // instead.
-            //
-            if !event.duration().is_zero() && event.duration() < min_duration {
-                nskipped += 1;
+    return match (entry.request_id(), audit.request_id()) {
+        (Some(theirs), Some(ours)) => theirs == ours,
+        _ => false
+    };
+}
+
+fn mri_mako_wanted(audit : &MuskieAuditInfo, entry : &MakoLogEntry)
+    -> bool
+{
+    return mri_mako_related(entry, audit.mai_objectid.as_ref(),
+        audit.request_id().as_deref());
+}
+
+///
+/// Accumulates the audit entries selected by a filter as Muskie logs are read,
+/// so that we can select the one entry the user cares about.
+///
+/// Entries that match the request id and URL criteria must be valid audit
+/// entries.  When those criteria are not specified, we're likely looking at
+/// a whole log, and we skip entries that fail validation rather than letting
+/// one unusual request prevent us from finding the one the user cares about.
+/// Records that aren't valid JSON at all (e.g., a line that was cut short)
+/// can't match anything, so we always skip those and report them.
+///
+struct MuskieAuditSelection<'a> {
+    mas_filter : &'a MantaRequestFilter,
+    mas_candidates : Vec<MuskieAuditInfo>,
+    mas_nentries : usize,
+    mas_ninvalid : usize,
+    /// records that weren't valid JSON (and were skipped)
+    mas_bad_records : Vec<MantaError>,
+}
+
+impl<'a> MuskieAuditSelection<'a> {
+    fn new(filter : &'a MantaRequestFilter)
+        -> MuskieAuditSelection<'a>
+    {
+        return MuskieAuditSelection {
+            mas_filter : filter,
+            mas_candidates : Vec::new(),
+            mas_nentries : 0,
+            mas_ninvalid : 0,
+            mas_bad_records : Vec::new()
+        };
+    }
+
+    ///
+    /// Reads the audit entries from `log`, keeping those that match the filter.
+    /// Other records are passed to `events`.
+    ///
+    fn add_log<R : BufRead>(&mut self, log : MuskieLog<R>,
+        events : &mut dyn FnMut(MuskieLogEvent))
+        -> Result<(), MantaError>
+    {
+        let filter = self.mas_filter;
+        let strict = filter.mrf_request_id.is_some() ||
+            filter.mrf_url.is_some();
+        let mut log = log;
+
+        while let Some(record) = log.next() {
+            let entry = match record {
+                Ok(MuskieLogRecord::Audit(entry)) => entry,
+                Ok(MuskieLogRecord::Other(event)) => {
+                    events(event);
+                    continue;
+                },
+                Err(e) => match e.kind() {
+                    MantaErrorKind::JsonSyntax(_) => {
+                        self.mas_bad_records.push(e);
+                        continue;
+                    },
+                    _ => return Err(e)
+                }
+            };
+
+            self.mas_nentries += 1;
+            if !filter.matches_entry(&entry) {
                 continue;
             }
+
+            let audit = match mri_audit_entry(&entry) {
+                Ok(a) => a,
+                Err(e) if strict => return Err(e.in_input(log.name()).at_line(
+                    log.record_line())),
+                Err(_) => {
+                    self.mas_ninvalid += 1;
+                    continue;
+                }
+            };
+
+            if filter.matches_audit(&audit) {
+                self.mas_candidates.push(audit);
+            }
+        }
+
+        return Ok(());
+    }
+
+    ///
+    /// Returns the one entry selected by the filter.  It's an error if no
+    /// entries or more than one entry matched.
+    ///
+    fn finish(mut self, diagnostics : &mut MantaDiagnostics)
+        -> Result<MuskieAuditInfo, MantaError>
+    {
+        let nshow = 10;
+        let nbad = self.mas_bad_records.len();
+        for error in self.mas_bad_records.iter().take(nshow) {
+            diagnostics.warn("muskie-record-invalid", None, format!(
+                "skipped Muskie log record: {}", error));
+        }
+        if nbad > nshow {
+            diagnostics.warn("muskie-record-invalid", None, format!(
+                "skipped {} more invalid Muskie log records", nbad - nshow));
         }
 
-        // let wall_start = format!("{}", event.wall_start());
-        let wall_start = event.wall_start().format("%T.%3fZ");
-        print!("  {:13} {:6} {:6} ", wall_start,
-            (event.wall_start().signed_duration_since(*base)).num_milliseconds(),
-            event.relative_start().num_milliseconds());
-
-        let maybe_subtimeline = event.subtimeline();
-        if let Some(subtimeline) = maybe_subtimeline {
-            println!("{:>6} {} {{", "-", event.label());
-            nskipped += mri_dump_timeline(subtimeline, false,
-                base, min_duration_option, depth + 1);
-
-            let wall_end = event.wall_end().format("%T.%3fZ");
-            println!("  {:13} {:6} {:>6} {:6} {:width$}}} (subtimeline ended)",
-                wall_end, (event.wall_start().signed_duration_since(*base) +
-                event.duration()).num_milliseconds(), "-",
-                event.duration().num_milliseconds(), "",
-                width = (depth * 4) as usize);
-        } else {
-            println!("{:6} {:width$}{}", event.duration().num_milliseconds(),
-                "", event.label(), width = (depth * 4) as usize);
+        let ninvalid = self.mas_ninvalid;
+        let skipped = if ninvalid == 0 { String::new() } else {
+            format!(" ({} invalid audit entr{} skipped)", ninvalid,
+                if ninvalid == 1 { "y" } else { "ies" })
+        };
+
+        if self.mas_candidates.len() == 1 {
+            if ninvalid > 0 {
+                diagnostics.note("audit-entries-skipped", None, format!(
+                    "Muskie logs contained other audit entries that were not \
+                    valid{}", skipped));
+            }
+            return Ok(self.mas_candidates.remove(0));
+        }
+
+        if self.mas_candidates.is_empty() {
+            return Err(MantaError::new(MantaErrorKind::Selection(
+                if self.mas_nentries == 0 {
+                    String::from("no Muskie audit entries found")
+                } else {
+                    format!("none of {} Muskie audit entries matched the \
+                        filter{}", self.mas_nentries, skipped)
+                })));
+        }
+
+        let candidates = &self.mas_candidates;
+        let matching = if self.mas_filter.is_empty() { "" } else {
+            " matching the filter"
+        };
+        let mut message = format!("found {} Muskie audit entries{}{}; specify \
+            a request id, URL, or time to select one:", candidates.len(),
+            matching, skipped);
+        for audit in candidates.iter().take(nshow) {
+            message.push_str(&format!("\n    {} {} {} {}",
+                audit.mai_time.format("%FT%T.%3fZ"),
+                audit.mai_response_headers.get("x-request-id").map_or(
+                    String::from("(no request id)"), |v| v.to_string()),
+                audit.mai_req_method, audit.mai_req_url));
+        }
+        if candidates.len() > nshow {
+            message.push_str(&format!("\n    ... and {} more",
+                candidates.len() - nshow));
+        }
+
+        return Err(MantaError::new(MantaErrorKind::Selection(message)));
+    }
+}
+
+///
+/// Returns a human-readable summary of what format we detected each input
+/// log to be, one log per line.
+///
+fn mri_describe_inputs(detected : &[(String, Option<MantaLogFormat>)])
+    -> String
+{
+    detected.iter().map(|(filename, format)| format!("    {}: {}", filename,
+        match format {
+            Some(f) => f.to_string(),
+            None => String::from("unrecognized format")
+        })).collect::<Vec<String>>().join("\n")
+}
+
+///
+/// Checks the request and response headers for missing or malformed values
+/// and for inconsistencies.  None of these prevent us from reporting on the
+/// request, but the user should know about them.
+///
+fn mri_check_headers(muskie_info : &MuskieAuditInfo,
+    diagnostics : &mut MantaDiagnostics)
+{
+    //
+    // Muskie reports the zone that handled the request in "x-server-name".
+    // This should always match the zone that logged the entry.
+    //
+    let source = Some("res.headers.x-server-name");
+    match muskie_info.mai_response_headers.get("x-server-name") {
+        None => {
+            diagnostics.warn("server-name-missing", source, String::from(
+                "response has no \"x-server-name\" header"));
+        },
+        Some(name) if name.to_string() != muskie_info.mai_hostname => {
+            diagnostics.warn("server-name-mismatch", source, format!(
+                "response \"x-server-name\" header (\"{}\") does not match \
+                the zone that logged the request (\"{}\")", name,
+                muskie_info.mai_hostname));
+        },
+        Some(_) => ()
+    }
+
+    //
+    // Muskie uses the client's request id when one was provided, so the
+    // request and response headers should agree.
+    //
+    let req_id = muskie_info.mai_req_headers.get("x-request-id");
+    let res_id = muskie_info.mai_response_headers.get("x-request-id");
+    match (req_id, res_id) {
+        (_, None) => {
+            diagnostics.warn("request-id-missing",
+                Some("res.headers.x-request-id"), String::from(
+                "response has no \"x-request-id\" header"));
+        },
+        (Some(req), Some(res)) if req.to_string() != res.to_string() => {
+            diagnostics.warn("request-id-mismatch",
+                Some("res.headers.x-request-id"), format!(
+                "response request id (\"{}\") does not match the request id \
+                the client sent (\"{}\")", res, req));
+        },
+        _ => ()
+    }
+
+    //
+    // See RFC 7230 section 3.3.3 for how the length of a message body is
+    // determined.  A message with both "transfer-encoding" and
+    // "content-length" is suspicious, as is a transfer coding other than
+    // "chunked" (which must come last if present).  PUT and POST requests
+    // need one or the other, since they carry a body.
+    //
+    let messages = [
+        ("req", "request", &muskie_info.mai_req_headers),
+        ("res", "response", &muskie_info.mai_response_headers)
+    ];
+    for (prefix, what, headers) in messages.iter() {
+        let te = headers.get("transfer-encoding").map(|v| v.to_string());
+        let has_length = headers.contains("content-length");
+        if let Some(ref te) = te {
+            let source = format!("{}.headers.transfer-encoding", prefix);
+            let last = te.rsplit(',').next().unwrap_or("").trim().
+                to_lowercase();
+            if last != "chunked" {
+                diagnostics.warn("transfer-encoding-unexpected",
+                    Some(&source), format!("{} has unexpected \
+                    \"transfer-encoding\" (\"{}\")", what, te));
+            }
+            if has_length {
+                diagnostics.warn("transfer-encoding-with-length",
+                    Some(&source), format!("{} has both \
+                    \"transfer-encoding\" and \"content-length\" headers",
+                    what));
+            }
         }
     }
 
-    if depth == 0 {
-        if nskipped > 0 {
-            println!("\n  NOTE: {} timeline event{} with duration less than {} \
-                ms {} not shown above.", nskipped,
-                if nskipped == 1 { "" } else { "s" },
-                min_duration_option.expect(
-                    "must be min_duration_option if events were filtered").
-                    num_milliseconds(),
-                if nskipped == 1 { "was" } else { "were" });
+    let method = muskie_info.mai_req_method.as_str();
+    if (method == "PUT" || method == "POST") &&
+        !muskie_info.mai_req_headers.contains("transfer-encoding") &&
+        !muskie_info.mai_req_headers.contains("content-length") {
+        diagnostics.warn("request-length-unspecified",
+            Some("req.headers.content-length"), format!("{} request has \
+            neither \"content-length\" nor \"transfer-encoding\" header",
+            method));
+    }
+
+    //
+    // The report shows these headers.  When one is missing or malformed, the
+    // report says "unknown" instead, and we say why here.
+    //
+    let req = &muskie_info.mai_req_headers;
+    let res = &muskie_info.mai_response_headers;
+    let shown = [
+        (req, "host", true, false),
+        (req, "user-agent", true, false),
+        (req, "date", true, false),
+        (res, "date", true, false),
+        (req, "content-length", false, true),
+        (res, "x-response-time", true, true),
+        (res, "content-length", false, true),
+        (res, "durability-level", false, true),
+    ];
+    for (headers, name, required, numeric) in shown.iter() {
+        let source = headers.path(name);
+        match headers.get(name) {
+            None if *required => {
+                diagnostics.warn("header-missing", Some(&source), format!(
+                    "\"{}\" header is missing", name));
+            },
+            Some(value) if *numeric && value.as_i64().is_none() => {
+                diagnostics.warn("header-invalid", Some(&source), format!(
+                    "\"{}\" header is not an integer (\"{}\")", name,
+                    value));
+            },
+            _ => ()
         }
+    }
 
-        println!("");
+    if let Some(ref value) = muskie_info.mai_bytes_transferred {
+        if value.as_i64().is_none() {
+            diagnostics.warn("value-invalid", Some("bytesTransferred"),
+                format!("bytes transferred is not an integer (\"{}\")",
+                value));
+        }
     }
+}
 
-    return nskipped;
+///
+/// Checks the storage nodes' access log entries against what Muskie reported
+/// for each storage node it contacted.  `mako_entries` has the entry matching
+/// each of `sharks` (see `mri_mako_match()`).
+///
+fn mri_check_mako(sharks : &[MuskieAuditSharkContacted],
+    mako_entries : &[Option<MakoLogEntry>],
+    diagnostics : &mut MantaDiagnostics)
+{
+    //
+    // Muskie and Mako can disagree about whether a request succeeded (e.g.,
+    // when Muskie gives up on a shark that later finishes the request).
+    //
+    for (i, (shark, mako)) in sharks.iter().zip(mako_entries.iter()).
+        enumerate() {
+        let source = format!("sharksContacted[{}]", i);
+        match mako {
+            None => {
+                diagnostics.warn("mako-no-match", Some(&source), format!(
+                    "\"{}\": no matching Mako access log entry found",
+                    shark.mai_shark_storid));
+            },
+            Some(mako) if mako.success() != shark.mai_shark_success => {
+                diagnostics.warn("shark-result-mismatch", Some(&source),
+                    format!("\"{}\": muskie reported {}, but mako reported \
+                    status {}", shark.mai_shark_storid,
+                    if shark.mai_shark_success { "ok" } else { "fail" },
+                    mako.mke_status_code));
+            },
+            Some(_) => ()
+        }
+    }
 }
 
-fn mri_timelines(muskie_info : &MuskieAuditInfo)
+fn mri_timelines(muskie_info : &MuskieAuditInfo, events : &[MuskieLogEvent],
+    haproxy : Option<&HaproxyLogEntry>, client : Option<&ClientRequestInfo>,
+    corrections : &[MantaClockSkew], diagnostics : &mut MantaDiagnostics)
     -> Result<(timeline::Timeline, timeline::Timeline,
-    Option<timeline::Timeline>), String>
+    Option<timeline::Timeline>), MantaError>
 {
     /*
      * The Muskie audit log entry is the only anchor point we have for this
@@ -255,6 +888,7 @@ fn mri_timelines(muskie_info : &MuskieAuditInfo)
     let walltime_end = muskie_info.mai_time;
     let mut muskie_timeline = timeline::TimelineBuilder::new_ending(
         walltime_end);
+    muskie_timeline.set_source("muskie");
     muskie_timeline.prepend("muskie created audit log entry",
         &chrono::Duration::microseconds(0));
 
@@ -262,143 +896,417 @@ fn mri_timelines(muskie_info : &MuskieAuditInfo)
     let mut handler_names : Vec<&String> = handler_durations.keys().collect();
     handler_names.reverse();
     for handler_name in handler_names {
-        let duration_us = handler_durations[handler_name].as_i64().expect(
-            "timer was not a 64-bit integer");
-        muskie_timeline.prepend(&format!("{}", handler_name),
-            &chrono::Duration::microseconds(duration_us));
+        if handlers::mri_handler_phase(handler_name).is_none() &&
+            !handlers::mri_handler_anonymous(handler_name) {
+            diagnostics.note("handler-unknown", Some(&format!(
+                "req.timers.{}", handler_name)), format!(
+                "handler \"{}\" is not in mreq's catalog of Muskie handlers, \
+                so its phase is unknown",
+                handler_name));
+        }
+
+        //
+        // We can't place a handler whose timer isn't an integer, or is
+        // negative or implausibly long, so we leave it out (which shifts
+        // earlier handlers later by its duration).
+        //
+        let value = &handler_durations[handler_name];
+        match (mri_muskie_timer(value), value.as_i64()) {
+            (Some(duration), _) => {
+                muskie_timeline.prepend(handler_name, &duration);
+            },
+            (None, Some(_)) => {
+                diagnostics.warn("timer-implausible", Some(&format!(
+                    "req.timers.{}", handler_name)), format!(
+                    "handler \"{}\": timer ({} us) is negative or longer \
+                    than a day; handler not shown on the timeline",
+                    handler_name, value));
+            },
+            (None, None) => {
+                diagnostics.warn("timer-invalid", Some(&format!(
+                    "req.timers.{}", handler_name)), format!(
+                    "handler \"{}\": timer is not an integer number of \
+                    microseconds ({}); handler not shown on the timeline",
+                    handler_name, value));
+            }
+        }
     }
 
     muskie_timeline.prepend("muskie began processing request",
         &chrono::Duration::microseconds(0));
-    let muskie_timeline = Box::new(muskie_timeline.finish());
 
     //
-    // TODO This could be more flexible.
-    // We commonly see HTTP "Date" header values that look like this:
+    // Other records that Muskie logged for this request have their own
+    // timestamps, so these are added as point events where they happened,
+    // within whichever handler was running at the time.
     //
-    //   Fri, 26 Apr 2019 21:18:02 GMT
+    for event in events {
+        let message : Vec<&str> = event.mlv_message.lines().collect();
+        muskie_timeline.add(&format!("muskie logged {}: {}",
+            event.level_name(), message.join(" ")), &event.mlv_time,
+            &chrono::Duration::microseconds(0));
+    }
+    let muskie_timeline = muskie_timeline.finish();
+
+    //
+    // On the overall timeline, consecutive handlers in the same phase (see
+    // the handlers module) are grouped together.  Anonymous handlers and
+    // those not in the catalog are left as they are, unless they're
+    // surrounded by handlers in the same phase.  So are runs of handlers that
+    // took less than a millisecond altogether, since a phase that took no time
+    // tells the reader nothing.
+    //
+    let muskie_phases = Box::new(muskie_timeline.grouped(&|event| {
+        handlers::mri_handler_phase(&event.label()).map(
+            |phase| format!("{} phase", phase))
+    }, chrono::Duration::milliseconds(1)));
+
     //
-    // Based on RFC 2616 (section 3.3.1, "Full Date"), this appears to be "RFC
-    // 822, updated by RFC 1123".  I have not yet found a way to parse this
-    // directly in Rust.  Additionally, note that the time zone is given by name
-    // ("GMT") rather than offset (e.g., "+00:00").  The chrono library we use
-    // for date and time manipulation does not seem to have any way to identify
-    // the time zone (e.g., offset) from the name.  As a result, short of
-    // implementing this ourselves with our own time zone database, we assume
-    // the common case of GMT and handle that directly.
+    // The client's "Date" header tells us (to the second) when the client
+    // generated the request, according to the client's clock.  The "Date"
+    // response header tells us when Muskie generated the response headers,
+    // according to Muskie's clock.  Showing both makes it easier to see when
+    // the two clocks disagree.  If either header is missing,
+    // mri_check_headers() has already reported that, and there's just no
+    // corresponding event.  A date that's nowhere near when Muskie processed
+    // the request is reported instead of shown, since it would stretch the
+    // timeline out to cover years.
     //
-    let client_time = muskie_info.mai_req_headers["date"].as_string();
-    let client_timestamp = {
-        let timestamp_parsed : Result<chrono::DateTime<chrono::Utc>, _>;
-
-        if client_time.ends_with(" GMT") {
-            let prefixlen = client_time.len() - " GMT".len();
-            let timestamp_prefix = &client_time[0..prefixlen];
-            let timestamp_formatted = format!("{} +00:00", timestamp_prefix);
-            timestamp_parsed = match chrono::DateTime::parse_from_str(
-                &timestamp_formatted, "%a, %d %h %Y %T %:z") {
-                Ok(ts) => Ok(ts.with_timezone(&chrono::Utc)),
-                Err(e) => Err(e)
+    // Events from other sources are shifted by the estimated skew of their
+    // clocks, if we've been asked to correct for that (in which case
+    // `corrections` is non-empty).
+    //
+    let haproxy_skew = mri_skew_for(corrections, MRI_SKEW_LOAD_BALANCER);
+    let client_skew = mri_skew_for(corrections, MRI_SKEW_CLIENT);
+    let dates = [
+        (&muskie_info.mai_req_headers, "client", "client", client_skew),
+        (&muskie_info.mai_response_headers, "muskie", "server",
+            chrono::Duration::zero())
+    ];
+    let mut date_events = Vec::new();
+    for (headers, who, code, skew) in dates.iter() {
+        let value = match headers.get("date") {
+            Some(value) => value.as_str(),
+            None => continue
+        };
+        match mri_parse_http_date(value) {
+            Ok(when) if mri_date_plausible(&when, &muskie_info.wall_start(),
+                &walltime_end) => date_events.push((*who, when - *skew)),
+            Ok(when) => {
+                diagnostics.warn(&format!("{}-date-implausible", code),
+                    Some(&headers.path("date")), format!("{} \"Date\" \
+                    header (\"{}\", or {}) is more than {} minutes from when \
+                    Muskie processed the request, so it's not shown on the \
+                    timeline", who, value, when.format("%FT%TZ"),
+                    MRI_DATE_MAX_DISTANCE_MINUTES));
+            },
+            Err(e) => {
+                diagnostics.warn(&format!("{}-date-invalid", code),
+                    Some(&headers.path("date")), format!("{} \"Date\" \
+                    header (\"{}\") could not be parsed: {}", who, value,
+                    e));
             }
-        } else {
-            timestamp_parsed = client_time.parse();
         }
+    }
 
-        timestamp_parsed
-    };
+    //
+    // If we have the load balancer's or client's log entries, they generally
+    // finish with the request after Muskie does, since the last of the
+    // response still has to make its way to the client.
+    //
+    let mut overall_end = walltime_end;
+    if let Some(h) = haproxy {
+        overall_end = std::cmp::max(overall_end, h.wall_end() - haproxy_skew);
+    }
+    if let Some(c) = client {
+        overall_end = std::cmp::max(overall_end,
+            c.cri_time_finished - client_skew);
+    }
+    let mut timeline = timeline::TimelineBuilder::new_ending(overall_end);
+
+    for (who, when) in date_events {
+        timeline.set_source(who);
+        timeline.add_bounded(&format!("{} generated Date header", who), &when,
+            &when, &(when + chrono::Duration::milliseconds(999)),
+            &chrono::Duration::microseconds(0),
+            timeline::TimelineProvenance::HeaderDerived);
+    }
 
-    let mut timeline = timeline::TimelineBuilder::new_ending(walltime_end);
+    timeline.set_source("muskie");
+    timeline.add_timeline("muskie handlers", muskie_phases.clone());
 
-    match client_timestamp {
-        Ok(when) => {
-            timeline.add("client generated Date header", &when,
-                &chrono::Duration::microseconds(0), None);
-        },
-        Err(e) => {
-            // XXX want some other way to track warnings
-            eprintln!("client timestamp (\"{}\"): error: {}", client_time, e);
-        }
+    if let Some(h) = haproxy {
+        mri_timeline_haproxy(&mut timeline, h, haproxy_skew);
     }
 
-    timeline.add_timeline("muskie handlers", muskie_timeline.clone());
+    if let Some(c) = client {
+        mri_timeline_client(&mut timeline, c, client_skew);
+    }
 
+    //
+    // Muskie may talk to several storage nodes at once (e.g., when streaming
+    // an upload to each copy), so these go on a separate timeline with a lane
+    // for each storage node.  For context, we include lanes for whatever
+    // Muskie and the load balancer were doing while the storage nodes were
+    // busy.
+    //
     let mut shark_timeline = None;
-    if let Some(ref sharks) = muskie_info.mai_sharks_contacted {
-        // XXX This is a bogus timestamp.  We should change the interface to
-        // not require it if you're not going to use prepend().
-        let last_time = muskie_info.mai_time;
-        let mut stbuilder = timeline::TimelineBuilder::new_ending(last_time);
+    let sharks = muskie_info.mai_sharks_contacted.as_ref().filter(
+        |sharks| !sharks.is_empty());
+    if let Some(sharks) = sharks {
+        let mut stbuilder = timeline::TimelineBuilder::new_unanchored();
 
-        for shark in sharks {
-            stbuilder.add(&format!("\"{}\": begin", shark.mai_shark_storid),
-                &shark.mai_shark_time_start,
-                &chrono::Duration::milliseconds(0), None);
-            if let Some(t) = shark.mai_shark_latency_ttfb {
-                stbuilder.add(&format!("\"{}\": ready", shark.mai_shark_storid),
-                    &(shark.mai_shark_time_start + t),
-                    &chrono::Duration::milliseconds(0), None);
+        let shark_start = sharks.iter().map(|s| s.mai_shark_time_start).min();
+        let shark_end = sharks.iter().map(|s| s.mai_shark_time_start +
+            s.mai_shark_latency_total.unwrap_or_else(chrono::Duration::zero)).
+            max();
+        if let (Some(window_start), Some(window_end)) = (shark_start, shark_end)
+        {
+            let overlaps = |start : &chrono::DateTime<chrono::Utc>,
+                duration : &chrono::Duration| {
+                *start <= window_end && *start + *duration >= window_start
+            };
+
+            if let Some(h) = haproxy {
+                stbuilder.set_source("load balancer");
+                for (label, start, duration) in
+                    mri_haproxy_phases(h, haproxy_skew) {
+                    if overlaps(&start, &duration) {
+                        stbuilder.add_to_lane("load balancer", label, &start,
+                            &duration);
+                    }
+                }
             }
-            if let Some(t) = shark.mai_shark_latency_total {
-                stbuilder.add(&format!("\"{}\": {}", shark.mai_shark_storid,
-                    if shark.mai_shark_success { "success" } else { "fail" }),
-                    &(shark.mai_shark_time_start + t),
-                    &chrono::Duration::milliseconds(0), None);
+
+            let min_duration = chrono::Duration::milliseconds(1);
+            stbuilder.set_source("muskie");
+            for event in muskie_timeline.events() {
+                if event.duration() >= min_duration &&
+                    overlaps(&event.wall_start(), &event.duration()) {
+                    stbuilder.add_to_lane("muskie", &event.label(),
+                        &event.wall_start(), &event.duration());
+                }
             }
         }
 
-        shark_timeline = Some(stbuilder.finish());
+        stbuilder.set_source("storage node");
+        for shark in sharks {
+            let lane = format!("\"{}\"", shark.mai_shark_storid);
+            let start = shark.mai_shark_time_start;
+            let result = if shark.mai_shark_success { "ok" } else { "fail" };
+            match (shark.mai_shark_latency_ttfb,
+                shark.mai_shark_latency_total) {
+                (Some(ttfb), Some(total)) => {
+                    stbuilder.add_to_lane(&lane, "awaiting first byte", &start,
+                        &ttfb);
+                    stbuilder.add_to_lane(&lane, &format!("streaming ({})",
+                        result), &(start + ttfb), &(total - ttfb));
+                },
+                (None, Some(total)) => {
+                    stbuilder.add_to_lane(&lane, &format!("request ({})",
+                        result), &start, &total);
+                },
+                (_, None) => {
+                    stbuilder.add_to_lane(&lane, &format!("started ({})",
+                        result), &start, &chrono::Duration::zero());
+                }
+            }
+        }
+
+        shark_timeline = stbuilder.try_finish();
     }
 
-    return Ok((timeline.finish(), *muskie_timeline, shark_timeline));
+    return Ok((timeline.finish(), *muskie_phases, shark_timeline));
 }
 
-fn mri_dump_object_metadata(mip : &MuskieAuditInfo)
+///
+/// Returns a label for a pseudo-event representing unaccounted time between
+/// events `before` and `after` on a timeline, suggesting what might explain
+/// it based on which components reported the surrounding events.
+///
+fn mri_gap_label(before : Option<&timeline::TimelineEvent>,
+    after : Option<&timeline::TimelineEvent>)
+    -> String
 {
-    println!("MANTA OBJECT METADATA:");
-    println!("  path:                     {}", mip.mai_req_url);
-    println!("  objectid:                 {}",
-        mip.mai_objectid.as_ref().unwrap_or(&String::from("unknown")));
-    println!("  metadata on shard:        {}",
-        mip.mai_shard_entry.as_ref().unwrap_or(&String::from("unknown")));
-    // TODO explicitly note case of parent metadata being a synthetic directory
-    // like "/account/stor"?
-    println!("  parent metadata on shard: {}",
-        mip.mai_shard_parent.as_ref().unwrap_or(&String::from("unknown")));
-
-    println!("  durability level:         {}",
-        mip.mai_response_headers.get("durability-level").map_or(
-            String::from("unknown"), |x| format!("{}", x.as_i64())));
-    println!("  md5sum (HTTP):            {}",
-        mip.mai_response_headers.get("content-md5").map_or(
-            &String::from("unknown"), |x| x.as_string()));
-
-    println!("");
+    let source_before = before.and_then(|e| e.source()).unwrap_or("unknown");
+    let source_after = after.and_then(|e| e.source()).unwrap_or("unknown");
+    let mut causes = Vec::new();
+
+    let from_header = |e : Option<&timeline::TimelineEvent>| e.is_some_and(
+        |e| e.provenance() == timeline::TimelineProvenance::HeaderDerived);
+    if from_header(before) || from_header(after) {
+        causes.push("Date header resolution");
+    }
+
+    if source_before != source_after {
+        causes.push("clock skew");
+    }
+
+    causes.push(match (source_before, source_after) {
+        ("client", "load balancer") | ("load balancer", "client") =>
+            "network (client and load balancer)",
+        ("client", "muskie") => "network or load balancer queue",
+        ("load balancer", "muskie") => "load balancer queue or network",
+        ("muskie", "load balancer") | ("muskie", "client") => "network",
+        ("muskie", "muskie") => "untimed muskie processing",
+        ("client", "client") => "client processing",
+        ("load balancer", "load balancer") => "load balancer processing",
+        _ => "unknown"
+    });
+
+    return format!("unaccounted ({})", causes.join(", "));
 }
 
-fn mri_dump_shark_info(mip : &MuskieAuditInfo)
+///
+/// Adds events from the load balancer's log entry to `timeline`.  haproxy
+/// reports its timers ("Tq/Tw/Tc/Tr/Tt") relative to when it accepted the
+/// connection, with each of the first four timers measuring one phase of the
+/// request.  If a phase never happened, its timer is "-1", and we cannot place
+/// any of the later events either (except for completion, which is relative to
+/// the accept time).
+///
+fn mri_timeline_haproxy(timeline : &mut timeline::TimelineBuilder,
+    haproxy : &HaproxyLogEntry, skew : chrono::Duration)
 {
-    if let None = mip.mai_sharks_contacted {
-        println!("STORAGE NODES CONTACTED: not found in log entry");
-        println!("");
-    }
-
-    let sharks = mip.mai_sharks_contacted.as_ref().unwrap();
-    println!("STORAGE NODES CONTACTED:");
-    println!("  {:13} {:>6} {:>6} {:>4} {}", "START", "TTFB", "TOTAL", "OK?",
-        "STOR_ID");
-    for shark in sharks {
-        println!("  {:13} {:>6} {:>6} {:>4} {}",
-            shark.mai_shark_time_start.format("%T.%3fZ"),
-            match shark.mai_shark_latency_ttfb {
-                Some(duration) => duration.num_milliseconds().to_string(),
-                None => String::from("-")
-            },
-            match shark.mai_shark_latency_total {
-                Some(duration) => duration.num_milliseconds().to_string(),
-                None => String::from("-")
-            },
-            if shark.mai_shark_success { "OK" } else { "FAIL" },
-            shark.mai_shark_storid);
+    let zero = chrono::Duration::milliseconds(0);
+    timeline.set_source("load balancer");
+    let accepted = haproxy.hle_accept_time - skew;
+    timeline.add("haproxy accepted connection", &accepted, &zero);
+
+    if let (Some(tq), Some(tw)) =
+        (haproxy.hle_time_request, haproxy.hle_time_queued) {
+        timeline.add("haproxy queued request", &(accepted + tq), &tw);
+
+        if let Some(tc) = haproxy.hle_time_connect {
+            let connected = accepted + tq + tw + tc;
+            timeline.add("haproxy connected to muskie", &connected, &zero);
+
+            if let Some(tr) = haproxy.hle_time_response {
+                timeline.add("haproxy received response headers",
+                    &(connected + tr), &zero);
+            }
+        }
+    }
+
+    timeline.add("haproxy response complete", &(haproxy.wall_end() - skew),
+        &zero);
+}
+
+///
+/// Returns the phases of the load balancer's handling of the request as (label,
+/// start, duration) tuples, skipping phases that never happened.  These are
+/// the intervals between the events that mri_timeline_haproxy() puts on the
+/// overall timeline.
+///
+fn mri_haproxy_phases(haproxy : &HaproxyLogEntry, skew : chrono::Duration)
+    -> Vec<(&'static str, chrono::DateTime<chrono::Utc>, chrono::Duration)>
+{
+    let mut rv = Vec::new();
+    let accepted = haproxy.hle_accept_time - skew;
+    let end = haproxy.wall_end() - skew;
+    let mut last = accepted;
+
+    if let Some(tq) = haproxy.hle_time_request {
+        rv.push(("receiving request", last, tq));
+        last = last + tq;
+
+        if let Some(tw) = haproxy.hle_time_queued {
+            rv.push(("queued", last, tw));
+            last = last + tw;
+
+            if let Some(tc) = haproxy.hle_time_connect {
+                rv.push(("connecting to muskie", last, tc));
+                last = last + tc;
+
+                if let Some(tr) = haproxy.hle_time_response {
+                    rv.push(("awaiting response", last, tr));
+                    last = last + tr;
+                }
+            }
+        }
+    }
+
+    rv.push(("sending response", last, end - last));
+    return rv;
+}
+
+///
+/// Adds events from the client's log entries to `timeline`.  These bracket
+/// everything else we know about the request, so comparing them with the
+/// load balancer's and Muskie's events shows how much time was spent on the
+/// network between the client and Manta.
+///
+fn mri_timeline_client(timeline : &mut timeline::TimelineBuilder,
+    client : &ClientRequestInfo, skew : chrono::Duration)
+{
+    let zero = chrono::Duration::milliseconds(0);
+    timeline.set_source("client");
+    timeline.add("client started request", &(client.cri_time_started - skew),
+        &zero);
+
+    for retry in &client.cri_time_retries {
+        timeline.add("client retried request", &(*retry - skew), &zero);
+    }
+
+    if let Some(ref headers) = client.cri_time_headers {
+        timeline.add("client received headers", &(*headers - skew), &zero);
+    }
+
+    timeline.add("client finished", &(client.cri_time_finished - skew), &zero);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn shark(storid : &str, success : bool)
+        -> MuskieAuditSharkContacted
+    {
+        return MuskieAuditSharkContacted {
+            mai_shark_storid : String::from(storid),
+            mai_shark_success : success,
+            mai_shark_time_start : chrono::Utc.ymd(2019, 5, 9).and_hms(
+                21, 34, 23),
+            mai_shark_latency_ttfb : None,
+            mai_shark_latency_total : None
+        };
+    }
+
+    fn mako(status : u16)
+        -> MakoLogEntry
+    {
+        return mri_mako_entry(&format!("127.0.0.1 - - \
+            [09/May/2019:21:34:24 +0000] \"PUT /owner/objectid HTTP/1.1\" {} \
+            0 0.500", status)).unwrap();
+    }
+
+    #[test]
+    fn check_mako()
+    {
+        let sharks = [
+            shark("1.stor", true), shark("2.stor", true),
+            shark("3.stor", false), shark("4.stor", false),
+            shark("5.stor", true)
+        ];
+        let entries = [
+            Some(mako(204)), Some(mako(500)), Some(mako(500)),
+            Some(mako(201)), None
+        ];
+        let mut diagnostics = MantaDiagnostics::new();
+        mri_check_mako(&sharks, &entries, &mut diagnostics);
+
+        let found : Vec<(&str, Option<&str>, &str)> = diagnostics.items().
+            iter().map(|d| (d.mdi_code.as_str(), d.mdi_source.as_deref(),
+            d.mdi_message.as_str())).collect();
+        assert_eq!(found, vec![
+            ("shark-result-mismatch", Some("sharksContacted[1]"),
+                "\"2.stor\": muskie reported ok, but mako reported status 500"),
+            ("shark-result-mismatch", Some("sharksContacted[3]"),
+                "\"4.stor\": muskie reported fail, but mako reported status \
+                201"),
+            ("mako-no-match", Some("sharksContacted[4]"),
+                "\"5.stor\": no matching Mako access log entry found"),
+        ]);
     }
-    println!("");
 }
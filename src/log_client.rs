@@ -0,0 +1,354 @@
+/*
+ * src/log_client.rs: node-manta client log format parser
+ *
+ * node-manta (and the restify clients underneath it) emit bunyan records while
+ * making a request.  Most of these are only logged at debug or trace level, so
+ * users have to run the client with something like "-v" or LOG_LEVEL=trace to
+ * get them.  Records for a particular request carry the request id either as
+ * "req_id" (on records logged by node-manta itself) or as the "x-request-id"
+ * header on the serialized "req" or "res" objects (on records logged by the
+ * restify client).
+ */
+
+use std::io::BufRead;
+
+use super::error::MantaError;
+use super::error::MantaErrorKind;
+use super::log_common::mri_open_file;
+use super::log_common::mri_parse_json;
+use super::log_common::LogLineReader;
+
+/*
+ * Given a file containing node-manta bunyan log records (one per line), return
+ * an iterator over the records in the file.  The file is read as the iterator
+ * is consumed.
+ */
+pub fn mri_parse_client_file(filename : &String)
+    -> Result<impl Iterator<Item = Result<ClientLogEntry, MantaError>>,
+    MantaError>
+{
+    Ok(mri_client_entries(mri_open_file(filename)?))
+}
+
+/*
+ * Given a reader over lines of a node-manta log, return an iterator over the
+ * records in the log.  Blank lines are skipped.  Records that can't be used
+ * (see `mri_client_entry()`) produce errors identifying the line, after which
+ * the caller may keep reading.
+ */
+pub fn mri_client_entries<R : BufRead>(lines : LogLineReader<R>)
+    -> impl Iterator<Item = Result<ClientLogEntry, MantaError>>
+{
+    let name = String::from(lines.name());
+    lines.filter_map(move |line| match line {
+        Err(e) => Some(Err(e)),
+        Ok(ref l) if l.ll_text.trim().is_empty() => None,
+        Ok(l) => Some(mri_client_entry(&l.ll_text).map_err(
+            |e| e.in_input(&name).at_line(l.ll_lineno)))
+    })
+}
+
+///
+/// Given one line from a node-manta log, parse it into a ClientLogEntry.  The
+/// record must be a bunyan version 0 record with a valid time.
+///
+pub fn mri_client_entry(line : &str)
+    -> Result<ClientLogEntry, MantaError>
+{
+    let entry : ClientLogEntry = mri_parse_json(line)?;
+    mri_client_time(&entry)?;
+    Ok(entry)
+}
+
+///
+/// Returns the time at which `entry` was logged, after checking that it's a
+/// record we understand.
+///
+fn mri_client_time(entry : &ClientLogEntry)
+    -> Result<chrono::DateTime<chrono::Utc>, MantaError>
+{
+    if entry.cle_bunyan_version != 0 {
+        return Err(MantaError::new(MantaErrorKind::UnsupportedBunyanVersion(
+            u64::from(entry.cle_bunyan_version))));
+    }
+
+    return entry.cle_time.parse().map_err(|e| MantaError::new(
+        MantaErrorKind::InvalidValue(String::from("time"), format!(
+        "\"{}\": {}", entry.cle_time, e))));
+}
+
+/*
+ * ClientLogEntry represents a bunyan record logged by node-manta.  We only
+ * model the fields we use.  "req" and "res" are left as generic JSON values
+ * because their shape depends on which serializer logged them.
+ */
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ClientLogEntry {
+    // Bunyan fields
+    #[serde(rename = "name")]       pub cle_name : String,
+    #[serde(rename = "hostname")]   pub cle_hostname : String,
+    #[serde(rename = "pid")]        pub cle_pid : u64,
+    #[serde(rename = "level")]      pub cle_level : i16,
+    #[serde(rename = "time")]       pub cle_time : String,
+    #[serde(rename = "v")]          pub cle_bunyan_version : u16,
+    #[serde(rename = "msg")]        pub cle_message : String,
+
+    // node-manta and restify-clients fields
+    #[serde(rename = "req_id")]     pub cle_req_id : Option<String>,
+    #[serde(rename = "req")]        pub cle_req : Option<serde_json::Value>,
+    #[serde(rename = "res")]        pub cle_res : Option<serde_json::Value>,
+    #[serde(rename = "attempt")]    pub cle_attempt : Option<u32>,
+}
+
+impl ClientLogEntry {
+    ///
+    /// Returns the request id associated with this record, if any.
+    ///
+    pub fn request_id(&self)
+        -> Option<&str>
+    {
+        if let Some(ref req_id) = self.cle_req_id {
+            return Some(req_id);
+        }
+
+        for obj in [&self.cle_req, &self.cle_res].iter() {
+            if let Some(id) = obj.as_ref().and_then(|o| o.pointer(
+                "/headers/x-request-id")).and_then(|v| v.as_str()) {
+                return Some(id);
+            }
+        }
+
+        return None;
+    }
+
+    ///
+    /// Returns the HTTP status code from the serialized response, if any.
+    ///
+    pub fn status_code(&self)
+        -> Option<u16>
+    {
+        let res = self.cle_res.as_ref()?;
+        let code = res.get("statusCode")?.as_u64()?;
+        if code > u64::from(u16::MAX) {
+            return None;
+        }
+        return Some(code as u16);
+    }
+}
+
+///
+/// A ClientRequestInfo summarizes the client's view of one request, as
+/// reconstructed from the client log records carrying its request id.
+///
+#[derive(Serialize)]
+pub struct ClientRequestInfo {
+    #[serde(rename = "hostname")]   pub cri_hostname : String,
+    #[serde(rename = "pid")]        pub cri_pid : String,
+    #[serde(rename = "nentries")]   pub cri_nentries : usize,
+    #[serde(rename = "time_started",
+        serialize_with = "super::json::mri_json_time")]
+    pub cri_time_started : chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "time_headers",
+        serialize_with = "super::json::mri_json_time_opt")]
+    pub cri_time_headers : Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(rename = "time_retries",
+        serialize_with = "super::json::mri_json_times")]
+    pub cri_time_retries : Vec<chrono::DateTime<chrono::Utc>>,
+    #[serde(rename = "time_finished",
+        serialize_with = "super::json::mri_json_time")]
+    pub cri_time_finished : chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "status_code")]
+    pub cri_status_code : Option<u16>,
+}
+
+///
+/// Given client log records and the request id of interest, returns a
+/// ClientRequestInfo describing the client's view of that request.  Returns
+/// `Ok(None)` if none of the records refer to the request.
+///
+/// The first record for the request marks when the client started it and the
+/// last marks when the client finished with it.  The first record carrying a
+/// serialized response marks when the client received the response headers.
+/// node-manta retries some failures using a new connection but the same
+/// request id, numbering each try in the "attempt" field.  The first record
+/// for each attempt after the first marks when that retry started.  (Messages
+/// aren't a reliable signal: "not retrying" mentions retries, too.)
+///
+pub fn mri_client_request(entries : &[ClientLogEntry], request_id : &str)
+    -> Result<Option<ClientRequestInfo>, MantaError>
+{
+    let mut matched = Vec::new();
+
+    for entry in entries {
+        if entry.request_id() != Some(request_id) {
+            continue;
+        }
+
+        matched.push((mri_client_time(entry)?, entry));
+    }
+
+    if matched.is_empty() {
+        return Ok(None);
+    }
+
+    matched.sort_by_key(|&(time, _)| time);
+
+    let (first_time, first) = matched[0];
+    let (last_time, _) = matched[matched.len() - 1];
+    let mut headers = None;
+    let mut status_code = None;
+    let mut retries = Vec::new();
+    let mut attempts_seen = Vec::new();
+
+    for &(time, entry) in &matched {
+        if headers.is_none() && entry.cle_res.is_some() {
+            headers = Some(time);
+            status_code = entry.status_code();
+        }
+
+        if let Some(attempt) = entry.cle_attempt {
+            if attempt > 1 && !attempts_seen.contains(&attempt) {
+                attempts_seen.push(attempt);
+                retries.push(time);
+            }
+        }
+    }
+
+    Ok(Some(ClientRequestInfo {
+        cri_hostname : first.cle_hostname.clone(),
+        cri_pid : first.cle_pid.to_string(),
+        cri_nentries : matched.len(),
+        cri_time_started : first_time,
+        cri_time_headers : headers,
+        cri_time_retries : retries,
+        cri_time_finished : last_time,
+        cri_status_code : status_code
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    const REQUEST_ID : &str = "ec5d32fe-5ff8-43ae-a152-45fd1005afff";
+
+    ///
+    /// Returns a record logged at 21:34:`seconds` with additional fields
+    /// `fields` (a fragment of a JSON object, with a trailing comma).
+    ///
+    fn entry(seconds : &str, fields : &str, msg : &str)
+        -> ClientLogEntry
+    {
+        return mri_client_entry(&format!("{{\"name\":\"mget\",\
+            \"hostname\":\"dap-laptop\",\"pid\":40212,\"level\":20,{}\
+            \"msg\":\"{}\",\"time\":\"2019-05-09T21:34:{}Z\",\"v\":0}}",
+            fields, msg, seconds)).unwrap();
+    }
+
+    fn time(seconds : u32, millis : u32)
+        -> chrono::DateTime<chrono::Utc>
+    {
+        return chrono::Utc.ymd(2019, 5, 9).and_hms_milli(21, 34, seconds,
+            millis);
+    }
+
+    fn req_id(id : &str)
+        -> String
+    {
+        return format!("\"req_id\":\"{}\",", id);
+    }
+
+    fn res(status : u16, id : &str)
+        -> String
+    {
+        return format!("\"res\":{{\"statusCode\":{},\"headers\":\
+            {{\"x-request-id\":\"{}\"}}}},", status, id);
+    }
+
+    #[test]
+    fn request_id()
+    {
+        assert_eq!(entry("23.160", &req_id(REQUEST_ID), "get").request_id(),
+            Some(REQUEST_ID));
+        assert_eq!(entry("23.340", &res(200, REQUEST_ID), "response").
+            request_id(), Some(REQUEST_ID));
+        assert_eq!(entry("23.340", "\"req\":{\"headers\":{}},", "request").
+            request_id(), None);
+    }
+
+    #[test]
+    fn entry_invalid()
+    {
+        assert!(mri_client_entry("get: entered").is_err());
+        assert!(mri_client_entry("{\"name\":\"mget\",\"hostname\":\"h\",\
+            \"pid\":1,\"level\":20,\"msg\":\"x\",\"time\":\"yesterday\",\
+            \"v\":0}").is_err());
+        assert!(mri_client_entry("{\"name\":\"mget\",\"hostname\":\"h\",\
+            \"pid\":1,\"level\":20,\"msg\":\"x\",\
+            \"time\":\"2019-05-09T21:34:23.160Z\",\"v\":1}").is_err());
+    }
+
+    ///
+    /// The client's view of the request runs from its first record to its
+    /// last, and the first record with a response tells us when the headers
+    /// arrived.
+    ///
+    #[test]
+    fn request_times()
+    {
+        let entries = [
+            entry("23.170", &res(503, REQUEST_ID), "response"),
+            entry("23.160", &req_id(REQUEST_ID), "get: entered"),
+            entry("24.000", &req_id("0b1c9e3e"), "ls: done"),
+            entry("23.340", &res(200, REQUEST_ID), "response"),
+            entry("23.900", &req_id(REQUEST_ID), "get: done"),
+        ];
+        let info = mri_client_request(&entries, REQUEST_ID).unwrap().unwrap();
+        assert_eq!(info.cri_nentries, 4);
+        assert_eq!(info.cri_hostname, "dap-laptop");
+        assert_eq!(info.cri_pid, "40212");
+        assert_eq!(info.cri_time_started, time(23, 160));
+        assert_eq!(info.cri_time_headers, Some(time(23, 170)));
+        assert_eq!(info.cri_status_code, Some(503));
+        assert_eq!(info.cri_time_finished, time(23, 900));
+        assert!(info.cri_time_retries.is_empty());
+    }
+
+    #[test]
+    fn request_retries()
+    {
+        let entries = [
+            entry("23.160", &req_id(REQUEST_ID), "get: entered"),
+            entry("23.200", &format!("{}\"attempt\":1,", req_id(REQUEST_ID)),
+                "request sent"),
+            entry("23.300", &req_id(REQUEST_ID), "Retrying after error"),
+            entry("23.400", &format!("{}\"attempt\":2,", req_id(REQUEST_ID)),
+                "request sent"),
+            entry("23.450", &format!("{}\"attempt\":2,", req_id(REQUEST_ID)),
+                "request failed"),
+            entry("23.500", &format!("{}\"attempt\":3,", req_id(REQUEST_ID)),
+                "request sent"),
+            entry("23.550", &format!("{}\"attempt\":3,", req_id(REQUEST_ID)),
+                "request failed, not retrying"),
+            entry("23.600", &req_id(REQUEST_ID), "get: done"),
+        ];
+        let info = mri_client_request(&entries, REQUEST_ID).unwrap().unwrap();
+        assert_eq!(info.cri_time_retries, vec![ time(23, 400),
+            time(23, 500) ]);
+        assert_eq!(info.cri_time_headers, None);
+        assert_eq!(info.cri_status_code, None);
+    }
+
+    #[test]
+    fn request_no_match()
+    {
+        let entries = [
+            entry("23.160", &req_id("0b1c9e3e"), "ls: entered"),
+            entry("23.340", &res(200, "0b1c9e3e"), "response"),
+            entry("23.400", "", "no request"),
+        ];
+        assert!(mri_client_request(&entries, REQUEST_ID).unwrap().is_none());
+        assert!(mri_client_request(&[], REQUEST_ID).unwrap().is_none());
+    }
+}
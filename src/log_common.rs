@@ -2,26 +2,748 @@
  * src/log_common.rs: common functions for log parsing
  */
 
+use std::collections::VecDeque;
+use std::fmt;
 use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Cursor;
 use std::io::Read;
 
-//
-// XXX This should really have a byte limit that stops when we've read that many
-// bytes.  Could use `take()`, but want it to emit an error rather than just
-// EOF.
-//
-pub fn mri_read_file(filename : &String)
-    -> Result<String, String>
+use super::error::mri_json_error;
+use super::error::MantaError;
+use super::error::MantaErrorKind;
+use super::log_haproxy::mri_haproxy_entry;
+use super::log_mako::mri_mako_entry;
+
+///
+/// Identifies the kinds of log files that we know how to parse.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MantaLogFormat {
+    /// bunyan records from Muskie (having "name": "muskie")
+    Muskie,
+    /// syslog lines from haproxy on the load balancers
+    Haproxy,
+    /// nginx access log lines from Mako on the storage nodes
+    Mako,
+    /// bunyan records from node-manta (see `MRI_CLIENT_FIELDS`)
+    Client,
+}
+
+impl fmt::Display for MantaLogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            MantaLogFormat::Muskie => "Muskie log",
+            MantaLogFormat::Haproxy => "haproxy log",
+            MantaLogFormat::Mako => "Mako access log",
+            MantaLogFormat::Client => "node-manta client log",
+        })
+    }
+}
+
+///
+/// Determines the format of the log read by `lines` by looking at its first
+/// record.  Returns `Ok(None)` if the log is not in any format we recognize
+/// and an error only if the log could not be read.  Lines read to make this
+/// determination are pushed back onto `lines`, so the caller can go on to
+/// parse the log from the beginning.
+///
+/// bunyan records are JSON objects, which we identify by the leading "{".
+/// These are usually one per line, but we keep reading while the record is
+/// incomplete so that pretty-printed records work too (see
+/// `mri_json_record_start()`).  Anything else must be a line-oriented format,
+/// which we identify by trying to parse the first line.
+///
+pub fn mri_detect_format<R : BufRead>(lines : &mut LogLineReader<R>)
+    -> Result<Option<MantaLogFormat>, MantaError>
+{
+    let mut consumed = Vec::new();
+    let result = mri_detect_lines(lines, &mut consumed);
+    lines.unread(consumed);
+    return result;
+}
+
+fn mri_detect_lines<R : BufRead>(lines : &mut LogLineReader<R>,
+    consumed : &mut Vec<LogLine>)
+    -> Result<Option<MantaLogFormat>, MantaError>
 {
-    let mut buffer = String::new();
-    let mut reader = match File::open(filename) {
+    let first = loop {
+        match lines.next() {
+            None => return Ok(None),
+            Some(Err(e)) => return Err(e),
+            Some(Ok(l)) => {
+                let text = String::from(l.ll_text.trim());
+                consumed.push(l);
+                if !text.is_empty() {
+                    break text;
+                }
+            }
+        }
+    };
+
+    if first.starts_with('{') {
+        let mut record = first;
+        loop {
+            match serde_json::from_str::<serde_json::Value>(&record) {
+                Ok(ref value) => return Ok(mri_detect_bunyan(value)),
+                Err(ref e) if e.is_eof() &&
+                    (record.len() as u64) < lines.max_line_bytes() => {
+                    match lines.next() {
+                        None => return Ok(None),
+                        Some(Err(e)) => return Err(e),
+                        Some(Ok(l)) => {
+                            if mri_json_record_start(&l.ll_text) {
+                                record.clear();
+                            } else {
+                                record.push('\n');
+                            }
+                            record.push_str(&l.ll_text);
+                            consumed.push(l);
+                        }
+                    }
+                },
+                Err(_) => return Ok(None)
+            }
+        }
+    }
+
+    if mri_haproxy_entry(&first).is_ok() {
+        return Ok(Some(MantaLogFormat::Haproxy));
+    }
+
+    if mri_mako_entry(&first).is_ok() {
+        return Ok(Some(MantaLogFormat::Mako));
+    }
+
+    return Ok(None);
+}
+
+///
+/// Returns whether `line`, which follows an incomplete JSON record, begins a
+/// new record rather than continuing that one.  bunyan writes one record per
+/// line, and pretty-printers indent everything inside a record, so a line that
+/// starts with "{" starts a new record.  This way, a record that was cut short
+/// (e.g., because the disk filled up) doesn't swallow the one after it.
+///
+pub fn mri_json_record_start(line : &str)
+    -> bool
+{
+    return line.starts_with('{');
+}
+
+///
+/// Fields that identify a bunyan record as one logged by node-manta (or the
+/// restify client underneath it) about a request.  Any one of these will do.
+/// Other programs write bunyan logs too (nearly every Triton and Manta
+/// service does), and we don't want to mistake one of those for a client log.
+///
+const MRI_CLIENT_FIELDS : [&str; 6] =
+    [ "req_id", "req", "res", "client_req", "client_res", "attempt" ];
+
+///
+/// Given the first record of a JSON log, determines which component logged it.
+///
+fn mri_detect_bunyan(value : &serde_json::Value)
+    -> Option<MantaLogFormat>
+{
+    let obj = value.as_object()?;
+    if !obj.contains_key("v") || !obj.contains_key("msg") ||
+        !obj.contains_key("time") {
+        return None;
+    }
+
+    match obj.get("name").and_then(|n| n.as_str()) {
+        Some("muskie") => Some(MantaLogFormat::Muskie),
+        Some(_) if MRI_CLIENT_FIELDS.iter().any(|f| obj.contains_key(*f)) =>
+            Some(MantaLogFormat::Client),
+        _ => None
+    }
+}
+
+///
+/// Default limit on the length of a single line of input.  Muskie audit entries
+/// are the largest records we expect, and they're generally well under 100 KiB,
+/// but error stacks can make them much larger.
+///
+pub const MRI_MAX_LINE_BYTES : u64 = 16 * 1024 * 1024;
+
+///
+/// Default limit on the total number of bytes read from a single input.  This
+/// is large enough for the hourly logs from a busy webapi zone.
+///
+pub const MRI_MAX_FILE_BYTES : u64 = 64 * 1024 * 1024 * 1024;
+
+///
+/// Opens `filename` for reading line-by-line with the default limits.
+///
+pub fn mri_open_file(filename : &String)
+    -> Result<LogLineReader<BufReader<File>>, MantaError>
+{
+    match File::open(filename) {
+        Ok(f) => Ok(LogLineReader::new(filename, BufReader::new(f))),
+        Err(e) => Err(mri_io_error(filename, e))
+    }
+}
+
+///
+/// The filename that refers to standard input, and the name we use for
+/// standard input in messages.
+///
+pub const MRI_STDIN_FILENAME : &str = "-";
+const MRI_STDIN_NAME : &str = "(stdin)";
+
+///
+/// Number of bytes at the start of each input that we examine to determine how
+/// it's encoded.  This must be at least one tar header block.
+///
+const MRI_SNIFF_BYTES : usize = 512;
+
+///
+/// Limit on how deeply inputs can be nested inside one another (e.g., a
+/// gzipped log inside a tarball that is itself gzipped).
+///
+const MRI_MAX_NESTING : u32 = 4;
+
+///
+/// Describes how an input's bytes are encoded, as determined from its first few
+/// bytes.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MantaInputEncoding {
+    Plain,
+    Gzip,
+    Bzip2,
+    Xz,
+    Tar
+}
+
+fn mri_sniff_encoding(prefix : &[u8])
+    -> MantaInputEncoding
+{
+    if prefix.starts_with(&[0x1f, 0x8b]) {
+        MantaInputEncoding::Gzip
+    } else if prefix.starts_with(b"BZh") {
+        MantaInputEncoding::Bzip2
+    } else if prefix.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        MantaInputEncoding::Xz
+    } else if prefix.len() >= 262 && &prefix[257..262] == b"ustar" {
+        MantaInputEncoding::Tar
+    } else {
+        MantaInputEncoding::Plain
+    }
+}
+
+///
+/// Opens `filename` and invokes `visitor` once for each log it contains, with
+/// the log's name and a reader for its uncompressed contents.
+///
+/// Plain files contain exactly one log.  Files compressed with gzip, bzip2, or
+/// xz are decompressed as they're read.  tar archives (which may themselves be
+/// compressed) contain one log for each regular file in the archive, and each
+/// of these may be compressed as well.  We identify all of these by their
+/// contents rather than their names.  Logs inside archives are named
+/// "ARCHIVE:MEMBER", where MEMBER is the path within the archive.
+///
+/// If `filename` is `MRI_STDIN_FILENAME`, the input is read from standard
+/// input, which (unlike a file) can only be visited once.
+///
+pub fn mri_visit_file(filename : &String,
+    visitor : &mut dyn FnMut(&str, &mut dyn BufRead) -> Result<(), MantaError>)
+    -> Result<(), MantaError>
+{
+    if filename == MRI_STDIN_FILENAME {
+        let stdin = std::io::stdin();
+        let mut stdin = stdin.lock();
+        return mri_visit_stream(MRI_STDIN_NAME, &mut stdin, 0, visitor);
+    }
+
+    let mut file = match File::open(filename) {
         Ok(f) => f,
-        Err(e) => return Err(format!("open \"{}\": {}", filename, e))
+        Err(e) => return Err(mri_io_error(filename, e))
+    };
+
+    return mri_visit_stream(filename, &mut file, 0, visitor);
+}
+
+fn mri_visit_stream(name : &str, source : &mut dyn Read, depth : u32,
+    visitor : &mut dyn FnMut(&str, &mut dyn BufRead) -> Result<(), MantaError>)
+    -> Result<(), MantaError>
+{
+    let mut prefix = Vec::with_capacity(MRI_SNIFF_BYTES);
+    if let Err(e) = (&mut *source).take(MRI_SNIFF_BYTES as u64).
+        read_to_end(&mut prefix) {
+        return Err(mri_io_error(name, e));
+    }
+
+    let encoding = mri_sniff_encoding(&prefix);
+    if encoding != MantaInputEncoding::Plain && depth >= MRI_MAX_NESTING {
+        return Err(MantaError::new(MantaErrorKind::UnrecognizedFormat(
+            format!("compressed or archived inputs nested more than {} \
+            levels deep", MRI_MAX_NESTING))).in_input(name));
+    }
+
+    let reader = Cursor::new(prefix).chain(source);
+    match encoding {
+        MantaInputEncoding::Plain => {
+            visitor(name, &mut BufReader::new(reader))
+        },
+        MantaInputEncoding::Gzip => {
+            let mut decoder = flate2::read::MultiGzDecoder::new(reader);
+            mri_visit_stream(name, &mut decoder, depth + 1, visitor)
+        },
+        MantaInputEncoding::Bzip2 => {
+            let mut decoder = bzip2_rs::DecoderReader::new(reader);
+            mri_visit_stream(name, &mut decoder, depth + 1, visitor)
+        },
+        MantaInputEncoding::Xz => {
+            let mut decoder = xz2::read::XzDecoder::new_multi_decoder(reader);
+            mri_visit_stream(name, &mut decoder, depth + 1, visitor)
+        },
+        MantaInputEncoding::Tar => {
+            mri_visit_archive(name, reader, depth, visitor)
+        }
+    }
+}
+
+fn mri_visit_archive<R : Read>(name : &str, reader : R, depth : u32,
+    visitor : &mut dyn FnMut(&str, &mut dyn BufRead) -> Result<(), MantaError>)
+    -> Result<(), MantaError>
+{
+    let mut archive = tar::Archive::new(reader);
+    let entries = match archive.entries() {
+        Ok(e) => e,
+        Err(e) => return Err(mri_io_error(name, e))
     };
 
-    if let Err(e) = reader.read_to_string(&mut buffer) {
-        return Err(format!("{}", e));
+    for entry in entries {
+        let mut entry = match entry {
+            Ok(e) => e,
+            Err(e) => return Err(mri_io_error(name, e))
+        };
+
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let member = match entry.path() {
+            Ok(p) => format!("{}:{}", name, p.display()),
+            Err(e) => return Err(mri_io_error(name, e))
+        };
+
+        mri_visit_stream(&member, &mut entry, depth + 1, visitor)?;
     }
 
-    return Ok(buffer);
+    return Ok(());
+}
+
+///
+/// One line of input, without the trailing newline.  `ll_lineno` is the
+/// 1-based line number within the input.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLine {
+    pub ll_lineno : u64,
+    pub ll_text : String
+}
+
+///
+/// A LogLineReader iterates over the lines of any `BufRead`, holding at most
+/// one line in memory at a time.  It enforces a limit on the length of each
+/// line and on the total size of the input.  Exceeding either limit, failing to
+/// read, or finding a line that's not valid UTF-8 produces an error that
+/// includes the input's name and line number, after which iteration stops.
+///
+pub struct LogLineReader<R : BufRead> {
+    llr_name : String,
+    llr_pending : VecDeque<LogLine>,
+    llr_source : R,
+    llr_lineno : u64,
+    llr_nbytes : u64,
+    llr_max_line_bytes : u64,
+    llr_max_file_bytes : u64,
+    llr_done : bool
+}
+
+impl<R : BufRead> LogLineReader<R> {
+    ///
+    /// Returns a reader for `source` using the default limits.  `name` is used
+    /// in error messages to identify the input.
+    ///
+    pub fn new(name : &str, source : R)
+        -> LogLineReader<R>
+    {
+        return LogLineReader::with_limits(name, source, MRI_MAX_LINE_BYTES,
+            MRI_MAX_FILE_BYTES);
+    }
+
+    ///
+    /// Returns a reader for `source` that fails on lines longer than
+    /// `max_line_bytes` or after reading more than `max_file_bytes` in total.
+    ///
+    pub fn with_limits(name : &str, source : R, max_line_bytes : u64,
+        max_file_bytes : u64)
+        -> LogLineReader<R>
+    {
+        return LogLineReader {
+            llr_name : String::from(name),
+            llr_pending : VecDeque::new(),
+            llr_source : source,
+            llr_lineno : 0,
+            llr_nbytes : 0,
+            llr_max_line_bytes : max_line_bytes,
+            llr_max_file_bytes : max_file_bytes,
+            llr_done : false
+        }
+    }
+
+    /// Returns the name of this input, as used in error messages.
+    pub fn name(&self)
+        -> &str
+    {
+        return &self.llr_name;
+    }
+
+    /// Returns the limit on the length of each line.
+    pub fn max_line_bytes(&self)
+        -> u64
+    {
+        return self.llr_max_line_bytes;
+    }
+
+    ///
+    /// Pushes `lines` back onto the front of the reader so that they'll be
+    /// returned again (in the same order) before any other lines.
+    ///
+    pub fn unread(&mut self, lines : Vec<LogLine>)
+    {
+        for line in lines.into_iter().rev() {
+            self.llr_pending.push_front(line);
+        }
+    }
+
+    fn fail(&mut self, error : std::io::Error)
+        -> Option<Result<LogLine, MantaError>>
+    {
+        self.llr_done = true;
+        return Some(Err(mri_io_error(&self.llr_name, error).at_line(
+            self.llr_lineno)));
+    }
+}
+
+impl<R : BufRead> Iterator for LogLineReader<R> {
+    type Item = Result<LogLine, MantaError>;
+
+    fn next(&mut self)
+        -> Option<Result<LogLine, MantaError>>
+    {
+        if let Some(line) = self.llr_pending.pop_front() {
+            return Some(Ok(line));
+        }
+
+        if self.llr_done {
+            return None;
+        }
+
+        //
+        // We read at most one byte more than either limit allows so that we
+        // can tell the difference between hitting the limit and reaching
+        // the end of a line or the end of the input.
+        //
+        let file_remaining = self.llr_max_file_bytes - self.llr_nbytes;
+        let limit = std::cmp::min(self.llr_max_line_bytes, file_remaining) + 1;
+        let mut buf = Vec::new();
+        let nread = match (&mut self.llr_source).take(limit).read_until(b'\n',
+            &mut buf) {
+            Ok(0) => {
+                self.llr_done = true;
+                return None;
+            },
+            Ok(n) => n as u64,
+            Err(e) => {
+                self.llr_lineno += 1;
+                return self.fail(e);
+            }
+        };
+
+        self.llr_lineno += 1;
+        self.llr_nbytes += nread;
+
+        if self.llr_nbytes > self.llr_max_file_bytes {
+            let max = self.llr_max_file_bytes;
+            return self.fail(mri_invalid_data(format!(
+                "input exceeds maximum size of {} bytes", max)));
+        }
+
+        if buf.last() == Some(&b'\n') {
+            buf.pop();
+            if buf.last() == Some(&b'\r') {
+                buf.pop();
+            }
+        } else if buf.len() as u64 > self.llr_max_line_bytes {
+            let max = self.llr_max_line_bytes;
+            return self.fail(mri_invalid_data(format!(
+                "line exceeds maximum length of {} bytes", max)));
+        }
+
+        match String::from_utf8(buf) {
+            Ok(text) => Some(Ok(LogLine {
+                ll_lineno : self.llr_lineno,
+                ll_text : text
+            })),
+            Err(e) => self.fail(mri_invalid_data(format!("invalid UTF-8: {}",
+                e.utf8_error())))
+        }
+    }
+}
+
+fn mri_io_error(name : &str, error : std::io::Error)
+    -> MantaError
+{
+    return MantaError::new(MantaErrorKind::Io(error)).in_input(name);
+}
+
+fn mri_invalid_data(message : String)
+    -> std::io::Error
+{
+    return std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+}
+
+///
+/// Parses `text` as a single JSON value of type `T`.  Errors identify the
+/// offending field by its JSON path.
+///
+pub fn mri_parse_json<T : serde::de::DeserializeOwned>(text : &str)
+    -> Result<T, MantaError>
+{
+    let mut deserializer = serde_json::Deserializer::from_str(text);
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(
+        mri_json_error)?;
+    deserializer.end().map_err(
+        |e| MantaError::new(MantaErrorKind::JsonSyntax(e)))?;
+    return Ok(value);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn detect(text : &str)
+        -> Option<MantaLogFormat>
+    {
+        let mut lines = LogLineReader::new("test", Cursor::new(
+            text.as_bytes()));
+        return mri_detect_format(&mut lines).unwrap();
+    }
+
+    #[test]
+    fn detect_bunyan()
+    {
+        assert_eq!(detect("{\"name\":\"muskie\",\"hostname\":\"h\",\"pid\":1,\
+            \"level\":30,\"msg\":\"handled: 200\",\"time\":\
+            \"2019-05-09T21:36:51.982Z\",\"v\":0}\n"),
+            Some(MantaLogFormat::Muskie));
+        assert_eq!(detect("{\"name\":\"mget\",\"hostname\":\"h\",\"pid\":1,\
+            \"level\":20,\"req_id\":\"ec5d32fe-5ff8-43ae-a152-45fd1005afff\",\
+            \"msg\":\"get: entered\",\"time\":\"2019-05-09T21:34:23.160Z\",\
+            \"v\":0}\n"), Some(MantaLogFormat::Client));
+        assert_eq!(detect("{\"name\":\"mget\",\"hostname\":\"h\",\"pid\":1,\
+            \"level\":10,\"res\":{\"statusCode\":200},\"msg\":\"Response \
+            received\",\"time\":\"2019-05-09T21:34:23.340Z\",\"v\":0}\n"),
+            Some(MantaLogFormat::Client));
+
+        // Pretty-printed records span several lines.
+        assert_eq!(detect("\n{\n  \"name\": \"muskie\",\n  \"msg\": \"\",\n  \
+            \"time\": \"2019-05-09T21:36:51.982Z\",\n  \"v\": 0\n}\n"),
+            Some(MantaLogFormat::Muskie));
+
+        // A record that was cut short doesn't hide the one after it.
+        assert_eq!(detect("{\"name\":\"muskie\",\"hostn\n\
+            {\"name\":\"muskie\",\"msg\":\"\",\
+            \"time\":\"2019-05-09T21:36:51.982Z\",\"v\":0}\n"),
+            Some(MantaLogFormat::Muskie));
+    }
+
+    #[test]
+    fn detect_other_bunyan()
+    {
+        assert_eq!(detect("{\"name\":\"moray\",\"hostname\":\"h\",\"pid\":1,\
+            \"level\":30,\"msg\":\"listening\",\"time\":\
+            \"2019-05-09T21:34:23.160Z\",\"v\":0}\n"), None);
+        assert_eq!(detect("{\"name\":\"mget\",\"req_id\":\"x\",\
+            \"time\":\"2019-05-09T21:34:23.160Z\",\"v\":0}\n"), None);
+        assert_eq!(detect("{\"msg\":\"x\",\"req_id\":\"x\",\
+            \"time\":\"2019-05-09T21:34:23.160Z\",\"v\":0}\n"), None);
+        assert_eq!(detect("[ 1, 2, 3 ]\n"), None);
+        assert_eq!(detect("{ \"name\": \n"), None);
+    }
+
+    #[test]
+    fn detect_lines()
+    {
+        assert_eq!(detect("2019-04-26T21:18:02+00:00 49a3d111 haproxy[664855]: \
+            ::ffff:172.20.5.18:64853 [26/Apr/2019:21:18:01.848] https \
+            secure_api/be2 4/0/1/155/276 200 405 - - ---- 17/1/17/5/0 0/0 \
+            \"GET /dap/public?limit=1024 HTTP/1.1\"\n"),
+            Some(MantaLogFormat::Haproxy));
+        assert_eq!(detect("127.0.0.1 - - [09/May/2019:21:36:51 +0000] \
+            \"GET /owner/objectid HTTP/1.1\" 200 51 0.002\n"),
+            Some(MantaLogFormat::Mako));
+        assert_eq!(detect("this is not a log\n"), None);
+        assert_eq!(detect("\n\n"), None);
+    }
+
+    ///
+    /// Reads `input` with the given limits, returning the lines read and the
+    /// error that stopped the reader, if any.
+    ///
+    fn read_lines(input : &[u8], max_line_bytes : u64, max_file_bytes : u64)
+        -> (Vec<(u64, String)>, Option<MantaError>)
+    {
+        let mut reader = LogLineReader::with_limits("test", Cursor::new(input),
+            max_line_bytes, max_file_bytes);
+        let mut lines = Vec::new();
+        while let Some(result) = reader.next() {
+            match result {
+                Ok(l) => lines.push((l.ll_lineno, l.ll_text)),
+                Err(e) => {
+                    assert!(reader.next().is_none(),
+                        "reader continued after an error");
+                    return (lines, Some(e));
+                }
+            }
+        }
+
+        return (lines, None);
+    }
+
+    fn lines(expected : &[(u64, &str)])
+        -> Vec<(u64, String)>
+    {
+        return expected.iter().map(|(n, l)| (*n, String::from(*l))).collect();
+    }
+
+    /// Checks that `error` is an `InvalidData` I/O error with `message`.
+    fn assert_invalid_data(error : Option<MantaError>, message : &str)
+    {
+        let error = error.expect("expected an error");
+        match error.kind() {
+            MantaErrorKind::Io(e) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::InvalidData)
+            },
+            _ => panic!("expected an I/O error, but found: {}", error)
+        }
+        assert_eq!(error.to_string(), message);
+    }
+
+    #[test]
+    fn reader_lines()
+    {
+        let (read, error) = read_lines(b"one\r\n\ntwo\nthree", 16, 1024);
+        assert_eq!(read, lines(&[ (1, "one"), (2, ""), (3, "two"),
+            (4, "three") ]));
+        assert!(error.is_none());
+
+        let mut reader = LogLineReader::new("test", Cursor::new(
+            "one\ntwo\n".as_bytes()));
+        let first = reader.next().unwrap().unwrap();
+        reader.unread(vec![first]);
+        let read : Vec<(u64, String)> = reader.map(
+            |l| l.map(|l| (l.ll_lineno, l.ll_text)).unwrap()).collect();
+        assert_eq!(read, lines(&[ (1, "one"), (2, "two") ]));
+    }
+
+    #[test]
+    fn reader_line_limit()
+    {
+        // A line may be exactly as long as the limit, not counting "\n".
+        let (read, error) = read_lines(b"12345678\n12345678", 8, 1024);
+        assert_eq!(read, lines(&[ (1, "12345678"), (2, "12345678") ]));
+        assert!(error.is_none());
+
+        let (read, error) = read_lines(b"ok\n123456789\nmore\n", 8, 1024);
+        assert_eq!(read, lines(&[ (1, "ok") ]));
+        assert_invalid_data(error,
+            "\"test\": line 2: line exceeds maximum length of 8 bytes");
+
+        let (read, error) = read_lines(b"123456789", 8, 1024);
+        assert!(read.is_empty());
+        assert_invalid_data(error,
+            "\"test\": line 1: line exceeds maximum length of 8 bytes");
+    }
+
+    #[test]
+    fn reader_file_limit()
+    {
+        let (read, error) = read_lines(b"1234\n5678\n", 8, 10);
+        assert_eq!(read, lines(&[ (1, "1234"), (2, "5678") ]));
+        assert!(error.is_none());
+
+        let (read, error) = read_lines(b"1234\n5678\n9\n", 8, 10);
+        assert_eq!(read, lines(&[ (1, "1234"), (2, "5678") ]));
+        assert_invalid_data(error,
+            "\"test\": line 3: input exceeds maximum size of 10 bytes");
+
+        let (read, error) = read_lines(b"1234\n567890\n", 8, 10);
+        assert_eq!(read, lines(&[ (1, "1234") ]));
+        assert_invalid_data(error,
+            "\"test\": line 2: input exceeds maximum size of 10 bytes");
+    }
+
+    #[test]
+    fn reader_invalid_utf8()
+    {
+        let (read, error) = read_lines(b"ok\nbad \xff here\nmore\n", 16, 1024);
+        assert_eq!(read, lines(&[ (1, "ok") ]));
+        assert_invalid_data(error, "\"test\": line 2: invalid UTF-8: invalid \
+            utf-8 sequence of 1 bytes from index 4");
+    }
+
+    ///
+    /// Returns the name and contents of each log in `input`.
+    ///
+    fn visit(name : &str, input : &[u8])
+        -> Vec<(String, String)>
+    {
+        let mut logs = Vec::new();
+        mri_visit_stream(name, &mut Cursor::new(input), 0,
+            &mut |name, reader| {
+            let mut text = String::new();
+            reader.read_to_string(&mut text).unwrap();
+            logs.push((String::from(name), text));
+            Ok(())
+        }).unwrap();
+        return logs;
+    }
+
+    ///
+    /// Each kind of compression is recognized by its contents, and decodes to
+    /// the same log as the others.
+    ///
+    #[test]
+    fn visit_compressed()
+    {
+        let tar = visit("logs.tar", include_bytes!(
+            "../testdata/cases/archive/logs.tar"));
+        let bz2 = visit("muskie.log.bz2", include_bytes!(
+            "../testdata/cases/compressed/muskie.log.bz2"));
+        assert_eq!(tar.len(), 1);
+        assert_eq!(tar[0].0, "logs.tar:webapi/muskie.log");
+        assert!(tar[0].1.starts_with("{\"name\":\"muskie\""));
+        assert_eq!(bz2, vec![ (String::from("muskie.log.bz2"),
+            tar[0].1.clone()) ]);
+
+        let gz = visit("haproxy.log.gz", include_bytes!(
+            "../testdata/cases/archive/haproxy.log.gz"));
+        let xz = visit("haproxy.log.xz", include_bytes!(
+            "../testdata/cases/compressed/haproxy.log.xz"));
+        assert_eq!(gz.len(), 1);
+        assert!(gz[0].1.contains(" haproxy["));
+        assert_eq!(xz, vec![ (String::from("haproxy.log.xz"),
+            gz[0].1.clone()) ]);
+
+        let plain = visit("plain.log", b"not compressed\n");
+        assert_eq!(plain, vec![ (String::from("plain.log"),
+            String::from("not compressed\n")) ]);
+    }
 }
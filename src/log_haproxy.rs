@@ -0,0 +1,523 @@
+/*
+ * src/log_haproxy.rs: haproxy log format parser
+ *
+ * The load balancers log one line per request using haproxy's "httplog"
+ * format, prefixed with the usual syslog header.  For example:
+ *
+ *   2019-04-26T21:18:02+00:00 49a3d111-c7a3-478a-9d9c-8ec85a0f64da
+ *       haproxy[664855]: ::ffff:172.20.5.18:64853 [26/Apr/2019:21:18:01.848]
+ *       https secure_api/be2 4/0/1/155/276 200 405 - - ---- 17/1/17/5/0 0/0
+ *       "GET /dap/public?limit=1024 HTTP/1.1"
+ *
+ * (This is all one line in the actual log.)  See the "HTTP log format" section
+ * of the haproxy configuration manual for details on each field.
+ */
+
+use std::io::BufRead;
+
+use super::error::MantaError;
+use super::error::MantaErrorKind;
+use super::log_common::mri_open_file;
+use super::log_common::LogLineReader;
+
+/*
+ * Given a file containing haproxy log entries (one per line), return an
+ * iterator over the entries in the file.  The file is read as the iterator is
+ * consumed.
+ */
+pub fn mri_parse_haproxy_file(filename : &String)
+    -> Result<impl Iterator<Item = Result<HaproxyLogEntry, MantaError>>,
+    MantaError>
+{
+    Ok(mri_haproxy_entries(mri_open_file(filename)?))
+}
+
+/*
+ * Given a reader over lines of an haproxy log, return an iterator over the
+ * entries in the log.  Blank lines are skipped.
+ */
+pub fn mri_haproxy_entries<R : BufRead>(lines : LogLineReader<R>)
+    -> impl Iterator<Item = Result<HaproxyLogEntry, MantaError>>
+{
+    let name = String::from(lines.name());
+    lines.filter_map(move |line| match line {
+        Err(e) => Some(Err(e)),
+        Ok(ref l) if l.ll_text.trim().is_empty() => None,
+        Ok(l) => Some(mri_haproxy_entry(&l.ll_text).map_err(
+            |e| e.in_input(&name).at_line(l.ll_lineno)))
+    })
+}
+
+///
+/// A HaproxyLogEntry represents one parsed haproxy "httplog" line.  Timers that
+/// haproxy reports as "-1" (because the corresponding phase never happened,
+/// e.g., when the client aborted the request) are represented as `None`.
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HaproxyLogEntry {
+    // Syslog fields
+    #[serde(rename = "syslog_time")]        pub hle_syslog_time : String,
+    #[serde(rename = "hostname")]           pub hle_hostname : String,
+    #[serde(rename = "pid")]                pub hle_pid : String,
+
+    // Client connection
+    #[serde(rename = "client_ip")]          pub hle_client_ip : String,
+    #[serde(rename = "client_port")]        pub hle_client_port : u16,
+    #[serde(rename = "accept_time",
+        serialize_with = "super::json::mri_json_time")]
+    pub hle_accept_time : chrono::DateTime<chrono::Utc>,
+
+    // Routing
+    #[serde(rename = "frontend")]           pub hle_frontend : String,
+    #[serde(rename = "backend")]            pub hle_backend : String,
+    #[serde(rename = "server")]             pub hle_server : String,
+
+    // Timers ("Tq/Tw/Tc/Tr/Tt")
+    #[serde(rename = "time_request_ms",
+        serialize_with = "super::json::mri_json_duration_opt")]
+    pub hle_time_request : Option<chrono::Duration>,
+    #[serde(rename = "time_queued_ms",
+        serialize_with = "super::json::mri_json_duration_opt")]
+    pub hle_time_queued : Option<chrono::Duration>,
+    #[serde(rename = "time_connect_ms",
+        serialize_with = "super::json::mri_json_duration_opt")]
+    pub hle_time_connect : Option<chrono::Duration>,
+    #[serde(rename = "time_response_ms",
+        serialize_with = "super::json::mri_json_duration_opt")]
+    pub hle_time_response : Option<chrono::Duration>,
+    #[serde(rename = "time_total_ms",
+        serialize_with = "super::json::mri_json_duration")]
+    pub hle_time_total : chrono::Duration,
+
+    #[serde(rename = "status_code")]        pub hle_status_code : Option<u16>,
+    #[serde(rename = "bytes_read")]         pub hle_bytes_read : u64,
+    #[serde(rename = "termination_state")]  pub hle_termination_state : String,
+
+    // Connection counters ("actconn/feconn/beconn/srv_conn/retries")
+    #[serde(rename = "conn_active")]        pub hle_conn_active : u32,
+    #[serde(rename = "conn_frontend")]      pub hle_conn_frontend : u32,
+    #[serde(rename = "conn_backend")]       pub hle_conn_backend : u32,
+    #[serde(rename = "conn_server")]        pub hle_conn_server : u32,
+    #[serde(rename = "retries")]            pub hle_retries : u32,
+
+    // Queue counters ("srv_queue/backend_queue")
+    #[serde(rename = "queue_server")]       pub hle_queue_server : u32,
+    #[serde(rename = "queue_backend")]      pub hle_queue_backend : u32,
+
+    #[serde(rename = "request_line")]       pub hle_request_line : String,
+}
+
+impl HaproxyLogEntry {
+    ///
+    /// Returns the wall-clock time when haproxy finished with this request,
+    /// based on the accept time and the total time ("Tt").  The parser
+    /// rejects entries for which this can't be represented.
+    ///
+    pub fn wall_end(&self)
+        -> chrono::DateTime<chrono::Utc>
+    {
+        return self.hle_accept_time + self.hle_time_total;
+    }
+}
+
+///
+/// Given the haproxy entries we've been given, returns the one that most
+/// likely describes the request that Muskie processed using `method` and `url`
+/// starting at `muskie_start`.
+///
+/// An entry matches when its request line has the same method and URL and
+/// when haproxy was processing the request at the time Muskie started it.
+/// Since the clocks on the two systems may differ a little, we allow a second
+/// of slop.  When several entries match, we pick the one accepted most
+/// recently before Muskie started, since haproxy forwards requests as soon as
+/// it has read the headers.
+///
+pub fn mri_haproxy_match(entries : &[HaproxyLogEntry], method : &str,
+    url : &str, muskie_start : &chrono::DateTime<chrono::Utc>)
+    -> Option<HaproxyLogEntry>
+{
+    let slop = chrono::Duration::seconds(1);
+
+    entries.iter().filter(|entry| {
+        let mut parts = entry.hle_request_line.split(' ');
+        parts.next() == Some(method) && parts.next() == Some(url) &&
+            entry.hle_accept_time <= *muskie_start + slop &&
+            entry.wall_end() >= *muskie_start - slop
+    }).min_by_key(|entry|
+        (*muskie_start - entry.hle_accept_time).num_milliseconds().abs()
+    ).cloned()
+}
+
+///
+/// Longest time (in milliseconds) that we accept for any of haproxy's timers:
+/// one day.  Longer values are almost certainly corrupt, and one long enough
+/// would put the end of the request beyond the range of times we can
+/// represent.
+///
+pub const MRI_HAPROXY_TIMER_MAX_MS : i64 = 24 * 60 * 60 * 1000;
+
+///
+/// Given one line from an haproxy log, parse it into a HaproxyLogEntry.
+///
+pub fn mri_haproxy_entry(line : &str)
+    -> Result<HaproxyLogEntry, MantaError>
+{
+    //
+    // The request line is the only field that may contain spaces, and it's
+    // always last.  Split it off first so that we can split the rest of the
+    // line on whitespace.
+    //
+    let quote = line.find('"').ok_or_else(||
+        mri_haproxy_syntax("expected quoted request line"))?;
+    let request_line = line[quote..].trim_end();
+    if request_line.len() < 2 || !request_line.ends_with('"') {
+        return Err(mri_haproxy_syntax("request line is not terminated"));
+    }
+    let request_line = &request_line[1..request_line.len() - 1];
+    let tokens : Vec<&str> = line[0..quote].split_whitespace().collect();
+
+    //
+    // The syslog header may have a variable number of tokens depending on the
+    // timestamp format, so we locate the "haproxy[pid]:" tag and work from
+    // there.
+    //
+    let tagidx = tokens.iter().position(|t| t.starts_with("haproxy[")).
+        ok_or_else(|| mri_haproxy_syntax("expected \"haproxy[PID]:\" tag"))?;
+    if tagidx < 2 {
+        return Err(mri_haproxy_syntax(
+            "expected syslog timestamp and hostname"));
+    }
+    let fields = &tokens[tagidx + 1..];
+    if fields.len() < 12 {
+        return Err(mri_haproxy_syntax(&format!("expected at least 12 fields \
+            after syslog header, but found {}", fields.len())));
+    }
+
+    let tag = tokens[tagidx];
+    let pid = tag.trim_start_matches("haproxy[").trim_end_matches(':').
+        trim_end_matches(']');
+
+    let (client_ip, client_port) = mri_haproxy_split_address(fields[0])?;
+    let accept_time = mri_haproxy_accept_time(fields[1])?;
+    let frontend = fields[2];
+    let (backend, server) = match fields[3].find('/') {
+        Some(i) => (&fields[3][0..i], &fields[3][i + 1..]),
+        None => return Err(mri_haproxy_invalid("backend/server", format!(
+            "expected \"/\", but found \"{}\"", fields[3])))
+    };
+
+    let timers = mri_haproxy_counters(fields[4], 5, "Tq/Tw/Tc/Tr/Tt")?;
+    if let Some(t) = timers.iter().flatten().find(
+        |t| **t > MRI_HAPROXY_TIMER_MAX_MS) {
+        return Err(mri_haproxy_invalid("Tq/Tw/Tc/Tr/Tt", format!(
+            "timer value too large: {} ms", t)));
+    }
+    let time_total = timers[4].ok_or_else(|| mri_haproxy_invalid(
+        "Tq/Tw/Tc/Tr/Tt", String::from("total time (\"Tt\") is missing")))?;
+    let time_total = chrono::Duration::milliseconds(time_total);
+    if accept_time.checked_add_signed(time_total).is_none() {
+        return Err(mri_haproxy_invalid("accept date", format!(
+            "request ends too far in the future: {}", fields[1])));
+    }
+
+    let status_code = match mri_haproxy_number(fields[5], "status code")? {
+        None => None,
+        Some(n) if (100..1000).contains(&n) => Some(n as u16),
+        Some(n) => return Err(mri_haproxy_invalid("status code", format!(
+            "unsupported value: {}", n)))
+    };
+    let bytes_read = mri_haproxy_number(fields[6], "bytes read")?.
+        ok_or_else(|| mri_haproxy_invalid("bytes read", String::from(
+        "unexpected value \"-1\"")))?;
+
+    let conns = mri_haproxy_counters(fields[10], 5,
+        "actconn/feconn/beconn/srv_conn/retries")?;
+    let queues = mri_haproxy_counters(fields[11], 2,
+        "srv_queue/backend_queue")?;
+
+    Ok(HaproxyLogEntry {
+        hle_syslog_time : tokens[0..tagidx - 1].join(" "),
+        hle_hostname : String::from(tokens[tagidx - 1]),
+        hle_pid : String::from(pid),
+        hle_client_ip : client_ip,
+        hle_client_port : client_port,
+        hle_accept_time : accept_time,
+        hle_frontend : String::from(frontend),
+        hle_backend : String::from(backend),
+        hle_server : String::from(server),
+        hle_time_request : timers[0].map(chrono::Duration::milliseconds),
+        hle_time_queued : timers[1].map(chrono::Duration::milliseconds),
+        hle_time_connect : timers[2].map(chrono::Duration::milliseconds),
+        hle_time_response : timers[3].map(chrono::Duration::milliseconds),
+        hle_time_total : time_total,
+        hle_status_code : status_code,
+        hle_bytes_read : bytes_read as u64,
+        hle_termination_state : String::from(fields[9]),
+        hle_conn_active : mri_haproxy_counter(conns[0], "actconn")?,
+        hle_conn_frontend : mri_haproxy_counter(conns[1], "feconn")?,
+        hle_conn_backend : mri_haproxy_counter(conns[2], "beconn")?,
+        hle_conn_server : mri_haproxy_counter(conns[3], "srv_conn")?,
+        hle_retries : mri_haproxy_counter(conns[4], "retries")?,
+        hle_queue_server : mri_haproxy_counter(queues[0], "srv_queue")?,
+        hle_queue_backend : mri_haproxy_counter(queues[1], "backend_queue")?,
+        hle_request_line : String::from(request_line)
+    })
+}
+
+///
+/// Splits a client address like "::ffff:172.20.5.18:64853" into the IP address
+/// and port.  The port is whatever follows the last colon.
+///
+fn mri_haproxy_split_address(field : &str)
+    -> Result<(String, u16), MantaError>
+{
+    let i = field.rfind(':').ok_or_else(|| mri_haproxy_invalid(
+        "client address", format!("expected \"IP:PORT\", but found \"{}\"",
+        field)))?;
+    let port = field[i + 1..].parse().map_err(|e| mri_haproxy_invalid(
+        "client address", format!("bad port in \"{}\": {}", field, e)))?;
+    Ok((String::from(&field[0..i]), port))
+}
+
+///
+/// Parses the accept date, which looks like "[26/Apr/2019:21:18:01.848]".
+/// haproxy logs this in the load balancer's local time, which is UTC for
+/// Manta zones.
+///
+fn mri_haproxy_accept_time(field : &str)
+    -> Result<chrono::DateTime<chrono::Utc>, MantaError>
+{
+    if !field.starts_with('[') || !field.ends_with(']') {
+        return Err(mri_haproxy_invalid("accept date", format!(
+            "expected \"[...]\", but found \"{}\"", field)));
+    }
+
+    let inner = &field[1..field.len() - 1];
+    match chrono::NaiveDateTime::parse_from_str(inner, "%d/%b/%Y:%H:%M:%S%.3f")
+    {
+        Ok(t) => Ok(chrono::DateTime::from_utc(t, chrono::Utc)),
+        Err(e) => Err(mri_haproxy_invalid("accept date", format!(
+            "\"{}\": {}", inner, e)))
+    }
+}
+
+///
+/// Parses a numeric field.  haproxy prefixes some values with "+" (e.g., when
+/// "option logasap" is used) and uses "-1" to denote values that were never
+/// set.  The latter is returned as `None`.
+///
+fn mri_haproxy_number(field : &str, label : &str)
+    -> Result<Option<i64>, MantaError>
+{
+    let value : i64 = field.trim_start_matches('+').parse().map_err(
+        |e| mri_haproxy_invalid(label, format!("bad number \"{}\": {}",
+        field, e)))?;
+    if value == -1 {
+        Ok(None)
+    } else if value < 0 {
+        Err(mri_haproxy_invalid(label, format!(
+            "unexpected negative value: {}", value)))
+    } else {
+        Ok(Some(value))
+    }
+}
+
+///
+/// Parses a group of slash-separated numbers (e.g., "4/0/1/155/276").
+///
+fn mri_haproxy_counters(field : &str, count : usize, label : &str)
+    -> Result<Vec<Option<i64>>, MantaError>
+{
+    let parts : Vec<&str> = field.split('/').collect();
+    if parts.len() != count {
+        return Err(mri_haproxy_invalid(label, format!(
+            "expected {} values, but found \"{}\"", count, field)));
+    }
+
+    parts.iter().map(|p| mri_haproxy_number(p, label)).collect()
+}
+
+fn mri_haproxy_counter(value : Option<i64>, label : &str)
+    -> Result<u32, MantaError>
+{
+    match value {
+        Some(n) if n <= i64::from(u32::MAX) => Ok(n as u32),
+        Some(n) => Err(mri_haproxy_invalid(label, format!(
+            "counter value too large: {}", n))),
+        None => Err(mri_haproxy_invalid(label, String::from(
+            "unexpected counter value \"-1\"")))
+    }
+}
+
+fn mri_haproxy_syntax(message : &str)
+    -> MantaError
+{
+    return MantaError::new(MantaErrorKind::Syntax(String::from(message)));
+}
+
+fn mri_haproxy_invalid(label : &str, message : String)
+    -> MantaError
+{
+    return MantaError::new(MantaErrorKind::InvalidValue(String::from(label),
+        message));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    const SAMPLE : &str = "2019-04-26T21:18:02+00:00 \
+        49a3d111-c7a3-478a-9d9c-8ec85a0f64da haproxy[664855]: \
+        ::ffff:172.20.5.18:64853 [26/Apr/2019:21:18:01.848] https \
+        secure_api/be2 4/0/1/155/276 200 405 - - ---- 17/1/17/5/0 0/0 \
+        \"GET /dap/public?limit=1024 HTTP/1.1\"";
+
+    fn ms(n : i64)
+        -> chrono::Duration
+    {
+        return chrono::Duration::milliseconds(n);
+    }
+
+    /// Returns `SAMPLE` with `from` replaced by `to`.
+    fn sample_with(from : &str, to : &str)
+        -> String
+    {
+        assert!(SAMPLE.contains(from));
+        return SAMPLE.replacen(from, to, 1);
+    }
+
+    fn accepted()
+        -> chrono::DateTime<chrono::Utc>
+    {
+        return chrono::Utc.ymd(2019, 4, 26).and_hms_milli(21, 18, 1, 848);
+    }
+
+    #[test]
+    fn entry()
+    {
+        let entry = mri_haproxy_entry(SAMPLE).unwrap();
+        assert_eq!(entry.hle_syslog_time, "2019-04-26T21:18:02+00:00");
+        assert_eq!(entry.hle_hostname, "49a3d111-c7a3-478a-9d9c-8ec85a0f64da");
+        assert_eq!(entry.hle_pid, "664855");
+        assert_eq!(entry.hle_client_ip, "::ffff:172.20.5.18");
+        assert_eq!(entry.hle_client_port, 64853);
+        assert_eq!(entry.hle_accept_time, accepted());
+        assert_eq!(entry.hle_frontend, "https");
+        assert_eq!(entry.hle_backend, "secure_api");
+        assert_eq!(entry.hle_server, "be2");
+        assert_eq!(entry.hle_time_request, Some(ms(4)));
+        assert_eq!(entry.hle_time_queued, Some(ms(0)));
+        assert_eq!(entry.hle_time_connect, Some(ms(1)));
+        assert_eq!(entry.hle_time_response, Some(ms(155)));
+        assert_eq!(entry.hle_time_total, ms(276));
+        assert_eq!(entry.wall_end(), accepted() + ms(276));
+        assert_eq!(entry.hle_status_code, Some(200));
+        assert_eq!(entry.hle_bytes_read, 405);
+        assert_eq!(entry.hle_termination_state, "----");
+        assert_eq!((entry.hle_conn_active, entry.hle_conn_frontend,
+            entry.hle_conn_backend, entry.hle_conn_server, entry.hle_retries),
+            (17, 1, 17, 5, 0));
+        assert_eq!((entry.hle_queue_server, entry.hle_queue_backend), (0, 0));
+        assert_eq!(entry.hle_request_line,
+            "GET /dap/public?limit=1024 HTTP/1.1");
+    }
+
+    ///
+    /// Timers for phases that never happened are "-1", but the total time is
+    /// always required.
+    ///
+    #[test]
+    fn entry_unset_timers()
+    {
+        let entry = mri_haproxy_entry(&sample_with("4/0/1/155/276",
+            "4/-1/-1/-1/+276")).unwrap();
+        assert_eq!(entry.hle_time_request, Some(ms(4)));
+        assert_eq!(entry.hle_time_queued, None);
+        assert_eq!(entry.hle_time_connect, None);
+        assert_eq!(entry.hle_time_response, None);
+        assert_eq!(entry.hle_time_total, ms(276));
+
+        let error = mri_haproxy_entry(&sample_with("4/0/1/155/276",
+            "4/0/1/155/-1")).unwrap_err();
+        assert_eq!(error.to_string(), "field \"Tq/Tw/Tc/Tr/Tt\": total time \
+            (\"Tt\") is missing");
+        let error = mri_haproxy_entry(&sample_with("4/0/1/155/276",
+            "4/0/1/-2/276")).unwrap_err();
+        assert_eq!(error.to_string(), "field \"Tq/Tw/Tc/Tr/Tt\": unexpected \
+            negative value: -2");
+    }
+
+    #[test]
+    fn entry_bad_accept_date()
+    {
+        for date in [ "26/Apr/2019:21:18:01.848", "[26/Apr/2019:21:18:01.848",
+            "[26/Foo/2019:21:18:01.848]", "[31/Apr/2019:21:18:01.848]",
+            "[26/Apr/2019]" ].iter() {
+            let line = sample_with("[26/Apr/2019:21:18:01.848]", date);
+            let error = mri_haproxy_entry(&line).unwrap_err();
+            assert!(error.to_string().starts_with("field \"accept date\": "),
+                "{}: {}", date, error);
+        }
+    }
+
+    ///
+    /// Absurdly long timers are rejected rather than overflowing when we work
+    /// out when the request ended.
+    ///
+    #[test]
+    fn entry_huge_timers()
+    {
+        let error = mri_haproxy_entry(&sample_with("4/0/1/155/276",
+            "4/0/1/155/9000000000000000")).unwrap_err();
+        assert_eq!(error.to_string(), "field \"Tq/Tw/Tc/Tr/Tt\": timer value \
+            too large: 9000000000000000 ms");
+        let error = mri_haproxy_entry(&sample_with("4/0/1/155/276",
+            "86400001/0/1/155/276")).unwrap_err();
+        assert_eq!(error.to_string(), "field \"Tq/Tw/Tc/Tr/Tt\": timer value \
+            too large: 86400001 ms");
+        assert!(mri_haproxy_entry(&sample_with("4/0/1/155/276",
+            "4/0/1/155/86400000")).is_ok());
+
+        let line = sample_with("26/Apr/2019:21:18:01.848",
+            "31/Dec/+262143:23:59:59.000");
+        assert!(mri_haproxy_entry(&line).is_ok());
+        let line = line.replace("4/0/1/155/276", "4/0/1/155/1000");
+        let error = mri_haproxy_entry(&line).unwrap_err();
+        assert_eq!(error.to_string(), "field \"accept date\": request ends \
+            too far in the future: [31/Dec/+262143:23:59:59.000]");
+    }
+
+    #[test]
+    fn matching()
+    {
+        let entry = mri_haproxy_entry(SAMPLE).unwrap();
+        let entries = vec![ entry.clone() ];
+        let url = "/dap/public?limit=1024";
+        let find = |method : &str, url : &str, start| mri_haproxy_match(
+            &entries, method, url, &start);
+
+        // Muskie may start any time haproxy was working on the request, with
+        // a second of slop on either side.
+        assert_eq!(find("GET", url, accepted() + ms(100)), Some(entry.clone()));
+        assert_eq!(find("GET", url, accepted() - ms(1000)),
+            Some(entry.clone()));
+        assert_eq!(find("GET", url, accepted() + ms(1276)),
+            Some(entry.clone()));
+        assert_eq!(find("GET", url, accepted() - ms(1001)), None);
+        assert_eq!(find("GET", url, accepted() + ms(1277)), None);
+
+        assert_eq!(find("PUT", url, accepted() + ms(100)), None);
+        assert_eq!(find("GET", "/dap/public", accepted() + ms(100)), None);
+
+        // Of several matching entries, the one accepted closest to when Muskie
+        // started wins.
+        let later = mri_haproxy_entry(&sample_with("21:18:01.848",
+            "21:18:02.048")).unwrap();
+        let entries = vec![ entry.clone(), later.clone() ];
+        assert_eq!(mri_haproxy_match(&entries, "GET", url,
+            &(accepted() + ms(50))), Some(entry));
+        assert_eq!(mri_haproxy_match(&entries, "GET", url,
+            &(accepted() + ms(180))), Some(later));
+    }
+}
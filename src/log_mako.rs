@@ -0,0 +1,584 @@
+/*
+ * src/log_mako.rs: Mako (nginx) access log format parser
+ *
+ * Mako's nginx logs one line per request using a variant of the "combined"
+ * format with the request latency appended:
+ *
+ *   $remote_addr - $remote_user [$time_local] "$request" $status
+ *       $body_bytes_sent $request_time "$http_referer" "$http_user_agent"
+ *       "$http_x_request_id" "$host" $upstream_response_time
+ *
+ * (This is all one line in the actual log.)  Older Mako configurations stop
+ * after $request_time or after the user agent, so every field after
+ * $request_time is optional.
+ */
+
+use std::io::BufRead;
+
+use super::error::MantaError;
+use super::error::MantaErrorKind;
+use super::log_common::mri_open_file;
+use super::log_common::LogLineReader;
+use super::log_muskie::MuskieAuditSharkContacted;
+
+/*
+ * Given a file containing Mako access log entries (one per line), return an
+ * iterator over the entries in the file.  The file is read as the iterator is
+ * consumed.
+ */
+pub fn mri_parse_mako_file(filename : &String)
+    -> Result<impl Iterator<Item = Result<MakoLogEntry, MantaError>>,
+    MantaError>
+{
+    Ok(mri_mako_entries(mri_open_file(filename)?))
+}
+
+/*
+ * Given a reader over lines of a Mako access log, return an iterator over the
+ * entries in the log.  Blank lines are skipped.
+ */
+pub fn mri_mako_entries<R : BufRead>(lines : LogLineReader<R>)
+    -> impl Iterator<Item = Result<MakoLogEntry, MantaError>>
+{
+    let name = String::from(lines.name());
+    lines.filter_map(move |line| match line {
+        Err(e) => Some(Err(e)),
+        Ok(ref l) if l.ll_text.trim().is_empty() => None,
+        Ok(l) => Some(mri_mako_entry(&l.ll_text).map_err(
+            |e| e.in_input(&name).at_line(l.ll_lineno)))
+    })
+}
+
+///
+/// A MakoLogEntry represents one parsed Mako access log line.  Fields that
+/// nginx logged as "-" are represented as `None`.
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MakoLogEntry {
+    #[serde(rename = "remote_address")]     pub mke_remote_address : String,
+    #[serde(rename = "remote_user")]
+    pub mke_remote_user : Option<String>,
+
+    ///
+    /// nginx logs $time_local when the request completes, and only to the
+    /// second.
+    ///
+    #[serde(rename = "time",
+        serialize_with = "super::json::mri_json_time")]
+    pub mke_time : chrono::DateTime<chrono::Utc>,
+
+    #[serde(rename = "method")]             pub mke_method : String,
+    #[serde(rename = "path")]               pub mke_path : String,
+    #[serde(rename = "http_version")]       pub mke_http_version : String,
+    #[serde(rename = "status_code")]        pub mke_status_code : u16,
+    #[serde(rename = "bytes_sent")]         pub mke_bytes_sent : u64,
+    #[serde(rename = "request_time_ms",
+        serialize_with = "super::json::mri_json_duration")]
+    pub mke_request_time : chrono::Duration,
+
+    #[serde(rename = "referer")]            pub mke_referer : Option<String>,
+    #[serde(rename = "user_agent")]         pub mke_user_agent : Option<String>,
+    #[serde(rename = "request_id")]         pub mke_request_id : Option<String>,
+    #[serde(rename = "host")]               pub mke_host : Option<String>,
+    #[serde(rename = "upstream_time_ms",
+        serialize_with = "super::json::mri_json_duration_opt")]
+    pub mke_upstream_time : Option<chrono::Duration>,
+}
+
+impl MakoLogEntry {
+    ///
+    /// Returns the object id for this request, which is the last component of
+    /// the request path ("/$owner/$objectid").
+    ///
+    pub fn objectid(&self)
+        -> &str
+    {
+        let path = match self.mke_path.find('?') {
+            Some(i) => &self.mke_path[0..i],
+            None => &self.mke_path
+        };
+
+        return path.rsplit('/').next().unwrap_or(path);
+    }
+
+    ///
+    /// Returns whether the storage node reported success for this request.
+    ///
+    pub fn success(&self)
+        -> bool
+    {
+        return self.mke_status_code >= 200 && self.mke_status_code < 300;
+    }
+}
+
+///
+/// Given one line from a Mako access log, parse it into a MakoLogEntry.
+///
+pub fn mri_mako_entry(line : &str)
+    -> Result<MakoLogEntry, MantaError>
+{
+    let fields = mri_mako_tokens(line)?;
+    if fields.len() < 8 {
+        return Err(MantaError::new(MantaErrorKind::Syntax(format!(
+            "expected at least 8 fields, but found {}", fields.len()))));
+    }
+
+    let optional = |i : usize| -> Option<String> {
+        match fields.get(i) {
+            Some(f) if f != "-" && !f.is_empty() => Some(f.clone()),
+            _ => None
+        }
+    };
+
+    let time = match chrono::DateTime::parse_from_str(&fields[3],
+        "%d/%b/%Y:%H:%M:%S %z") {
+        Ok(t) => t.with_timezone(&chrono::Utc),
+        Err(e) => return Err(mri_mako_invalid("time", format!("\"{}\": {}",
+            fields[3], e)))
+    };
+
+    let request : Vec<&str> = fields[4].split(' ').collect();
+    if request.len() != 3 {
+        return Err(mri_mako_invalid("request", format!("expected \"METHOD \
+            PATH VERSION\", but found \"{}\"", fields[4])));
+    }
+
+    let status_code = fields[5].parse().map_err(|e| mri_mako_invalid("status",
+        format!("bad number \"{}\": {}", fields[5], e)))?;
+    let bytes_sent = fields[6].parse().map_err(|e| mri_mako_invalid(
+        "body bytes sent", format!("bad number \"{}\": {}", fields[6], e)))?;
+    let request_time = mri_mako_seconds(&fields[7]).ok_or_else(||
+        mri_mako_invalid("request time", format!("bad value \"{}\"",
+        fields[7])))?;
+    if time.checked_sub_signed(request_time).is_none() {
+        return Err(mri_mako_invalid("time", format!("request starts too far \
+            in the past: \"{}\"", fields[3])));
+    }
+
+    let upstream_time = match optional(12) {
+        None => None,
+        Some(ref f) => Some(mri_mako_seconds(f).ok_or_else(||
+            mri_mako_invalid("upstream response time", format!(
+            "bad value \"{}\"", f)))?)
+    };
+
+    Ok(MakoLogEntry {
+        mke_remote_address : fields[0].clone(),
+        mke_remote_user : optional(2),
+        mke_time : time,
+        mke_method : String::from(request[0]),
+        mke_path : String::from(request[1]),
+        mke_http_version : String::from(request[2]),
+        mke_status_code : status_code,
+        mke_bytes_sent : bytes_sent,
+        mke_request_time : request_time,
+        mke_referer : optional(8),
+        mke_user_agent : optional(9),
+        mke_request_id : optional(10),
+        mke_host : optional(11),
+        mke_upstream_time : upstream_time
+    })
+}
+
+///
+/// Splits an nginx access log line into fields.  Fields are separated by
+/// spaces, except that "[...]" and "\"...\"" each delimit a single field that
+/// may contain spaces.  nginx escapes quotes inside quoted values, so we need
+/// not worry about embedded delimiters.
+///
+fn mri_mako_tokens(line : &str)
+    -> Result<Vec<String>, MantaError>
+{
+    let mut tokens = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == ' ' {
+            continue;
+        }
+
+        let terminator = match c {
+            '"' => Some('"'),
+            '[' => Some(']'),
+            _ => None
+        };
+
+        let mut token = String::new();
+        match terminator {
+            Some(term) => {
+                loop {
+                    match chars.next() {
+                        Some(c) if c == term => break,
+                        Some(c) => token.push(c),
+                        None => return Err(MantaError::new(
+                            MantaErrorKind::Syntax(format!("unterminated \
+                            field (expected '{}')", term))))
+                    }
+                }
+            },
+            None => {
+                token.push(c);
+                while let Some(&c) = chars.peek() {
+                    if c == ' ' {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+            }
+        }
+
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+fn mri_mako_invalid(label : &str, message : String)
+    -> MantaError
+{
+    return MantaError::new(MantaErrorKind::InvalidValue(String::from(label),
+        message));
+}
+
+///
+/// Longest duration (in seconds) that we accept in a Mako access log entry:
+/// one day.  Longer values are almost certainly corrupt, and one long enough
+/// would put the start of the request out of the range of times we can
+/// represent.
+///
+const MRI_MAKO_SECONDS_MAX : f64 = 24.0 * 60.0 * 60.0;
+
+///
+/// Parses an nginx duration in seconds with millisecond resolution (e.g.,
+/// "0.003").  If several upstream servers were contacted, nginx logs a
+/// comma-separated list, and we report the sum.  Returns `None` if any part is
+/// not a number of seconds between 0 and `MRI_MAKO_SECONDS_MAX`, or the sum
+/// exceeds that.
+///
+fn mri_mako_seconds(field : &str)
+    -> Option<chrono::Duration>
+{
+    let mut total = chrono::Duration::milliseconds(0);
+
+    for part in field.split(',') {
+        let secs : f64 = part.trim().parse().ok()?;
+        if !(0.0..=MRI_MAKO_SECONDS_MAX).contains(&secs) {
+            return None;
+        }
+        total = total.checked_add(&chrono::Duration::milliseconds(
+            (secs * 1000.0).round() as i64))?;
+    }
+
+    if total > chrono::Duration::milliseconds(
+        (MRI_MAKO_SECONDS_MAX * 1000.0) as i64) {
+        return None;
+    }
+
+    Some(total)
+}
+
+///
+/// Returns whether `entry` could describe a shark request made for the Muskie
+/// request with request id `request_id` on the object with id `objectid`.
+/// The entry's path must refer to the object, and if both Mako and Muskie
+/// logged a request id, they must agree.  If we don't know the object id
+/// (e.g., because the request failed before Muskie looked up the object's
+/// metadata), only the request id can tie the entry to the request, so the
+/// entry must have it.
+///
+pub fn mri_mako_related(entry : &MakoLogEntry, objectid : Option<&String>,
+    request_id : Option<&str>)
+    -> bool
+{
+    if let (Some(theirs), Some(ours)) =
+        (entry.mke_request_id.as_deref(), request_id) {
+        if theirs != ours {
+            return false;
+        }
+    }
+
+    return match objectid {
+        Some(objectid) => entry.objectid() == objectid,
+        None => request_id.is_some() &&
+            entry.mke_request_id.as_deref() == request_id
+    };
+}
+
+///
+/// Given the sharks contacted by Muskie for a request and the Mako log entries
+/// we've been given, returns the Mako entry that most likely describes each
+/// shark request.  The result has one element per shark in `sharks`, in the
+/// same order.
+///
+/// An entry matches a shark when it's related to the request (see
+/// `mri_mako_related()`), when the storage id agrees (if Mako logged it), and
+/// when the entry's completion time falls within the window during
+/// which Muskie was talking to that shark.  Since nginx only logs to the
+/// second, we allow a second of slop on either side.  Each entry is used at
+/// most once, and when several entries match, we pick the one that finished
+/// closest to when Muskie says the shark request finished.
+///
+pub fn mri_mako_match(sharks : &[MuskieAuditSharkContacted],
+    objectid : Option<&String>, request_id : Option<&str>,
+    muskie_end : &chrono::DateTime<chrono::Utc>, entries : &[MakoLogEntry])
+    -> Vec<Option<MakoLogEntry>>
+{
+    let slop = chrono::Duration::seconds(1);
+    let mut used = vec![false; entries.len()];
+
+    sharks.iter().map(|shark| {
+        let window_start = shark.mai_shark_time_start - slop;
+        let expected_end = match shark.mai_shark_latency_total {
+            Some(total) => shark.mai_shark_time_start + total,
+            None => *muskie_end
+        };
+        let window_end = expected_end + slop;
+
+        let mut best : Option<(usize, chrono::Duration)> = None;
+        for (i, entry) in entries.iter().enumerate() {
+            if used[i] {
+                continue;
+            }
+
+            if !mri_mako_related(entry, objectid, request_id) {
+                continue;
+            }
+
+            if let Some(ref host) = entry.mke_host {
+                let hostname = host.split(':').next().unwrap_or(host);
+                if hostname != shark.mai_shark_storid {
+                    continue;
+                }
+            }
+
+            if entry.mke_time < window_start || entry.mke_time > window_end {
+                continue;
+            }
+
+            let distance = (entry.mke_time - expected_end).num_milliseconds().
+                abs();
+            let distance = chrono::Duration::milliseconds(distance);
+            if best.is_none_or(|(_, d)| distance < d) {
+                best = Some((i, distance));
+            }
+        }
+
+        best.map(|(i, _)| {
+            used[i] = true;
+            entries[i].clone()
+        })
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    const OBJECTID : &str = "97c40f30-ee7e-c398-a5ae-e855c84a37c0";
+    const REQUEST_ID : &str = "ec5d32fe-5ff8-43ae-a152-45fd1005afff";
+    const STORID : &str = "1.stor.staging.joyent.us";
+
+    /// Returns a log line for a GET of `objectid` that completed at `time`.
+    fn line(objectid : &str, time : &str, request_id : &str, host : &str)
+        -> String
+    {
+        return format!("127.0.0.1 - - [09/May/2019:{} +0000] \
+            \"GET /bc8cd146-fecb-11e1-bd8a-bb6f54b49808/{} HTTP/1.1\" 200 \
+            1074069384 148.356 \"-\" \"-\" \"{}\" \"{}\" -", time, objectid,
+            request_id, host);
+    }
+
+    fn entry(objectid : &str, time : &str, request_id : &str, host : &str)
+        -> MakoLogEntry
+    {
+        return mri_mako_entry(&line(objectid, time, request_id, host)).
+            unwrap();
+    }
+
+    ///
+    /// Returns a shark that Muskie says it used from 21:34:23.624 to
+    /// 21:36:51.979.
+    ///
+    fn shark()
+        -> MuskieAuditSharkContacted
+    {
+        return MuskieAuditSharkContacted {
+            mai_shark_storid : String::from(STORID),
+            mai_shark_success : true,
+            mai_shark_time_start : chrono::Utc.ymd(2019, 5, 9).and_hms_milli(
+                21, 34, 23, 624),
+            mai_shark_latency_ttfb : Some(chrono::Duration::milliseconds(4)),
+            mai_shark_latency_total : Some(chrono::Duration::milliseconds(
+                148355))
+        };
+    }
+
+    fn find(entries : &[MakoLogEntry], objectid : Option<&str>)
+        -> Option<MakoLogEntry>
+    {
+        let objectid = objectid.map(String::from);
+        let muskie_end = chrono::Utc.ymd(2019, 5, 9).and_hms_milli(
+            21, 36, 51, 982);
+        let mut matched = mri_mako_match(&[ shark() ], objectid.as_ref(),
+            Some(REQUEST_ID), &muskie_end, entries);
+        assert_eq!(matched.len(), 1);
+        return matched.pop().unwrap();
+    }
+
+    #[test]
+    fn parse_full()
+    {
+        let entry = entry(OBJECTID, "21:36:51", REQUEST_ID, "1.stor:80");
+        assert_eq!(entry.mke_time, chrono::Utc.ymd(2019, 5, 9).and_hms(
+            21, 36, 51));
+        assert_eq!(entry.mke_method, "GET");
+        assert_eq!(entry.objectid(), OBJECTID);
+        assert_eq!(entry.mke_status_code, 200);
+        assert_eq!(entry.mke_request_time,
+            chrono::Duration::milliseconds(148356));
+        assert_eq!(entry.mke_referer, None);
+        assert_eq!(entry.mke_request_id.as_deref(), Some(REQUEST_ID));
+        assert_eq!(entry.mke_host.as_deref(), Some("1.stor:80"));
+        assert_eq!(entry.mke_upstream_time, None);
+        assert!(entry.success());
+    }
+
+    #[test]
+    fn parse_short()
+    {
+        let entry = mri_mako_entry("10.0.0.1 - - [09/May/2019:21:36:51 -0700] \
+            \"PUT /owner/objectid?x=1 HTTP/1.1\" 204 0 0.003,0.002").unwrap();
+        assert_eq!(entry.mke_time, chrono::Utc.ymd(2019, 5, 10).and_hms(
+            4, 36, 51));
+        assert_eq!(entry.objectid(), "objectid");
+        assert_eq!(entry.mke_request_time, chrono::Duration::milliseconds(5));
+        assert_eq!(entry.mke_user_agent, None);
+        assert_eq!(entry.mke_request_id, None);
+    }
+
+    #[test]
+    fn parse_invalid()
+    {
+        assert!(mri_mako_entry("10.0.0.1 - - [09/May/2019:21:36:51 +0000] \
+            \"GET / HTTP/1.1\" 200").is_err());
+        assert!(mri_mako_entry("10.0.0.1 - - [09/May/2019:21:36:51 +0000 \
+            \"GET / HTTP/1.1\" 200 0 0.001").is_err());
+        assert!(mri_mako_entry("10.0.0.1 - - [yesterday] \"GET / HTTP/1.1\" \
+            200 0 0.001").is_err());
+        assert!(mri_mako_entry("10.0.0.1 - - [09/May/2019:21:36:51 +0000] \
+            \"GET / HTTP/1.1\" 200 0 -1").is_err());
+    }
+
+    ///
+    /// Durations that aren't finite, or are longer than a day, are rejected
+    /// rather than overflowing when we work out when the request started.
+    ///
+    #[test]
+    fn parse_out_of_range()
+    {
+        let parse = |time : &str, secs : &str| mri_mako_entry(&format!(
+            "10.0.0.1 - - [{}] \"GET / HTTP/1.1\" 200 0 {}", time, secs));
+        let time = "09/May/2019:21:36:51 +0000";
+
+        for secs in [ "inf", "-inf", "NaN", "1e15", "86400.001",
+            "86400,0.001" ].iter() {
+            let error = parse(time, secs).unwrap_err();
+            assert_eq!(error.to_string(), format!("field \"request time\": \
+                bad value \"{}\"", secs));
+        }
+
+        assert_eq!(parse(time, "86400").unwrap().mke_request_time,
+            chrono::Duration::days(1));
+        assert_eq!(parse(time, "43200,43200").unwrap().mke_request_time,
+            chrono::Duration::days(1));
+
+        let error = mri_mako_entry(&format!("10.0.0.1 - - [{}] \
+            \"GET / HTTP/1.1\" 200 0 0.001 \"-\" \"-\" \"-\" \"-\" inf",
+            time)).unwrap_err();
+        assert_eq!(error.to_string(), "field \"upstream response time\": \
+            bad value \"inf\"");
+
+        let early = "01/Jan/-262144:00:00:00 +0000";
+        assert!(parse(early, "0").is_ok());
+        let error = parse(early, "1").unwrap_err();
+        assert_eq!(error.to_string(), format!("field \"time\": request starts \
+            too far in the past: \"{}\"", early));
+    }
+
+    #[test]
+    fn match_shark()
+    {
+        let entries = [
+            entry("5d7b3c5e-2a44-cb3c-e4a1-d7c04d4d2b11", "21:36:51",
+                "0b1c9e3e-42a8-4bb4-8c31-1a2c3c2e7d0a", STORID),
+            entry(OBJECTID, "21:36:51", REQUEST_ID, STORID),
+        ];
+        assert_eq!(find(&entries, Some(OBJECTID)), Some(entries[1].clone()));
+    }
+
+    #[test]
+    fn match_host_mismatch()
+    {
+        let entries = [
+            entry(OBJECTID, "21:36:51", REQUEST_ID, "2.stor.staging.joyent.us")
+        ];
+        assert_eq!(find(&entries, Some(OBJECTID)), None);
+
+        let entries = [
+            entry(OBJECTID, "21:36:51", REQUEST_ID,
+                "1.stor.staging.joyent.us:80")
+        ];
+        assert_eq!(find(&entries, Some(OBJECTID)), Some(entries[0].clone()));
+    }
+
+    ///
+    /// Muskie finished with the shark at 21:36:51.979, so allowing a second of
+    /// slop, the entry must have been logged no later than 21:36:52.979 (and,
+    /// since nginx logs to the second, at 21:36:52).
+    ///
+    #[test]
+    fn match_slop()
+    {
+        let entries = [ entry(OBJECTID, "21:36:52", REQUEST_ID, STORID) ];
+        assert_eq!(find(&entries, Some(OBJECTID)), Some(entries[0].clone()));
+
+        let entries = [ entry(OBJECTID, "21:36:53", REQUEST_ID, STORID) ];
+        assert_eq!(find(&entries, Some(OBJECTID)), None);
+
+        let entries = [ entry(OBJECTID, "21:34:22", REQUEST_ID, STORID) ];
+        assert_eq!(find(&entries, Some(OBJECTID)), None);
+    }
+
+    #[test]
+    fn match_closest()
+    {
+        let entries = [
+            entry(OBJECTID, "21:36:51", REQUEST_ID, STORID),
+            entry(OBJECTID, "21:36:52", REQUEST_ID, STORID),
+        ];
+        assert_eq!(find(&entries, Some(OBJECTID)), Some(entries[1].clone()));
+    }
+
+    ///
+    /// Without the object id, an entry must carry the request's id to match.
+    ///
+    #[test]
+    fn match_no_objectid()
+    {
+        let entries = [ entry(OBJECTID, "21:36:51", REQUEST_ID, STORID) ];
+        assert_eq!(find(&entries, None), Some(entries[0].clone()));
+
+        let entries = [
+            entry(OBJECTID, "21:36:51", "0b1c9e3e-42a8-4bb4-8c31-1a2c3c2e7d0a",
+                STORID),
+            entry(OBJECTID, "21:36:51", "-", STORID)
+        ];
+        assert_eq!(find(&entries, None), None);
+        assert!(!mri_mako_related(&entries[1], None, Some(REQUEST_ID)));
+        assert!(!mri_mako_related(&entries[1], None, None));
+        assert!(mri_mako_related(&entries[1], Some(&String::from(OBJECTID)),
+            Some(REQUEST_ID)));
+    }
+}
@@ -5,39 +5,214 @@
  */
 
 use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::fmt;
 
 use serde_json::Map;
 
-use super::mri_read_file;
+use std::io::BufRead;
+
+use super::error::mri_json_error;
+use super::error::MantaError;
+use super::error::MantaErrorKind;
+use super::log_common::mri_json_record_start;
+use super::log_common::mri_open_file;
+use super::log_common::LogLineReader;
 
 /*
- * Given a file containing a single Muskie log entry, return a MuskieLog
- * describing the file's contents.
+ * Given a file containing Muskie log entries, return a MuskieLog that iterates
+ * over the records in the file.  The file is read as the iterator is consumed.
  */
 pub fn mri_parse_muskie_file(filename : &String)
-    -> Result<MuskieLog, String>
+    -> Result<MuskieLog<impl BufRead>, MantaError>
 {
-    let buffer = mri_read_file(filename)?;
-    let parsed : Result<MuskieLogEntry, serde_json::Error> =
-        serde_json::from_str(&buffer);
-    if let Err(e) = parsed {
-        return Err(format!("parse \"{}\": {}", &filename, e));
+    Ok(MuskieLog::new(mri_open_file(filename)?))
+}
+
+/*
+ * A MuskieLog iterates over the records in a Muskie log.  This is usually a
+ * whole bunyan log (one record per line), but a record may also span several
+ * lines (e.g., if it was pretty-printed).  Audit entries are parsed into
+ * MuskieLogEntry objects.  Other records can take nearly any shape, so we only
+ * pick out the few fields we need to place them on a request's timeline, and
+ * we skip those that don't have them (e.g., records not associated with any
+ * request).
+ */
+pub struct MuskieLog<R : BufRead> {
+    muskie_lines : LogLineReader<R>,
+    muskie_record_line : u64
+}
+
+impl<R : BufRead> MuskieLog<R> {
+    pub fn new(lines : LogLineReader<R>)
+        -> MuskieLog<R>
+    {
+        return MuskieLog { muskie_lines : lines, muskie_record_line : 0 }
     }
 
-    Ok(MuskieLog {
-        muskie_filename: filename.clone(),
-        muskie_entries: vec!(parsed.unwrap())
-    })
+    /// Returns the name of the log being read.
+    pub fn name(&self)
+        -> &str
+    {
+        return self.muskie_lines.name();
+    }
+
+    ///
+    /// Returns the line number where the record most recently returned by the
+    /// iterator began.  This is useful for reporting problems found in the
+    /// record after it was parsed.
+    ///
+    pub fn record_line(&self)
+        -> u64
+    {
+        return self.muskie_record_line;
+    }
+
+    ///
+    /// Reads the next complete JSON record, which begins on the next non-blank
+    /// line.  Returns the record and the line number where it began.  If the
+    /// record is invalid, returns an error, and the next call picks up with the
+    /// record after it.
+    ///
+    fn next_record(&mut self)
+        -> Option<Result<(u64, serde_json::Value), MantaError>>
+    {
+        let first = loop {
+            match self.muskie_lines.next()? {
+                Err(e) => return Some(Err(e)),
+                Ok(ref l) if l.ll_text.trim().is_empty() => continue,
+                Ok(l) => break l
+            }
+        };
+
+        let lineno = first.ll_lineno;
+        let mut text = first.ll_text;
+        loop {
+            let error = match serde_json::from_str(&text) {
+                Ok(record) => return Some(Ok((lineno, record))),
+                Err(e) => e
+            };
+
+            //
+            // If the record is merely incomplete, it may continue onto the
+            // next line.  We bound the total size of the record the same way
+            // the reader bounds the size of each line.
+            //
+            let max = self.muskie_lines.max_line_bytes();
+            let next = if error.is_eof() && (text.len() as u64) < max {
+                self.muskie_lines.next()
+            } else {
+                None
+            };
+
+            match next {
+                Some(Ok(ref l)) if !mri_json_record_start(&l.ll_text) => {
+                    text.push('\n');
+                    text.push_str(&l.ll_text);
+                    continue;
+                },
+                Some(Ok(l)) => self.muskie_lines.unread(vec![l]),
+                Some(Err(e)) => return Some(Err(e)),
+                None => ()
+            }
+
+            return Some(Err(MantaError::new(MantaErrorKind::JsonSyntax(
+                error)).in_input(self.muskie_lines.name()).at_line(lineno)));
+        }
+    }
+}
+
+impl<R : BufRead> Iterator for MuskieLog<R> {
+    type Item = Result<MuskieLogRecord, MantaError>;
+
+    fn next(&mut self)
+        -> Option<Result<MuskieLogRecord, MantaError>>
+    {
+        loop {
+            let (lineno, record) = match self.next_record()? {
+                Ok(r) => r,
+                Err(e) => return Some(Err(e))
+            };
+            self.muskie_record_line = lineno;
+
+            if record.get("audit") != Some(&serde_json::Value::Bool(true)) {
+                match mri_muskie_event(&record) {
+                    Some(event) => return Some(Ok(MuskieLogRecord::Other(
+                        event))),
+                    None => continue
+                }
+            }
+
+            return Some(serde_path_to_error::deserialize(record).map(
+                |mle| MuskieLogRecord::Audit(Box::new(mle))).map_err(
+                |e| mri_json_error(e).in_input(self.muskie_lines.name()).
+                at_line(lineno)));
+        }
+    }
 }
 
 /*
- * A MuskieLog just identifies the filename it came from and a sequence of
- * MuskieLogEntry objects.
+ * A MuskieLogRecord is one record from a Muskie log: either an audit entry or
+ * some other record that Muskie logged while processing a request.
  */
-pub struct MuskieLog {
-    pub muskie_filename : String,
-    pub muskie_entries : Vec<MuskieLogEntry>
+#[derive(Debug, Clone, PartialEq)]
+pub enum MuskieLogRecord {
+    Audit(Box<MuskieLogEntry>),
+    Other(MuskieLogEvent),
+}
+
+///
+/// A MuskieLogEvent represents a non-audit record that Muskie logged while
+/// processing a request (e.g., about metadata lookups, shark selection, or
+/// errors on the data stream).  These are usually only logged at debug or
+/// trace level.
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MuskieLogEvent {
+    #[serde(rename = "req_id")]     pub mlv_req_id : String,
+    #[serde(rename = "time", serialize_with = "super::json::mri_json_time")]
+    pub mlv_time : chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "level")]      pub mlv_level : i16,
+    #[serde(rename = "message")]    pub mlv_message : String,
+}
+
+impl MuskieLogEvent {
+    ///
+    /// Returns the name of the bunyan level at which this record was logged.
+    ///
+    pub fn level_name(&self)
+        -> String
+    {
+        return String::from(match self.mlv_level {
+            10 => "TRACE",
+            20 => "DEBUG",
+            30 => "INFO",
+            40 => "WARN",
+            50 => "ERROR",
+            60 => "FATAL",
+            n => return format!("LEVEL {}", n)
+        });
+    }
+}
+
+///
+/// Given a non-audit bunyan record from a Muskie log, returns a MuskieLogEvent
+/// describing it, or `None` if it's missing any of the fields we need.
+///
+fn mri_muskie_event(record : &serde_json::Value)
+    -> Option<MuskieLogEvent>
+{
+    let req_id = record.get("req_id")?.as_str()?;
+    let time = record.get("time")?.as_str()?.parse().ok()?;
+    let level = i16::try_from(record.get("level")?.as_i64()?).ok()?;
+    let message = record.get("msg")?.as_str()?;
+
+    Some(MuskieLogEvent {
+        mlv_req_id : String::from(req_id),
+        mlv_time : time,
+        mlv_level : level,
+        mlv_message : String::from(message)
+    })
 }
 
 /*
@@ -122,38 +297,20 @@ pub enum MuskieLogEntryHeaderValue {
     Int(i64)
 }
 
-impl MuskieLogEntryHeaderValue {
-    // XXX We should validate and handle this better.
-    pub fn as_string(&self) -> &String {
-        match self {
-            MuskieLogEntryHeaderValue::Str(s) => s,
-            _ => panic!("header value is not a string")
-        }
-    }
-
-    // XXX We should validate and handle this better.
-    pub fn as_i64(&self) -> i64 {
-        match self {
-            MuskieLogEntryHeaderValue::Int(i64val) => *i64val,
-            // XXX This is somewhat dubious, but the problem is that Muskie logs
-            // all request headers as a string (probably since they initially
-            // came in as strings from the client) while it logs response
-            // headers that were originally numeric as numbers.  We have to deal
-            // with this.  We could do it earlier when parsing, but it's simpler
-            // for now to parse here if needed.
-            // This technically violates Rust's naming convention, where "as_"
-            // is supposed to be a "free" operation.  However, this best
-            // reflects the caller's view of this operation (namely, that we're
-            // just returning a particular representation of the header).  This
-            // would be free if we'd parsed it earlier.
-            MuskieLogEntryHeaderValue::Str(strval) => {
-                match strval.parse() {
-                    Ok(x) => x,
-                    Err(e) => panic!("header value is not a number \
-                        (parse error: {})", e)
-                }
-            }
-        }
+impl MuskieLogEntry {
+    ///
+    /// Returns the request id for this entry.  Muskie reports this in the
+    /// "x-request-id" response header, which matches the request header of
+    /// the same name when the client provided one.
+    ///
+    pub fn request_id(&self)
+        -> Option<String>
+    {
+        let from_res = self.mle_response.as_ref().and_then(|res|
+            res.mle_response_headers.get("x-request-id"));
+        let from_req = self.mle_request.as_ref().and_then(|req|
+            req.mle_req_headers.get("x-request-id"));
+        from_res.or(from_req).map(|v| v.to_string())
     }
 }
 
@@ -172,7 +329,7 @@ impl std::fmt::Display for MuskieLogEntryHeaderValue {
  * would require additionally implementing Clone, Deserialize, and PartialEq by
  * hand.
  */
-#[derive(Clone, Deserialize, PartialEq)]
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
 #[serde(untagged)]
 pub enum MuskieLogEntryTimers {
     Timers(Map<String, serde_json::Value>)
@@ -217,7 +374,7 @@ pub enum MuskieErrorValue {
     NoError(bool)
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
 pub struct MuskieErrorObject {
     #[serde(rename = "stack")]      pub mle_error_stack : String,
     #[serde(rename = "name")]       pub mle_error_name : String,
@@ -235,33 +392,171 @@ pub enum MuskieLogEntryMaybeNumeric {
     Str(String),
     Int(i64)
 }
-impl MuskieLogEntryMaybeNumeric {
-    // XXX We should validate and handle this better.
-    pub fn as_i64(&self) -> i64 {
-        match self {
-            MuskieLogEntryMaybeNumeric::Int(i64val) => *i64val,
-            // XXX This is somewhat dubious, but the problem is that Muskie logs
-            // all request headers as a string (probably since they initially
-            // came in as strings from the client) while it logs response
-            // headers that were originally numeric as numbers.  We have to deal
-            // with this.  We could do it earlier when parsing, but it's simpler
-            // for now to parse here if needed.
-            // This technically violates Rust's naming convention, where "as_"
-            // is supposed to be a "free" operation.  However, this best
-            // reflects the caller's view of this operation (namely, that we're
-            // just returning a particular representation of the header).  This
-            // would be free if we'd parsed it earlier.
-            MuskieLogEntryMaybeNumeric::Str(strval) => {
-                match strval.parse() {
-                    Ok(x) => x,
-                    Err(e) => panic!("numeric value is not a number \
-                        (parse error: {})", e)
-                }
-            }
+
+///
+/// A value from a Muskie log entry that may have been recorded as either a
+/// string or an integer.  Muskie logs all request headers as strings (since
+/// they came in that way from the client), while it logs response headers that
+/// were originally numeric as numbers.  Rather than make consumers deal with
+/// both, we normalize values when we build the audit information: every value
+/// has its textual form, and values that are valid integers (however they were
+/// recorded) also have their numeric form.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct MuskieValue {
+    mva_text : String,
+    mva_number : Option<i64>
+}
+
+impl MuskieValue {
+    pub fn from_text(text : &str)
+        -> MuskieValue
+    {
+        return MuskieValue {
+            mva_text : String::from(text),
+            mva_number : text.trim().parse().ok()
+        };
+    }
+
+    pub fn from_i64(number : i64)
+        -> MuskieValue
+    {
+        return MuskieValue {
+            mva_text : number.to_string(),
+            mva_number : Some(number)
+        };
+    }
+
+    /// Returns the value as it would appear in an HTTP header.
+    pub fn as_str(&self)
+        -> &str
+    {
+        return &self.mva_text;
+    }
+
+    /// Returns the value as an integer, if it is one.
+    pub fn as_i64(&self)
+        -> Option<i64>
+    {
+        return self.mva_number;
+    }
+}
+
+///
+/// Values are serialized as JSON numbers if they're integers written the usual
+/// way (so that, e.g., "007" stays a string) and as strings otherwise.
+///
+impl serde::Serialize for MuskieValue {
+    fn serialize<S>(&self, serializer : S) -> Result<S::Ok, S::Error>
+        where S : serde::Serializer
+    {
+        match self.mva_number {
+            Some(n) if n.to_string() == self.mva_text => {
+                serializer.serialize_i64(n)
+            },
+            _ => serializer.serialize_str(&self.mva_text)
+        }
+    }
+}
+
+impl std::fmt::Display for MuskieValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.mva_text)
+    }
+}
+
+impl From<&MuskieLogEntryHeaderValue> for MuskieValue {
+    fn from(value : &MuskieLogEntryHeaderValue) -> MuskieValue {
+        match value {
+            MuskieLogEntryHeaderValue::Str(s) => MuskieValue::from_text(s),
+            MuskieLogEntryHeaderValue::Int(n) => MuskieValue::from_i64(*n),
+        }
+    }
+}
+
+impl From<&MuskieLogEntryMaybeNumeric> for MuskieValue {
+    fn from(value : &MuskieLogEntryMaybeNumeric) -> MuskieValue {
+        match value {
+            MuskieLogEntryMaybeNumeric::Str(s) => MuskieValue::from_text(s),
+            MuskieLogEntryMaybeNumeric::Int(n) => MuskieValue::from_i64(*n),
         }
     }
 }
 
+///
+/// The request or response headers from a Muskie audit entry.  Header names are
+/// lowercase, as Muskie logs them.  The `string()` and `number()` accessors
+/// return an error identifying the header (by JSON path, e.g.,
+/// "req.headers.host") when it's missing or not of the expected type, so that
+/// callers can report the problem and carry on.
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct MuskieHeaders {
+    #[serde(skip)]
+    mhd_path : &'static str,
+    mhd_values : BTreeMap<String, MuskieValue>
+}
+
+impl MuskieHeaders {
+    ///
+    /// Normalizes raw headers from a log entry.  `path` is the JSON path of
+    /// the headers object (e.g., "req.headers").
+    ///
+    pub fn new(path : &'static str,
+        raw : &BTreeMap<String, MuskieLogEntryHeaderValue>)
+        -> MuskieHeaders
+    {
+        return MuskieHeaders {
+            mhd_path : path,
+            mhd_values : raw.iter().map(|(name, value)|
+                (name.clone(), MuskieValue::from(value))).collect()
+        };
+    }
+
+    /// Returns the JSON path of header `name` (e.g., "req.headers.host").
+    pub fn path(&self, name : &str)
+        -> String
+    {
+        return format!("{}.{}", self.mhd_path, name);
+    }
+
+    /// Returns the value of header `name`, if present.
+    pub fn get(&self, name : &str)
+        -> Option<&MuskieValue>
+    {
+        return self.mhd_values.get(name);
+    }
+
+    /// Returns whether header `name` is present.
+    pub fn contains(&self, name : &str)
+        -> bool
+    {
+        return self.mhd_values.contains_key(name);
+    }
+
+    /// Returns the value of header `name`, which must be present.
+    pub fn string(&self, name : &str)
+        -> Result<&str, MantaError>
+    {
+        match self.get(name) {
+            Some(value) => Ok(value.as_str()),
+            None => Err(MantaError::new(MantaErrorKind::MissingField(
+                self.path(name))))
+        }
+    }
+
+    /// Returns the value of header `name`, which must be an integer.
+    pub fn number(&self, name : &str)
+        -> Result<i64, MantaError>
+    {
+        let value = self.string(name)?;
+        return self.mhd_values[name].as_i64().ok_or_else(|| MantaError::new(
+            MantaErrorKind::InvalidValue(self.path(name), format!(
+            "expected an integer, but found \"{}\"", value))));
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, PartialEq)]
 pub struct MuskieLogSharkContacted {
     #[serde(rename = "shark")]           pub mle_shark_storid : String,
@@ -278,49 +573,151 @@ pub struct MuskieLogSharkContacted {
 /// A MuskieAuditInfo object collects the valid parts of a MuskieLogEntry that
 /// represent a completed request.
 ///
+#[derive(Serialize)]
 pub struct MuskieAuditInfo {
     // Bunyan fields
-    pub mai_hostname : String,
-    pub mai_pid : String,
+    #[serde(rename = "hostname")]   pub mai_hostname : String,
+    #[serde(rename = "pid")]        pub mai_pid : String,
+    #[serde(rename = "time", serialize_with = "super::json::mri_json_time")]
     pub mai_time : chrono::DateTime<chrono::Utc>,
 
     // Muskie-specific fields
-    pub mai_operation : String,
-    pub mai_route : String,
+    #[serde(rename = "operation")]  pub mai_operation : String,
+    #[serde(rename = "route")]      pub mai_route : String,
 
+    #[serde(rename = "remote_address")]
     pub mai_remote_address_logical : String,    // TODO can this be missing?
+    #[serde(rename = "billable_operation")]
     pub mai_billable_operation : String,        // TODO can this be missing?
-    pub mai_timers : MuskieLogEntryTimers,
+    #[serde(rename = "timers")]     pub mai_timers : MuskieLogEntryTimers,
 
+    #[serde(rename = "req_header_length")]
     pub mai_req_header_length : u16,
+    #[serde(rename = "req_method")]
     pub mai_req_method : String,                // TODO should be enum?
+    #[serde(rename = "req_url")]
     pub mai_req_url : String,                   // TODO should be enum?
+    #[serde(rename = "req_http_version")]
     pub mai_req_http_version : String,
+    #[serde(rename = "req_owner_uuid")]
     pub mai_req_owner_uuid : String,
-    pub mai_req_headers : BTreeMap<String, MuskieLogEntryHeaderValue>,
+    #[serde(rename = "req_headers")]
+    pub mai_req_headers : MuskieHeaders,
+    #[serde(rename = "req_caller_operator")]
     pub mai_req_caller_operator : bool,
+    #[serde(rename = "req_caller_uuid")]
     pub mai_req_caller_uuid : String,           // TODO what does this look like
+    #[serde(rename = "req_caller_login")]
     pub mai_req_caller_login : String,          // when it's missing?
 
+    #[serde(rename = "res_header_length")]
     pub mai_response_header_length : u16,
+    #[serde(rename = "res_status_code")]
     pub mai_response_status_code : u16,               // TODO parse as enum
-    pub mai_response_headers : BTreeMap<String, MuskieLogEntryHeaderValue>,
+    #[serde(rename = "res_headers")]
+    pub mai_response_headers : MuskieHeaders,
 
+    #[serde(rename = "error")]
     pub mai_error : Option<MuskieErrorObject>,
 
     // For object-related requests
-    pub mai_objectid : Option<String>,
+    #[serde(rename = "objectid")]   pub mai_objectid : Option<String>,
+    #[serde(rename = "shard_entry")]
     pub mai_shard_entry : Option<String>,
+    #[serde(rename = "shard_parent")]
     pub mai_shard_parent : Option<String>,
-    pub mai_bytes_transferred : Option<i64>,
+    #[serde(rename = "bytes_transferred")]
+    pub mai_bytes_transferred : Option<MuskieValue>,
+    #[serde(rename = "sharks_contacted")]
     pub mai_sharks_contacted : Option<Vec<MuskieAuditSharkContacted>>
 }
 
+impl MuskieAuditInfo {
+    ///
+    /// Returns the request id, which Muskie reports in the "x-request-id"
+    /// response header (and which usually came from the request header).
+    ///
+    pub fn request_id(&self)
+        -> Option<String>
+    {
+        return self.mai_response_headers.get("x-request-id").or_else(
+            || self.mai_req_headers.get("x-request-id")).map(
+            |v| v.to_string());
+    }
+
+    ///
+    /// Returns the total time that Muskie reports having spent in request
+    /// handlers.  Since the audit entry is logged when the request completes,
+    /// this tells us approximately when Muskie started processing it.  Timers
+    /// that `mri_muskie_timer()` rejects are left out, just as they're left off
+    /// the timeline.
+    ///
+    pub fn handler_elapsed(&self)
+        -> chrono::Duration
+    {
+        //
+        // Each timer is bounded, so the sum can't overflow in practice, but
+        // if it somehow did, we stop at the largest sum we could compute.
+        //
+        let mut total = chrono::Duration::zero();
+        for value in self.mai_timers.map().values() {
+            if let Some(duration) = mri_muskie_timer(value) {
+                match total.checked_add(&duration) {
+                    Some(sum) => total = sum,
+                    None => break
+                }
+            }
+        }
+
+        return total;
+    }
+
+    ///
+    /// Returns the wall-clock time when Muskie started processing this
+    /// request, as inferred from the handler timers.  (If that's before the
+    /// earliest time we can represent, this returns the time the request
+    /// completed instead.)
+    ///
+    pub fn wall_start(&self)
+        -> chrono::DateTime<chrono::Utc>
+    {
+        return self.mai_time.checked_sub_signed(self.handler_elapsed()).
+            unwrap_or(self.mai_time);
+    }
+}
+
+///
+/// Longest time (in microseconds) that we believe a single Muskie handler could
+/// take: one day.  A timer longer than this is almost certainly corrupt, and
+/// one long enough would push the start of the request out of the range of
+/// times we can represent.
+///
+pub const MRI_MUSKIE_TIMER_MAX_US : i64 = 86_400_000_000;
+
+///
+/// Given the value of one of Muskie's handler timers, returns how long the
+/// handler took, or `None` if the value isn't an integer number of
+/// microseconds between 0 and `MRI_MUSKIE_TIMER_MAX_US`.
+///
+pub fn mri_muskie_timer(value : &serde_json::Value)
+    -> Option<chrono::Duration>
+{
+    return value.as_i64().filter(|us| (0..=MRI_MUSKIE_TIMER_MAX_US).contains(
+        us)).map(chrono::Duration::microseconds);
+}
+
+#[derive(Serialize)]
 pub struct MuskieAuditSharkContacted {
-    pub mai_shark_storid : String,
-    pub mai_shark_success : bool,
+    #[serde(rename = "storid")]     pub mai_shark_storid : String,
+    #[serde(rename = "success")]    pub mai_shark_success : bool,
+    #[serde(rename = "time_start",
+        serialize_with = "super::json::mri_json_time")]
     pub mai_shark_time_start : chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "latency_ttfb_ms",
+        serialize_with = "super::json::mri_json_duration_opt")]
     pub mai_shark_latency_ttfb : Option<chrono::Duration>,
+    #[serde(rename = "latency_total_ms",
+        serialize_with = "super::json::mri_json_duration_opt")]
     pub mai_shark_latency_total : Option<chrono::Duration>,
 }
 
@@ -330,40 +727,40 @@ pub struct MuskieAuditSharkContacted {
 /// describing the request and response.  Otherwises, returns an error.
 ///
 pub fn mri_audit_entry(mle : &MuskieLogEntry)
-    -> Result<MuskieAuditInfo, String>
+    -> Result<MuskieAuditInfo, MantaError>
 {
     if mle.mle_bunyan_version != 0 {
-        return Err(format!("expected bunyan version 0, but found {}",
-            mle.mle_bunyan_version));
+        return Err(MantaError::new(MantaErrorKind::UnsupportedBunyanVersion(
+            u64::from(mle.mle_bunyan_version))));
     }
 
-    if mle.mle_audit.is_none() || !mle.mle_audit.unwrap() {
-        return Err(format!("expected audit log entry (having \"audit\": true)"));
+    if mle.mle_audit != Some(true) {
+        return Err(mri_audit_invalid("audit", String::from(
+            "expected audit log entry (having \"audit\": true)")));
     }
 
     let wall_time : chrono::DateTime<chrono::Utc> = match mle.mle_time.parse() {
         Ok(t) => t,
-        Err(e) => return Err(format!("{}", e))
+        Err(e) => return Err(mri_audit_invalid("time", format!("\"{}\": {}",
+            mle.mle_time, e)))
     };
-    let operation : &String = mle.mle_operation.as_ref().ok_or(
-        String::from("expected \"operation\" field)"))?;
-    let route : &String = mle.mle_route.as_ref().ok_or(
-        String::from("expected \"route\" field)"))?;
-    let remote_address_logical : &String =
-        mle.mle_remote_address_logical.as_ref().ok_or(
-        String::from("expected \"logicalRemoteAddress\" field)"))?;
-    let billable_operation : &String =
-        mle.mle_billable_operation.as_ref().ok_or(
-        String::from("expected \"billable_operation\" field)"))?;
-    let request : &MuskieLogEntryRequest =
-        mle.mle_request.as_ref().ok_or(
-        String::from("expected \"req\" field)"))?;
-    let response : &MuskieLogEntryResponse =
-        mle.mle_response.as_ref().ok_or(
-        String::from("expected \"res\" field)"))?;
-    let caller : &MuskieLogEntryCaller =
-        request.mle_req_caller.as_ref().ok_or(
-        String::from("expected \"req.caller\" field)"))?;
+    let operation : &String = mri_audit_required(&mle.mle_operation,
+        "operation")?;
+    let route : &String = mri_audit_required(&mle.mle_route, "route")?;
+    let remote_address_logical : &String = mri_audit_required(
+        &mle.mle_remote_address_logical, "logicalRemoteAddress")?;
+    let billable_operation : &String = mri_audit_required(
+        &mle.mle_billable_operation, "billable_operation")?;
+    let request : &MuskieLogEntryRequest = mri_audit_required(
+        &mle.mle_request, "req")?;
+    let response : &MuskieLogEntryResponse = mri_audit_required(
+        &mle.mle_response, "res")?;
+    let caller : &MuskieLogEntryCaller = mri_audit_required(
+        &request.mle_req_caller, "req.caller")?;
+    let req_header_length = mri_audit_required(
+        &mle.mle_request_header_length, "reqHeaderLength")?;
+    let res_header_length = mri_audit_required(
+        &mle.mle_response_header_length, "resHeaderLength")?;
 
     let error = match &mle.mle_error {
         None => None,
@@ -371,12 +768,13 @@ pub fn mri_audit_entry(mle : &MuskieLogEntry)
             MuskieErrorValue::Error(error_object) => Some(error_object.clone()),
             MuskieErrorValue::NoError(false) => None,
             MuskieErrorValue::NoError(true) => {
-                return Err(format!("unexpected value for error: \"true\""))
+                return Err(mri_audit_invalid("err", String::from(
+                    "unexpected value: true")));
             }
         }
     };
 
-    let sharks = mri_audit_sharks(&mle)?;
+    let sharks = mri_audit_sharks(mle)?;
 
     return Ok(MuskieAuditInfo {
         mai_hostname : mle.mle_hostname.clone(),
@@ -387,17 +785,17 @@ pub fn mri_audit_entry(mle : &MuskieLogEntry)
         mai_remote_address_logical : remote_address_logical.clone(),
         mai_billable_operation : billable_operation.clone(),
         mai_timers : request.mle_req_timers.clone(),
-        mai_req_header_length : *mle.mle_request_header_length.as_ref().ok_or(
-            String::from("expected \"reqHeaderLength\" field)"))?,
+        mai_req_header_length : *req_header_length,
         mai_req_method : request.mle_req_method.clone(),
         mai_req_url : request.mle_req_url.clone(),
         mai_req_http_version : request.mle_req_http_version.clone(),
         mai_req_owner_uuid : request.mle_req_owner.clone(),
-        mai_req_headers : request.mle_req_headers.clone(),
+        mai_req_headers : MuskieHeaders::new("req.headers",
+            &request.mle_req_headers),
         mai_response_status_code : response.mle_response_status_code,
-        mai_response_header_length : *mle.mle_response_header_length.as_ref().
-            ok_or(String::from("expected \"reqHeaderLength\" field)"))?,
-        mai_response_headers : response.mle_response_headers.clone(),
+        mai_response_header_length : *res_header_length,
+        mai_response_headers : MuskieHeaders::new("res.headers",
+            &response.mle_response_headers),
         mai_req_caller_operator : caller.mle_req_caller_groups.contains(
             &String::from("operators")),
         mai_req_caller_uuid : caller.mle_req_caller_uuid.clone(),
@@ -407,37 +805,39 @@ pub fn mri_audit_entry(mle : &MuskieLogEntry)
         mai_shard_entry : mle.mle_shard_entry.clone(),
         mai_shard_parent : mle.mle_shard_parent.clone(),
         mai_bytes_transferred : mle.mle_bytes_transferred.as_ref().map(
-            |x| x.as_i64()),
+            MuskieValue::from),
         mai_sharks_contacted : sharks
     });
 }
 
 fn mri_audit_sharks(mle : &MuskieLogEntry)
-    -> Result<Option<Vec<MuskieAuditSharkContacted>>, String>
+    -> Result<Option<Vec<MuskieAuditSharkContacted>>, MantaError>
 {
     if let Some(ref rawsharks) = mle.mle_sharks_contacted {
         let mut sharks : Vec<MuskieAuditSharkContacted> = Vec::new();
 
-        for rawshark in rawsharks {
+        for (i, rawshark) in rawsharks.iter().enumerate() {
             let success = match &rawshark.mle_shark_result {
                 None => false,
                 Some(ref result) if result == "fail" => false,
                 Some(ref result) if result == "ok" => true,
                 Some(unknown) => {
-                    return Err(format!("log entry shark contacted (\"{}\"): \
-                        expected \"result\" to be \"ok\" or \"fail\",
-                        but found \"{}\"", rawshark.mle_shark_storid, unknown));
+                    return Err(mri_audit_invalid(&format!(
+                        "sharksContacted[{}].result", i), format!(
+                        "shark \"{}\": expected \"ok\" or \"fail\", but \
+                        found \"{}\"", rawshark.mle_shark_storid, unknown)));
                 }
             };
 
             let start_time = chrono::NaiveDateTime::from_timestamp_opt(
                 (rawshark.mle_shark_time_start / 1000) as i64,
                 (1000000 * (rawshark.mle_shark_time_start % 1000)) as u32);
-            if start_time == None {
-                return Err(format!("log entry shark contacted (\"{}\"): \
-                    unsupported millisecond timestamp: \"{}\"",
-                    rawshark.mle_shark_storid,
-                    rawshark.mle_shark_time_start));
+            if start_time.is_none() {
+                return Err(mri_audit_invalid(&format!(
+                    "sharksContacted[{}]._startTime", i), format!(
+                    "shark \"{}\": unsupported millisecond timestamp: \
+                    \"{}\"", rawshark.mle_shark_storid,
+                    rawshark.mle_shark_time_start)));
             }
 
             let ttfb = rawshark.mle_shark_latency_ttfb.map(
@@ -460,3 +860,21 @@ fn mri_audit_sharks(mle : &MuskieLogEntry)
         return Ok(None);
     }
 }
+
+///
+/// Returns the value of a field that's optional in Muskie log entries generally
+/// but required in audit entries.  `path` is the field's JSON path.
+///
+fn mri_audit_required<'a, T>(value : &'a Option<T>, path : &str)
+    -> Result<&'a T, MantaError>
+{
+    return value.as_ref().ok_or_else(|| MantaError::new(
+        MantaErrorKind::MissingField(String::from(path))));
+}
+
+fn mri_audit_invalid(path : &str, message : String)
+    -> MantaError
+{
+    return MantaError::new(MantaErrorKind::InvalidValue(String::from(path),
+        message));
+}
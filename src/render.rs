@@ -0,0 +1,792 @@
+/*
+ * src/render.rs: rendering reports about a request
+ */
+
+//!
+//! # Rendering
+//!
+//! A `MantaRenderer` writes a report about a request (a `MantaRequestInfo`) to
+//! any `io::Write`: standard output, a file, a buffer to be served by some
+//! other program, and so on.  `MantaTextRenderer` produces the human-readable
+//! report, `MantaJsonRenderer` produces the JSON document described in the
+//! json module, `MantaChromeTraceRenderer` produces the Chrome trace described
+//! in the trace module, `MantaZipkinRenderer` produces the Zipkin spans
+//! described in the zipkin module, and `MantaGanttRenderer` draws the timeline
+//! as a chart (see the gantt module).
+//!
+
+use std::io;
+use std::io::Write;
+
+use super::latency::MantaLatencyBreakdown;
+use super::log_haproxy::HaproxyLogEntry;
+use super::log_mako::MakoLogEntry;
+use super::log_muskie::MuskieAuditInfo;
+use super::log_muskie::MuskieValue;
+use super::skew::MantaClockSkew;
+use super::timeline;
+use super::MantaRequestInfo;
+use super::MRI_GAP_THRESHOLD_MS;
+
+///
+/// Writes a report about a request in some format.
+///
+pub trait MantaRenderer {
+    ///
+    /// Writes the report about `mri` to `out`.  Errors are only possible from
+    /// `out` itself.
+    ///
+    fn render(&self, mri : &MantaRequestInfo, out : &mut dyn Write)
+        -> io::Result<()>;
+}
+
+///
+/// Renders the human-readable report: the request's details, a summary of
+/// where its time went, and its timelines.
+///
+pub struct MantaTextRenderer;
+
+impl MantaRenderer for MantaTextRenderer {
+    fn render(&self, mri : &MantaRequestInfo, out : &mut dyn Write)
+        -> io::Result<()>
+    {
+        return mri_dump_text(out, mri);
+    }
+}
+
+fn mri_dump_text(out : &mut dyn Write, mri : &MantaRequestInfo)
+    -> io::Result<()>
+{
+    let muskie_info = &mri.mri_muskie;
+    let remote_ip = &muskie_info.mai_remote_address_logical;
+    let dns_name = muskie_info.mai_req_headers.string("host").unwrap_or(
+        "unknown");
+    let min_duration_option = Some(chrono::Duration::milliseconds(1));
+
+    if !mri.mri_diagnostics.is_empty() {
+        writeln!(out, "WARNINGS:")?;
+        for diagnostic in mri.mri_diagnostics.items() {
+            writeln!(out, "  {}", diagnostic)?;
+        }
+        writeln!(out)?;
+    }
+
+    // TODO add: whether client requested keep-alive and whether it got it
+    writeln!(out, "MANTA CLIENT:")?;
+    writeln!(out, "  remote IP:      {}", remote_ip)?;
+    writeln!(out, "  Manta DNS name: {}", dns_name)?;
+    writeln!(out, "    (inferred from client \"Host\" header)")?;
+    writeln!(out, "  agent: {}",
+        muskie_info.mai_req_headers.string("user-agent").unwrap_or("unknown"))?;
+    if let Some(ref client) = mri.mri_client {
+        writeln!(out, "  client log:     {} entr{} for this request (host {} \
+            PID {})", client.cri_nentries,
+            if client.cri_nentries == 1 { "y" } else { "ies" },
+            client.cri_hostname, client.cri_pid)?;
+        writeln!(out, "  client latency: {} ms (from first to last client log \
+            entry)", (client.cri_time_finished -
+            client.cri_time_started).num_milliseconds())?;
+        writeln!(out, "  client status:  {}", match client.cri_status_code {
+            Some(code) => code.to_string(),
+            None => String::from("unknown (no response logged)")
+        })?;
+        writeln!(out, "  client retries: {}", client.cri_time_retries.len())?;
+    }
+    writeln!(out)?;
+
+    if let Some(ref haproxy) = mri.mri_haproxy {
+        mri_dump_haproxy(out, haproxy)?;
+    }
+
+    writeln!(out, "WEBAPI SERVER:  ZONE {} PID {}", muskie_info.mai_hostname,
+        muskie_info.mai_pid)?;
+    if !mri.mri_muskie_events.is_empty() {
+        writeln!(out, "  other log records: {} (shown on the timeline below)",
+            mri.mri_muskie_events.len())?;
+    }
+    writeln!(out)?;
+
+    writeln!(out, "REQUEST DETAILS:")?;
+    writeln!(out, "  request id:       {}",
+        muskie_info.request_id().unwrap_or_else(|| String::from("unknown")))?;
+    writeln!(out, "  method:           {}", muskie_info.mai_req_method)?;
+    writeln!(out, "  operation:        {}", muskie_info.mai_operation)?;
+    writeln!(out, "  billable op:      {}",
+        muskie_info.mai_billable_operation)?;
+    writeln!(out, "  url:              {}", muskie_info.mai_req_url)?;
+    writeln!(out, "  caller account:   {} ({})",
+        muskie_info.mai_req_caller_login, muskie_info.mai_req_caller_uuid)?;
+    writeln!(out, "  caller privilege: {}",
+        if muskie_info.mai_req_caller_operator { "OPERATOR" }
+        else { "unprivileged account" })?;
+    writeln!(out, "  owner account:    {}", muskie_info.mai_req_owner_uuid)?;
+    writeln!(out, "  route:            {}", muskie_info.mai_route)?;
+    writeln!(out)?;
+
+    writeln!(out, "RESPONSE DETAILS:")?;
+    writeln!(out, "  status code:     {}",
+        muskie_info.mai_response_status_code)?;
+    writeln!(out, "  muskie latency:  {} ms (calculated from timers)",
+        mri.mri_timeline_muskie.total_elapsed().num_milliseconds())?;
+    writeln!(out, "  x-response-time: {} (\"x-response-time\" header)",
+        muskie_info.mai_response_headers.number("x-response-time").map_or(
+        String::from("unknown"), |ms| format!("{} ms", ms)))?;
+    writeln!(out, "    (This is the latency-to-first-byte reported by the \
+        server.)")?;
+    writeln!(out)?;
+
+    // TODO should probably include "headobject"?
+    if muskie_info.mai_route == "putobject" ||
+        muskie_info.mai_route == "getstorage" ||
+        muskie_info.mai_route == "deletestorage" {
+        mri_dump_object_metadata(out, muskie_info)?;
+    }
+
+    if muskie_info.mai_route == "putobject" ||
+        muskie_info.mai_route == "getstorage" {
+        mri_dump_shark_info(out, muskie_info, &mri.mri_mako)?;
+    }
+
+    match &muskie_info.mai_error {
+        None => writeln!(out,
+            "ERROR INFORMATION: no error found in log entry")?,
+        Some(ref error) => {
+            writeln!(out, "ERROR INFORMATION:")?;
+            writeln!(out, "  name:    {}", error.mle_error_name)?;
+            writeln!(out, "  message: {}", error.mle_error_message)?;
+            // XXX add verbose option to print stack too
+        }
+    }
+    writeln!(out)?;
+
+    let fmt_bytes = |v : &MuskieValue| match v.as_i64() {
+        Some(n) => format!("{} bytes", n),
+        None => String::from("unknown")
+    };
+    writeln!(out, "DATA TRANSFER:")?;
+    writeln!(out, "  request headers:           {} bytes",
+        muskie_info.mai_req_header_length)?;
+    writeln!(out, "  request content length:    {}",
+        match muskie_info.mai_req_headers.get("content-length") {
+            Some(header_value) => fmt_bytes(header_value),
+            None => String::from("unspecified\n    (presumably streamed using \
+                chunked transfer encoding)")
+        })?;
+    writeln!(out, "  response headers:          {} bytes",
+        muskie_info.mai_response_header_length)?;
+    writeln!(out, "  response content length:   {}",
+        match muskie_info.mai_response_headers.get("content-length") {
+            Some(header_value) => fmt_bytes(header_value),
+            None => String::from("unspecified\n    (presumably streamed using \
+                chunked transfer encoding)")
+        })?;
+    writeln!(out, "  object bytes transferred:  {}",
+        muskie_info.mai_bytes_transferred.as_ref().and_then(
+            |b| b.as_i64()).map_or(String::from("unknown"),
+            |b| b.to_string()))?;
+    writeln!(out)?;
+
+    if !mri.mri_clock_skew.is_empty() {
+        mri_dump_skew(out, &mri.mri_clock_skew, mri.mri_skew_corrected)?;
+    }
+
+    mri_dump_latency(out, &mri.mri_latency)?;
+
+    writeln!(out, "OVERALL TIMELINE: starts at {}\n",
+        mri.mri_timeline_overall.wall_start().format("%FT%T.%3fZ"))?;
+    mri_dump_timeline(out, &mri.mri_timeline_overall, true,
+        &mri.mri_timeline_overall.wall_start(), min_duration_option, 0)?;
+
+    writeln!(out, "TIME ACCOUNTING:")?;
+    writeln!(out, "  {:>8} {:>8} {:>8}  TIMELINE", "TOTAL", "ACCTD",
+        "UNACCTD")?;
+    mri_dump_accounting(out, "overall", &mri.mri_timeline_overall, 0)?;
+    writeln!(out, "\n  Time not covered by any event is unaccounted.  \
+        Stretches of at least {} ms\n  are shown on the timeline as \
+        \"unaccounted\" events, labeled with likely\n  causes based on the \
+        events on either side.\n", MRI_GAP_THRESHOLD_MS)?;
+
+    if let Some(ref shark_timeline) = mri.mri_timeline_sharks {
+        writeln!(out, "STORAGE NODE-RELATED EVENTS:\n")?;
+        mri_dump_lanes(out, shark_timeline,
+            &mri.mri_timeline_overall.wall_start())?;
+    }
+
+    writeln!(out, "TIMELINE HEADERS:\n")?;
+    writeln!(out, "   rSTART   relative time (in milliseconds) since the first \
+        event\n            in the whole timeline\n")?;
+    writeln!(out, "   rCURR    relative time (in milliseconds) since the first \
+        event\n            in the current subtimeline\n")?;
+    writeln!(out,
+        "   ELAPSD   elapsed time (in milliseconds) for this event\n")?;
+    writeln!(out, "   Events whose start time is uncertain by more than a \
+        millisecond show how\n   much earlier (-) or later (+) they may \
+        have started and where the time\n   came from (e.g., \"+999 ms, from \
+        header\" for a time taken from a \"Date\"\n   header, which has a \
+        resolution of one second).")?;
+
+    return Ok(());
+}
+
+fn mri_dump_timeline(out : &mut dyn Write, timeline : &timeline::Timeline,
+    dump_header : bool, base : &chrono::DateTime<chrono::Utc>,
+    min_duration_option : Option<chrono::Duration>, depth : u8)
+    -> io::Result<u16>
+{
+    if dump_header {
+        writeln!(out, "  {:13} {:>6} {:>6} {:>6} {}", "WALL TIME",
+            "rSTART", "rCURR", "ELAPSD", "EVENT")?;
+    }
+
+    let mut nskipped = 0;
+
+    for event in timeline.events() {
+        if let Some(min_duration) = min_duration_option {
+            //
+            // TODO we're using 0 as a special value for important events that
+            // have no elapsed time, but this should probably be an option
+            // instead.
+            //
+            if !event.duration().is_zero() && event.duration() < min_duration {
+                nskipped += 1;
+                continue;
+            }
+        }
+
+        // let wall_start = format!("{}", event.wall_start());
+        let wall_start = event.wall_start().format("%T.%3fZ");
+        write!(out, "  {:13} {:6} {:6} ", wall_start,
+            (event.wall_start().signed_duration_since(*base)).
+            num_milliseconds(),
+            event.relative_start().num_milliseconds())?;
+
+        let maybe_subtimeline = event.subtimeline();
+        if let Some(subtimeline) = maybe_subtimeline {
+            writeln!(out, "{:>6} {} {{", "-", event.label())?;
+            nskipped += mri_dump_timeline(out, subtimeline, false,
+                base, min_duration_option, depth + 1)?;
+
+            let wall_end = event.wall_end().format("%T.%3fZ");
+            writeln!(out,
+                "  {:13} {:6} {:>6} {:6} {:width$}}} (subtimeline ended)",
+                wall_end, (event.wall_start().signed_duration_since(*base) +
+                event.duration()).num_milliseconds(), "-",
+                event.duration().num_milliseconds(), "",
+                width = (depth * 4) as usize)?;
+        } else {
+            writeln!(out, "{:6} {:width$}{}{}",
+                event.duration().num_milliseconds(), "", event.label(),
+                mri_fmt_uncertainty(event), width = (depth * 4) as usize)?;
+        }
+    }
+
+    if depth == 0 {
+        if nskipped > 0 {
+            writeln!(out, "\n  NOTE: {} timeline event{} with duration less \
+                than {} ms {} not shown above.", nskipped,
+                if nskipped == 1 { "" } else { "s" },
+                min_duration_option.expect(
+                    "must be min_duration_option if events were filtered").
+                    num_milliseconds(),
+                if nskipped == 1 { "was" } else { "were" })?;
+        }
+
+        writeln!(out)?;
+    }
+
+    return Ok(nskipped);
+}
+
+///
+/// Writes one line summarizing how much of `timeline` is accounted for by its
+/// events, followed by a line for each of its subtimelines (recursively).
+///
+fn mri_dump_accounting(out : &mut dyn Write, label : &str,
+    timeline : &timeline::Timeline, depth : usize)
+    -> io::Result<()>
+{
+    let total = timeline.total_elapsed();
+    let unaccounted = timeline.unaccounted();
+    writeln!(out, "  {:8} {:8} {:8}  {:width$}{}", total.num_milliseconds(),
+        (total - unaccounted).num_milliseconds(),
+        unaccounted.num_milliseconds(), "", label, width = depth * 4)?;
+
+    for event in timeline.events() {
+        if let Some(subtimeline) = event.subtimeline() {
+            mri_dump_accounting(out, &event.label(), subtimeline,
+                depth + 1)?;
+        }
+    }
+
+    return Ok(());
+}
+
+///
+/// Writes a timeline whose events are in lanes, with the lanes side by side.
+/// Each row is a time at which some event started or ended.  In each lane's
+/// column, an event that starts at that time is shown as "> LABEL", one that
+/// ends is shown as "< N ms" (its duration), and one that's still going is
+/// shown as "|".
+///
+fn mri_dump_lanes(out : &mut dyn Write, timeline : &timeline::Timeline,
+    base : &chrono::DateTime<chrono::Utc>)
+    -> io::Result<()>
+{
+    //
+    // We only display times to the millisecond, so we also group events into
+    // rows by millisecond.
+    //
+    let truncate = |t : chrono::DateTime<chrono::Utc>| {
+        t - chrono::Duration::nanoseconds(i64::from(
+            t.timestamp_subsec_nanos() % 1_000_000))
+    };
+    let lanes : Vec<(&String, Vec<&timeline::TimelineEvent>)> =
+        timeline.lanes().iter().map(|lane| (lane, timeline.events().iter().
+        filter(|e| e.lane() == Some(lane.as_str())).collect())).collect();
+
+    let mut times : Vec<chrono::DateTime<chrono::Utc>> = Vec::new();
+    for event in timeline.events() {
+        times.push(truncate(event.wall_start()));
+        times.push(truncate(event.wall_end()));
+    }
+    times.sort();
+    times.dedup();
+
+    let rows : Vec<(chrono::DateTime<chrono::Utc>, Vec<String>)> =
+        times.into_iter().map(|when| (when, lanes.iter().map(|(_, events)| {
+            if let Some(e) = events.iter().find(
+                |e| truncate(e.wall_start()) == when) {
+                format!("> {}", e.label())
+            } else if let Some(e) = events.iter().find(
+                |e| truncate(e.wall_end()) == when) {
+                format!("< {} ms", e.duration().num_milliseconds())
+            } else if events.iter().any(|e| truncate(e.wall_start()) < when &&
+                truncate(e.wall_end()) > when) {
+                String::from("|")
+            } else {
+                String::new()
+            }
+        }).collect())).collect();
+
+    let widths : Vec<usize> = lanes.iter().enumerate().map(|(i, (lane, _))| {
+        rows.iter().map(|(_, cells)| cells[i].chars().count()).fold(
+            lane.chars().count(), std::cmp::max).clamp(12, 28)
+    }).collect();
+    let fit = |text : &str, width : usize| -> String {
+        text.chars().take(width).collect()
+    };
+
+    let mut header = format!("  {:13} {:>6}", "WALL TIME", "rSTART");
+    for ((lane, _), width) in lanes.iter().zip(widths.iter()) {
+        header.push_str(&format!("  {:width$}", fit(lane, *width),
+            width = *width));
+    }
+    writeln!(out, "{}", header.trim_end())?;
+
+    for (when, cells) in rows {
+        let mut row = format!("  {:13} {:6}", when.format("%T.%3fZ"),
+            (when - *base).num_milliseconds());
+        for (cell, width) in cells.iter().zip(widths.iter()) {
+            row.push_str(&format!("  {:width$}", fit(cell, *width),
+                width = *width));
+        }
+        writeln!(out, "{}", row.trim_end())?;
+    }
+
+    writeln!(out)?;
+
+    return Ok(());
+}
+
+///
+/// Returns a suffix for an event's label describing how uncertain its start
+/// time is, or an empty string if it's known to within the millisecond
+/// resolution that we display.
+///
+fn mri_fmt_uncertainty(event : &timeline::TimelineEvent)
+    -> String
+{
+    let before = (event.wall_start() - event.earliest_start()).
+        num_milliseconds();
+    let after = (event.latest_start() - event.wall_start()).num_milliseconds();
+    if before + after <= 1 {
+        return String::new();
+    }
+
+    let range = if before == after {
+        format!("\u{b1}{}", before)
+    } else if before == 0 {
+        format!("+{}", after)
+    } else if after == 0 {
+        format!("-{}", before)
+    } else {
+        format!("-{}/+{}", before, after)
+    };
+
+    return format!(" ({} ms, {})", range, event.provenance());
+}
+
+fn mri_dump_haproxy(out : &mut dyn Write, haproxy : &HaproxyLogEntry)
+    -> io::Result<()>
+{
+    let fmt_timer = |t : Option<chrono::Duration>| match t {
+        Some(d) => format!("{} ms", d.num_milliseconds()),
+        None => String::from("-")
+    };
+
+    writeln!(out, "LOAD BALANCER:  ZONE {} PID {}", haproxy.hle_hostname,
+        haproxy.hle_pid)?;
+    writeln!(out, "  client address:    {} port {}", haproxy.hle_client_ip,
+        haproxy.hle_client_port)?;
+    writeln!(out, "  frontend:          {}", haproxy.hle_frontend)?;
+    writeln!(out, "  backend/server:    {}/{}", haproxy.hle_backend,
+        haproxy.hle_server)?;
+    writeln!(out, "  request:           {}", haproxy.hle_request_line)?;
+    writeln!(out, "  status code:       {}", match haproxy.hle_status_code {
+        Some(code) => code.to_string(),
+        None => String::from("none (no response sent)")
+    })?;
+    writeln!(out, "  bytes read:        {}", haproxy.hle_bytes_read)?;
+    writeln!(out, "  termination state: {}", haproxy.hle_termination_state)?;
+    writeln!(out, "  connections:       {} active, {} frontend, {} backend, \
+        {} server", haproxy.hle_conn_active, haproxy.hle_conn_frontend,
+        haproxy.hle_conn_backend, haproxy.hle_conn_server)?;
+    writeln!(out, "  retries:           {}", haproxy.hle_retries)?;
+    writeln!(out, "  queues:            {} server, {} backend",
+        haproxy.hle_queue_server, haproxy.hle_queue_backend)?;
+    writeln!(out, "  time to receive request (Tq): {}",
+        fmt_timer(haproxy.hle_time_request))?;
+    writeln!(out, "  time queued (Tw):             {}",
+        fmt_timer(haproxy.hle_time_queued))?;
+    writeln!(out, "  time to connect (Tc):         {}",
+        fmt_timer(haproxy.hle_time_connect))?;
+    writeln!(out, "  time to response (Tr):        {}",
+        fmt_timer(haproxy.hle_time_response))?;
+    writeln!(out, "  total time (Tt):              {}",
+        fmt_timer(Some(haproxy.hle_time_total)))?;
+    writeln!(out)?;
+
+    return Ok(());
+}
+
+///
+/// Writes where the request's time went: the biggest single item on its
+/// critical path, the time in each category, and the critical path itself
+/// (leaving out stretches shorter than a millisecond).
+///
+fn mri_dump_latency(out : &mut dyn Write, latency : &MantaLatencyBreakdown)
+    -> io::Result<()>
+{
+    let fmt_ms = |d : chrono::Duration| match d.num_milliseconds() {
+        0 if !d.is_zero() => String::from("<1 ms"),
+        ms => format!("{} ms", ms)
+    };
+    let fmt_pct = |d : chrono::Duration| format!("{:.1}%",
+        latency.percent(d));
+
+    writeln!(out, "LATENCY BREAKDOWN: {} total (from first to last event)",
+        fmt_ms(latency.mlb_total))?;
+
+    //
+    // The headline summarizes the table below it, so it's about the largest
+    // category.  That may be made up of several stretches of the critical
+    // path, so we separately call out the single longest stretch.
+    //
+    if let Some((category, duration)) = latency.mlb_categories.first() {
+        writeln!(out, "  {} of time in {}", fmt_pct(*duration), category)?;
+    }
+    if let Some(largest) = latency.largest() {
+        let category = largest.mps_category.to_string();
+        writeln!(out, "  longest single stretch: {} ({}{})",
+            largest.mps_label, fmt_ms(largest.mps_duration),
+            if largest.mps_label.starts_with(&category) { String::new() }
+            else { format!(", {}", category) })?;
+    }
+    writeln!(out)?;
+
+    writeln!(out, "  {:>10} {:>6}  {}", "TIME", "%", "CATEGORY")?;
+    for (category, duration) in &latency.mlb_categories {
+        writeln!(out, "  {:>10} {:>6}  {}", fmt_ms(*duration),
+            fmt_pct(*duration), category)?;
+    }
+    writeln!(out)?;
+
+    let min_duration = chrono::Duration::milliseconds(1);
+    writeln!(out, "  CRITICAL PATH:")?;
+    writeln!(out, "  {:>10} {:>6}  {:23}  {}", "TIME", "%", "CATEGORY",
+        "WAITING ON")?;
+    let mut nskipped = 0;
+    for segment in &latency.mlb_path {
+        if segment.mps_duration < min_duration {
+            nskipped += 1;
+            continue;
+        }
+
+        writeln!(out, "  {:>10} {:>6}  {:23}  {}", fmt_ms(segment.mps_duration),
+            fmt_pct(segment.mps_duration), segment.mps_category.to_string(),
+            segment.mps_label)?;
+    }
+    if nskipped > 0 {
+        writeln!(out, "  ({} shorter stretch{} not shown)", nskipped,
+            if nskipped == 1 { "" } else { "es" })?;
+    }
+    writeln!(out)?;
+
+    return Ok(());
+}
+
+fn mri_dump_skew(out : &mut dyn Write, skews : &[MantaClockSkew],
+    corrected : bool)
+    -> io::Result<()>
+{
+    let fmt_bound = |t : Option<chrono::Duration>| match t {
+        Some(d) => d.num_milliseconds().to_string(),
+        None => String::from("-")
+    };
+
+    writeln!(out, "CLOCK SKEW: (relative to muskie's clock, in milliseconds)")?;
+    writeln!(out, "  {:>8} {:>8} {:>8} {}", "MIN", "MAX", "ESTIMATE",
+        "SOURCE")?;
+    for skew in skews {
+        writeln!(out, "  {:>8} {:>8} {:>8} {}{}", fmt_bound(skew.mcs_min),
+            fmt_bound(skew.mcs_max), skew.mcs_estimate.num_milliseconds(),
+            skew.mcs_source,
+            if skew.inconsistent() { " (inconsistent)" } else { "" })?;
+    }
+    writeln!(out, "    (positive values mean the source's clock is ahead)")?;
+    if corrected {
+        writeln!(out, "    (client and load balancer events on the timeline \
+            below have been\n    shifted by the estimated skew)")?;
+    }
+    writeln!(out)?;
+
+    return Ok(());
+}
+
+fn mri_dump_object_metadata(out : &mut dyn Write, mip : &MuskieAuditInfo)
+    -> io::Result<()>
+{
+    writeln!(out, "MANTA OBJECT METADATA:")?;
+    writeln!(out, "  path:                     {}", mip.mai_req_url)?;
+    writeln!(out, "  objectid:                 {}",
+        mip.mai_objectid.as_ref().unwrap_or(&String::from("unknown")))?;
+    writeln!(out, "  metadata on shard:        {}",
+        mip.mai_shard_entry.as_ref().unwrap_or(&String::from("unknown")))?;
+    // TODO explicitly note case of parent metadata being a synthetic directory
+    // like "/account/stor"?
+    writeln!(out, "  parent metadata on shard: {}",
+        mip.mai_shard_parent.as_ref().unwrap_or(&String::from("unknown")))?;
+
+    writeln!(out, "  durability level:         {}",
+        mip.mai_response_headers.get("durability-level").and_then(
+            |x| x.as_i64()).map_or(String::from("unknown"),
+            |x| x.to_string()))?;
+    writeln!(out, "  md5sum (HTTP):            {}",
+        mip.mai_response_headers.get("content-md5").map_or("unknown",
+            |x| x.as_str()))?;
+
+    writeln!(out)?;
+
+    return Ok(());
+}
+
+fn mri_dump_shark_info(out : &mut dyn Write, mip : &MuskieAuditInfo,
+    mako_entries : &[Option<MakoLogEntry>])
+    -> io::Result<()>
+{
+    if mip.mai_sharks_contacted.is_none() {
+        writeln!(out, "STORAGE NODES CONTACTED: not found in log entry")?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let sharks = mip.mai_sharks_contacted.as_ref().unwrap();
+    writeln!(out, "STORAGE NODES CONTACTED:")?;
+    writeln!(out, "  {:13} {:>6} {:>6} {:>4} {}", "START", "TTFB", "TOTAL",
+        "OK?", "STOR_ID")?;
+    for shark in sharks {
+        writeln!(out, "  {:13} {:>6} {:>6} {:>4} {}",
+            shark.mai_shark_time_start.format("%T.%3fZ"),
+            match shark.mai_shark_latency_ttfb {
+                Some(duration) => duration.num_milliseconds().to_string(),
+                None => String::from("-")
+            },
+            match shark.mai_shark_latency_total {
+                Some(duration) => duration.num_milliseconds().to_string(),
+                None => String::from("-")
+            },
+            if shark.mai_shark_success { "OK" } else { "FAIL" },
+            shark.mai_shark_storid)?;
+    }
+    writeln!(out)?;
+
+    if mako_entries.iter().all(|e| e.is_none()) {
+        return Ok(());
+    }
+
+    //
+    // Show the storage nodes' own view of each request.  Disagreements with
+    // Muskie are reported as diagnostics.
+    //
+    let fmt_ms = |t : Option<chrono::Duration>| match t {
+        Some(d) => d.num_milliseconds().to_string(),
+        None => String::from("-")
+    };
+    writeln!(out, "STORAGE NODE ACCESS LOG ENTRIES:")?;
+    writeln!(out, "  {:13} {:>6} {:>10} {:>6} {:>6} {}", "END", "STATUS",
+        "BYTES", "RTIME", "UPSTRM", "STOR_ID")?;
+    for (shark, mako) in sharks.iter().zip(mako_entries.iter()) {
+        let mako = match mako {
+            Some(m) => m,
+            None => {
+                writeln!(out, "  {:13} {:>6} {:>10} {:>6} {:>6} {}", "-",
+                    "-", "-", "-", "-", shark.mai_shark_storid)?;
+                continue;
+            }
+        };
+
+        writeln!(out, "  {:13} {:>6} {:>10} {:>6} {:>6} {}",
+            mako.mke_time.format("%T.%3fZ"), mako.mke_status_code,
+            mako.mke_bytes_sent, fmt_ms(Some(mako.mke_request_time)),
+            fmt_ms(mako.mke_upstream_time), shark.mai_shark_storid)?;
+    }
+    writeln!(out, "    (mako logs END times to the second)")?;
+    writeln!(out)?;
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn ms(n : i64)
+        -> chrono::Duration
+    {
+        return chrono::Duration::milliseconds(n);
+    }
+
+    fn dump_lanes(timeline : &timeline::Timeline)
+        -> String
+    {
+        let mut out = Vec::new();
+        mri_dump_lanes(&mut out, timeline, &timeline.wall_start()).unwrap();
+        return String::from_utf8(out).unwrap();
+    }
+
+    ///
+    /// Events in different lanes may overlap, and each lane shows its own
+    /// events starting, running, and ending.
+    ///
+    #[test]
+    fn lanes_overlapping()
+    {
+        let start = chrono::Utc.ymd(2019, 5, 9).and_hms(21, 34, 23);
+        let mut builder = timeline::TimelineBuilder::new_unanchored();
+        builder.add_to_lane("muskie", "streamFromSharks", &start, &ms(30));
+        builder.add_to_lane("\"1.stor\"", "awaiting first byte",
+            &(start + ms(5)), &ms(4));
+        builder.add_to_lane("\"1.stor\"", "streaming (ok)", &(start + ms(9)),
+            &ms(20));
+        builder.add_to_lane("\"2.stor\"", "request", &(start + ms(5)),
+            &ms(25));
+        let timeline = builder.try_finish().unwrap();
+
+        assert_eq!(dump_lanes(&timeline), "  \
+WALL TIME     rSTART  muskie              \"1.stor\"               \"2.stor\"
+  21:34:23.000Z      0  > streamFromSharks
+  21:34:23.005Z      5  |                   > awaiting first byte  > request
+  21:34:23.009Z      9  |                   > streaming (ok)       |
+  21:34:23.029Z     29  |                   < 20 ms                |
+  21:34:23.030Z     30  < 30 ms                                    < 25 ms
+
+");
+    }
+
+    ///
+    /// Columns are as wide as their widest cell in characters, not bytes, so
+    /// labels with non-ASCII characters line up too.
+    ///
+    #[test]
+    fn lanes_width_in_chars()
+    {
+        let start = chrono::Utc.ymd(2019, 5, 9).and_hms(21, 34, 23);
+        let mut builder = timeline::TimelineBuilder::new_unanchored();
+        builder.add_to_lane("a", "\u{e9}t\u{e9} \u{2192} r\u{e9}ponse",
+            &start, &ms(2));
+        builder.add_to_lane("b", "next", &(start + ms(1)), &ms(1));
+        let timeline = builder.try_finish().unwrap();
+
+        assert_eq!(dump_lanes(&timeline), "  \
+WALL TIME     rSTART  a                b
+  21:34:23.000Z      0  > \u{e9}t\u{e9} \u{2192} r\u{e9}ponse
+  21:34:23.001Z      1  |                > next
+  21:34:23.002Z      2  < 2 ms           < 1 ms
+
+");
+    }
+
+    /// Returns the uncertainty suffix for the first event of `builder`.
+    fn uncertainty(builder : timeline::TimelineBuilder)
+        -> String
+    {
+        return mri_fmt_uncertainty(&builder.finish().events()[0]);
+    }
+
+    #[test]
+    fn uncertainty_header()
+    {
+        let when = chrono::Utc.ymd(2019, 5, 9).and_hms(21, 34, 23);
+        let mut builder = timeline::TimelineBuilder::new_ending(when);
+        builder.add_bounded("client generated Date header", &when, &when,
+            &(when + ms(999)), &chrono::Duration::zero(),
+            timeline::TimelineProvenance::HeaderDerived);
+        assert_eq!(uncertainty(builder), " (+999 ms, from header)");
+    }
+
+    #[test]
+    fn uncertainty_ranges()
+    {
+        let when = chrono::Utc.ymd(2019, 5, 9).and_hms(21, 34, 23);
+        let bounded = |before : i64, after : i64| {
+            let mut builder = timeline::TimelineBuilder::new_ending(when);
+            builder.add_bounded("event", &when, &(when - ms(before)),
+                &(when + ms(after)), &chrono::Duration::zero(),
+                timeline::TimelineProvenance::Inferred);
+            uncertainty(builder)
+        };
+
+        assert_eq!(bounded(0, 0), "");
+        assert_eq!(bounded(1, 0), "");
+        assert_eq!(bounded(0, 1), "");
+        assert_eq!(bounded(1, 1), " (\u{b1}1 ms, inferred)");
+        assert_eq!(bounded(2, 0), " (-2 ms, inferred)");
+        assert_eq!(bounded(1, 3), " (-1/+3 ms, inferred)");
+
+        let mut builder = timeline::TimelineBuilder::new_ending(when);
+        builder.add("event", &when, &chrono::Duration::zero());
+        assert_eq!(uncertainty(builder), "");
+    }
+
+    ///
+    /// Working backwards through enough handlers accumulates more than a
+    /// millisecond of uncertainty, from a microsecond for each handler.
+    ///
+    #[test]
+    fn uncertainty_prepended()
+    {
+        let when = chrono::Utc.ymd(2019, 5, 9).and_hms(21, 34, 23);
+        let mut builder = timeline::TimelineBuilder::new_ending(when);
+        for _ in 0..1999 {
+            builder.prepend("handler", &chrono::Duration::microseconds(10));
+        }
+        assert_eq!(uncertainty(builder), "");
+
+        let mut builder = timeline::TimelineBuilder::new_ending(when);
+        for _ in 0..2000 {
+            builder.prepend("handler", &chrono::Duration::microseconds(10));
+        }
+        assert_eq!(uncertainty(builder), " (-2 ms, inferred)");
+    }
+}
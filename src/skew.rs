@@ -0,0 +1,404 @@
+/*
+ * src/skew.rs: estimating clock skew between log sources
+ */
+
+//!
+//! # Clock skew
+//!
+//! Each component that logs a request (the client, the load balancer, Muskie,
+//! and the storage nodes) records times from its own clock, and those clocks
+//! don't exactly agree.  We use Muskie's clock as the reference, since Muskie's
+//! audit entry anchors the whole timeline, and estimate each other source's
+//! skew: the amount by which that source's clock is ahead of Muskie's.
+//!
+//! We can't measure skew directly, but causality bounds it.  The client sends
+//! the request before the load balancer accepts it, which happens before
+//! Muskie begins processing it, which happens before Muskie contacts any
+//! storage node.  At the other end, Muskie finishes with the storage nodes
+//! before it finishes the request, and the load balancer and client finish
+//! after that.  Each such ordering between an event on some source's clock
+//! and an event on Muskie's clock yields a lower or upper bound on that
+//! source's skew.  Orderings between the client and the load balancer are
+//! used too, by way of the load balancer's own bounds.
+//!
+//! Several of these timestamps only have a resolution of one second (e.g., the
+//! client's "Date" header and the storage nodes' access log entries), and we
+//! account for that so that the bounds remain sound.
+//!
+//! Within the resulting bounds, we assume the clocks agree unless that's
+//! impossible, so the estimate is the skew closest to zero that's consistent
+//! with the bounds.
+//!
+
+use super::date::mri_date_plausible;
+use super::date::mri_parse_http_date;
+use super::diagnostics::MantaDiagnostics;
+use super::log_client::ClientRequestInfo;
+use super::log_haproxy::HaproxyLogEntry;
+use super::log_mako::MakoLogEntry;
+use super::log_muskie::MuskieAuditInfo;
+
+/// name of the load balancer's clock in `MantaClockSkew::mcs_source`
+pub const MRI_SKEW_LOAD_BALANCER : &str = "load balancer";
+/// name of the client's clock in `MantaClockSkew::mcs_source`
+pub const MRI_SKEW_CLIENT : &str = "client";
+
+///
+/// Describes what we know about one source's clock relative to Muskie's.
+/// `mcs_min` and `mcs_max` bound the skew (the amount by which the source's
+/// clock is ahead of Muskie's), when we have any constraints in that direction.
+/// `mcs_estimate` is our best guess, which falls within the bounds unless the
+/// bounds are inconsistent.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct MantaClockSkew {
+    #[serde(rename = "source")]     pub mcs_source : String,
+    #[serde(rename = "min_ms",
+        serialize_with = "super::json::mri_json_duration_opt")]
+    pub mcs_min : Option<chrono::Duration>,
+    #[serde(rename = "max_ms",
+        serialize_with = "super::json::mri_json_duration_opt")]
+    pub mcs_max : Option<chrono::Duration>,
+    #[serde(rename = "estimate_ms",
+        serialize_with = "super::json::mri_json_duration")]
+    pub mcs_estimate : chrono::Duration,
+    #[serde(rename = "nconstraints")]
+    pub mcs_nconstraints : usize,
+}
+
+impl MantaClockSkew {
+    /// Returns whether the bounds on this source's skew contradict each other.
+    pub fn inconsistent(&self)
+        -> bool
+    {
+        match (self.mcs_min, self.mcs_max) {
+            (Some(min), Some(max)) => min > max,
+            _ => false
+        }
+    }
+}
+
+///
+/// Accumulates causal constraints on the skew of one source's clock.  Times
+/// passed in are on the source's clock (`source_time`) and on Muskie's clock
+/// (`muskie_time`).  `resolution` is the resolution of the source's timestamp:
+/// the event actually happened somewhere in [source_time, source_time +
+/// resolution).
+///
+struct SkewBounds {
+    sb_min : Option<chrono::Duration>,
+    sb_max : Option<chrono::Duration>,
+    sb_nconstraints : usize,
+}
+
+impl SkewBounds {
+    fn new()
+        -> SkewBounds
+    {
+        return SkewBounds {
+            sb_min : None,
+            sb_max : None,
+            sb_nconstraints : 0
+        };
+    }
+
+    ///
+    /// Records that the event at `source_time` happened before the event at
+    /// `muskie_time`.
+    ///
+    fn before(&mut self, source_time : &chrono::DateTime<chrono::Utc>,
+        muskie_time : &chrono::DateTime<chrono::Utc>)
+    {
+        self.at_least(*source_time - *muskie_time);
+    }
+
+    ///
+    /// Records that the event at `source_time` (having resolution
+    /// `resolution`) happened after the event at `muskie_time`.
+    ///
+    fn after(&mut self, source_time : &chrono::DateTime<chrono::Utc>,
+        resolution : chrono::Duration,
+        muskie_time : &chrono::DateTime<chrono::Utc>)
+    {
+        self.at_most(*source_time + resolution - *muskie_time);
+    }
+
+    fn at_least(&mut self, skew : chrono::Duration)
+    {
+        self.sb_min = Some(self.sb_min.map_or(skew,
+            |m| std::cmp::max(m, skew)));
+        self.sb_nconstraints += 1;
+    }
+
+    fn at_most(&mut self, skew : chrono::Duration)
+    {
+        self.sb_max = Some(self.sb_max.map_or(skew,
+            |m| std::cmp::min(m, skew)));
+        self.sb_nconstraints += 1;
+    }
+
+    fn finish(self, source : String, diagnostics : &mut MantaDiagnostics)
+        -> MantaClockSkew
+    {
+        let zero = chrono::Duration::zero();
+        let mut skew = MantaClockSkew {
+            mcs_source : source,
+            mcs_min : self.sb_min,
+            mcs_max : self.sb_max,
+            mcs_estimate : zero,
+            mcs_nconstraints : self.sb_nconstraints
+        };
+
+        if skew.inconsistent() {
+            let (min, max) = (self.sb_min.unwrap(), self.sb_max.unwrap());
+            diagnostics.warn("clock-skew-inconsistent", None, format!(
+                "{}: timestamps are not consistent with any clock skew \
+                (must be at least {} ms and at most {} ms relative to \
+                muskie)", skew.mcs_source, min.num_milliseconds(),
+                max.num_milliseconds()));
+            skew.mcs_estimate = (min + max) / 2;
+            return skew;
+        }
+
+        if let Some(min) = self.sb_min {
+            skew.mcs_estimate = std::cmp::max(skew.mcs_estimate, min);
+        }
+        if let Some(max) = self.sb_max {
+            skew.mcs_estimate = std::cmp::min(skew.mcs_estimate, max);
+        }
+
+        return skew;
+    }
+}
+
+///
+/// Estimates the skew of each source's clock relative to Muskie's.  Sources
+/// for which we have no constraints are left out.  The load balancer comes
+/// first, then the client, then each storage node for which we have an access
+/// log entry (in the order Muskie contacted them).
+///
+pub fn mri_estimate_skew(muskie_info : &MuskieAuditInfo,
+    haproxy : Option<&HaproxyLogEntry>, client : Option<&ClientRequestInfo>,
+    mako_entries : &[Option<MakoLogEntry>],
+    diagnostics : &mut MantaDiagnostics)
+    -> Vec<MantaClockSkew>
+{
+    let muskie_start = muskie_info.wall_start();
+    let muskie_end = muskie_info.mai_time;
+    let one_second = chrono::Duration::seconds(1);
+    let mut rv = Vec::new();
+
+    //
+    // The load balancer accepts the connection before Muskie begins processing
+    // the request, and it's done with the request after Muskie is.
+    //
+    let mut haproxy_bounds = None;
+    if let Some(h) = haproxy {
+        let mut bounds = SkewBounds::new();
+        bounds.before(&h.hle_accept_time, &muskie_start);
+        bounds.after(&h.wall_end(), chrono::Duration::zero(), &muskie_end);
+        let skew = bounds.finish(String::from(MRI_SKEW_LOAD_BALANCER),
+            diagnostics);
+        if !skew.inconsistent() {
+            haproxy_bounds = Some((skew.mcs_min, skew.mcs_max));
+        }
+        rv.push(skew);
+    }
+
+    //
+    // The client generates the "Date" header before sending the request, and
+    // the client log (if we have it) records when the client started and
+    // finished with the request.  If we have bounds for the load balancer,
+    // the client's times are also bounded by the load balancer's times, but
+    // only by as much as we know about the load balancer's own skew.  A
+    // "Date" header that isn't plausible (see mri_timelines()) would only
+    // produce nonsensical bounds, so we ignore it.
+    //
+    let mut client_bounds = SkewBounds::new();
+    if let Some(date) = muskie_info.mai_req_headers.get("date") {
+        let when = mri_parse_http_date(date.as_str()).ok().filter(
+            |when| mri_date_plausible(when, &muskie_start, &muskie_end));
+        if let Some(when) = when {
+            client_bounds.before(&when, &muskie_start);
+            if let (Some(h), Some((Some(hmin), _))) =
+                (haproxy, haproxy_bounds) {
+                client_bounds.at_least(when - h.hle_accept_time + hmin);
+            }
+        }
+    }
+
+    if let Some(c) = client {
+        client_bounds.before(&c.cri_time_started, &muskie_start);
+        client_bounds.after(&c.cri_time_finished, chrono::Duration::zero(),
+            &muskie_end);
+        if let (Some(h), Some((hmin, hmax))) = (haproxy, haproxy_bounds) {
+            if let Some(hmin) = hmin {
+                client_bounds.at_least(c.cri_time_started -
+                    h.hle_accept_time + hmin);
+            }
+            if let Some(hmax) = hmax {
+                client_bounds.at_most(c.cri_time_finished - h.wall_end() +
+                    hmax);
+            }
+        }
+    }
+
+    if client_bounds.sb_nconstraints > 0 {
+        rv.push(client_bounds.finish(String::from(MRI_SKEW_CLIENT),
+            diagnostics));
+    }
+
+    //
+    // Each storage node starts processing its request after Muskie contacts
+    // it and finishes before Muskie reports being done with it.  Storage
+    // nodes log the completion time, to the second, and the request latency.
+    //
+    if let Some(ref sharks) = muskie_info.mai_sharks_contacted {
+        for (shark, mako) in sharks.iter().zip(mako_entries.iter()) {
+            let mako = match mako {
+                Some(mako) => mako,
+                None => continue
+            };
+
+            let mut bounds = SkewBounds::new();
+            let mako_start = mako.mke_time - mako.mke_request_time;
+            bounds.after(&mako_start, one_second, &shark.mai_shark_time_start);
+            if let Some(total) = shark.mai_shark_latency_total {
+                bounds.before(&mako.mke_time,
+                    &(shark.mai_shark_time_start + total));
+            }
+
+            rv.push(bounds.finish(format!("storage node \"{}\"",
+                shark.mai_shark_storid), diagnostics));
+        }
+    }
+
+    return rv;
+}
+
+///
+/// Returns the estimated skew for source `source`, or zero if we have no
+/// estimate for it or its bounds are inconsistent (in which case no correction
+/// would make its timestamps consistent with Muskie's).
+///
+pub fn mri_skew_for(skews : &[MantaClockSkew], source : &str)
+    -> chrono::Duration
+{
+    return skews.iter().find(|s| s.mcs_source == source &&
+        !s.inconsistent()).map_or(chrono::Duration::zero(),
+        |s| s.mcs_estimate);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn ms(n : i64)
+        -> chrono::Duration
+    {
+        return chrono::Duration::milliseconds(n);
+    }
+
+    /// Returns the time `n` milliseconds after an arbitrary base time.
+    fn at(n : i64)
+        -> chrono::DateTime<chrono::Utc>
+    {
+        return chrono::Utc.ymd(2019, 5, 9).and_hms(21, 34, 23) + ms(n);
+    }
+
+    fn finish(bounds : SkewBounds)
+        -> (MantaClockSkew, MantaDiagnostics)
+    {
+        let mut diagnostics = MantaDiagnostics::new();
+        let skew = bounds.finish(String::from("test"), &mut diagnostics);
+        return (skew, diagnostics);
+    }
+
+    ///
+    /// An event on the source's clock that happened before one on Muskie's
+    /// bounds the skew from below, and one that happened after bounds it from
+    /// above (allowing for the source timestamp's resolution).  The tightest
+    /// bound in each direction wins.
+    ///
+    #[test]
+    fn bounds()
+    {
+        let mut bounds = SkewBounds::new();
+        bounds.before(&at(100), &at(150));
+        bounds.before(&at(100), &at(120));
+        bounds.after(&at(2000), ms(0), &at(1900));
+        bounds.after(&at(2000), ms(999), &at(1800));
+        let (skew, diagnostics) = finish(bounds);
+
+        assert_eq!(skew.mcs_min, Some(ms(-20)));
+        assert_eq!(skew.mcs_max, Some(ms(100)));
+        assert_eq!(skew.mcs_nconstraints, 4);
+        assert!(!skew.inconsistent());
+        assert!(diagnostics.is_empty());
+
+        let (skew, _) = finish(SkewBounds::new());
+        assert_eq!(skew.mcs_min, None);
+        assert_eq!(skew.mcs_max, None);
+        assert_eq!(skew.mcs_estimate, ms(0));
+        assert_eq!(skew.mcs_nconstraints, 0);
+    }
+
+    ///
+    /// The estimate is the skew closest to zero that satisfies the bounds.
+    ///
+    #[test]
+    fn estimate()
+    {
+        let mut bounds = SkewBounds::new();
+        bounds.at_least(ms(-20));
+        bounds.at_most(ms(100));
+        assert_eq!(finish(bounds).0.mcs_estimate, ms(0));
+
+        let mut bounds = SkewBounds::new();
+        bounds.at_least(ms(30));
+        bounds.at_most(ms(100));
+        assert_eq!(finish(bounds).0.mcs_estimate, ms(30));
+
+        let mut bounds = SkewBounds::new();
+        bounds.at_most(ms(-272));
+        assert_eq!(finish(bounds).0.mcs_estimate, ms(-272));
+
+        let mut bounds = SkewBounds::new();
+        bounds.at_least(ms(-500));
+        assert_eq!(finish(bounds).0.mcs_estimate, ms(0));
+    }
+
+    ///
+    /// Bounds that contradict each other produce a warning, an estimate
+    /// halfway between them, and no correction.
+    ///
+    #[test]
+    fn inconsistent()
+    {
+        let mut bounds = SkewBounds::new();
+        bounds.at_least(ms(300));
+        bounds.at_most(ms(100));
+        let (skew, diagnostics) = finish(bounds);
+
+        assert!(skew.inconsistent());
+        assert_eq!(skew.mcs_estimate, ms(200));
+        let items = diagnostics.items();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].mdi_code, "clock-skew-inconsistent");
+        assert_eq!(items[0].mdi_message, "test: timestamps are not consistent \
+            with any clock skew (must be at least 300 ms and at most 100 ms \
+            relative to muskie)");
+
+        let skews = [ skew, MantaClockSkew {
+            mcs_source : String::from(MRI_SKEW_CLIENT),
+            mcs_min : Some(ms(-300)),
+            mcs_max : Some(ms(-250)),
+            mcs_estimate : ms(-250),
+            mcs_nconstraints : 2
+        } ];
+        assert_eq!(mri_skew_for(&skews, "test"), ms(0));
+        assert_eq!(mri_skew_for(&skews, MRI_SKEW_CLIENT), ms(-250));
+        assert_eq!(mri_skew_for(&skews, MRI_SKEW_LOAD_BALANCER), ms(0));
+    }
+}
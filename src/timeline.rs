@@ -15,33 +15,85 @@
 //!
 //! Timelines are constructed using a `TimelineBuilder`.
 //!
-//! The current expectation is that events will not overlap in time on a
-//! timeline (though it's possible for there to be gaps between events).
+//! Events may also be assigned to a named lane (e.g., one for each storage
+//! node involved in a request), which represents one component doing one thing
+//! at a time.  Events in the same lane are expected not to overlap in time
+//! (though it's possible for there to be gaps between events), but events in
+//! different lanes can overlap arbitrarily, as when Muskie streams data to
+//! several storage nodes at once.  Events that aren't assigned to a lane are
+//! all treated as belonging to one implicit lane.
 //!
+//! Many of the times we put on a timeline are not known exactly.  Some come
+//! from sources with coarse resolution (e.g., an HTTP "Date" header, which is
+//! only precise to the second), and some are inferred from other events (e.g.,
+//! by working backwards from a known end time with `prepend()`).  Each event
+//! records the earliest and latest times at which it could have started, along
+//! with a `TimelineProvenance` describing where its time came from.
+//!
+
+///
+/// Describes where an event's start time came from.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineProvenance {
+    /// The time was logged directly by the component where the event happened.
+    Measured,
+    ///
+    /// The time was inferred by working backwards from a later event (see
+    /// `TimelineBuilder::prepend()`).
+    ///
+    Inferred,
+    /// The time came from an HTTP header (e.g., "Date").
+    HeaderDerived,
+}
+
+impl std::fmt::Display for TimelineProvenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            TimelineProvenance::Measured => "measured",
+            TimelineProvenance::Inferred => "inferred",
+            TimelineProvenance::HeaderDerived => "from header",
+        })
+    }
+}
 
 ///
 /// Timelines represent a sequence of events at specific wall clock times.
 /// Create these using a `TimelineBuilder`.  See module-level documentation for
 /// details.
 ///
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Timeline {
     ///
     /// The list of events in this timeline, in wall-clock order.
     ///
+    #[serde(rename = "events")]
     tl_events : Vec<TimelineEvent>,
 
     ///
-    /// The end time of this timeline.  For our purposes, timelines are always
-    /// anchored by an end time (which must be provided when constructing them),
-    /// so this is meaningful even for a timeline with no actual events.
+    /// The names of the lanes used by events in this timeline, in the order
+    /// they were first used.
     ///
+    #[serde(rename = "lanes")]
+    tl_lanes : Vec<String>,
+
+    ///
+    /// The end time of this timeline.  For a timeline anchored by an end time,
+    /// this is that time, which is meaningful even for a timeline with no
+    /// actual events.  Otherwise, this is the latest time at which an event
+    /// ended (or the start time, if that's later).
+    ///
+    #[serde(rename = "end", serialize_with = "super::json::mri_json_time")]
     tl_end : chrono::DateTime<chrono::Utc>,
 
     ///
-    /// The wall-clock time for the start of this timeline.  For a timeline with
-    /// no events, the start time will match the end time.
+    /// The wall-clock time for the start of this timeline.  This is the start
+    /// of the first event, or the anchoring start time if that's earlier.  For
+    /// a timeline with no events, the start time matches whichever anchor it
+    /// has.
     ///
+    #[serde(rename = "start", serialize_with = "super::json::mri_json_time")]
     tl_start : chrono::DateTime<chrono::Utc>
 }
 
@@ -57,6 +109,15 @@ impl Timeline
         return self.tl_end - self.tl_start;
     }
 
+    ///
+    /// Returns the end time of the timeline.
+    ///
+    pub fn wall_end(&self)
+        -> chrono::DateTime<chrono::Utc>
+    {
+        return self.tl_end;
+    }
+
     ///
     /// Returns the start time of the timeline.
     ///
@@ -75,18 +136,279 @@ impl Timeline
     {
         return &self.tl_events;
     }
+
+    ///
+    /// Returns the names of the lanes used in this timeline, in the order they
+    /// were first used.  This is empty if no events were assigned to lanes.
+    ///
+    pub fn lanes(&self)
+        -> &[String]
+    {
+        return &self.tl_lanes;
+    }
+
+    ///
+    /// Returns the intervals of this timeline not covered by any of its events,
+    /// in order.  Each hole is described by the event that most recently ended
+    /// before it (if any), its start time, and its duration.  Subtimelines are
+    /// treated as single events here, and existing gap pseudo-events count as
+    /// covering the time they represent.
+    ///
+    pub fn holes(&self)
+        -> Vec<(Option<&TimelineEvent>, chrono::DateTime<chrono::Utc>,
+        chrono::Duration)>
+    {
+        let mut rv = Vec::new();
+        let mut covered = self.tl_start;
+        let mut last : Option<&TimelineEvent> = None;
+
+        for event in &self.tl_events {
+            if event.te_wall_start > covered {
+                rv.push((last, covered, event.te_wall_start - covered));
+            }
+
+            if event.wall_end() >= covered {
+                covered = event.wall_end();
+                last = Some(event);
+            }
+        }
+
+        if self.tl_end > covered {
+            rv.push((last, covered, self.tl_end - covered));
+        }
+
+        return rv;
+    }
+
+    ///
+    /// Returns the total time in this timeline not covered by any of its
+    /// events (other than gap pseudo-events).  See `holes()`.
+    ///
+    pub fn unaccounted(&self)
+        -> chrono::Duration
+    {
+        let holes = self.holes().iter().fold(chrono::Duration::zero(),
+            |total, (_, _, duration)| total + *duration);
+        let gaps = self.tl_events.iter().filter(|e| e.te_gap).fold(
+            chrono::Duration::zero(), |total, e| total + e.te_duration);
+        return holes + gaps;
+    }
+
+    ///
+    /// Returns a copy of this timeline (and, recursively, its subtimelines)
+    /// with a pseudo-event inserted for each hole at least `threshold` long.
+    /// These events are marked as gaps (see `TimelineEvent::is_gap()`) and
+    /// labeled by calling `label` with the events on either side of the hole
+    /// (the second of which is `None` for a hole at the end of the timeline).
+    ///
+    pub fn with_gaps(&self, threshold : chrono::Duration,
+        label : &dyn Fn(Option<&TimelineEvent>, Option<&TimelineEvent>)
+        -> String)
+        -> Timeline
+    {
+        let mut gaps = Vec::new();
+        for (before, start, duration) in self.holes() {
+            if duration < threshold {
+                continue;
+            }
+
+            let end = start + duration;
+            let after = self.tl_events.iter().find(|e| e.te_wall_start >= end);
+            gaps.push(TimelineEvent {
+                te_wall_start : start,
+                te_earliest_start : start,
+                te_latest_start : start,
+                te_provenance : TimelineProvenance::Inferred,
+                te_relative_start : start - self.tl_start,
+                te_duration : duration,
+                te_label : label(before, after),
+                te_lane : None,
+                te_source : None,
+                te_gap : true,
+                te_timeline : None
+            });
+        }
+
+        let mut events : Vec<TimelineEvent> = self.tl_events.iter().map(|e| {
+            let mut e = e.clone();
+            e.te_timeline = e.te_timeline.map(
+                |t| Box::new(t.with_gaps(threshold, label)));
+            e
+        }).collect();
+        events.extend(gaps);
+        events.sort_by_key(|e| e.te_wall_start);
+
+        return Timeline {
+            tl_events : events,
+            tl_lanes : self.tl_lanes.clone(),
+            tl_end : self.tl_end,
+            tl_start : self.tl_start
+        };
+    }
+
+    ///
+    /// Returns a copy of this timeline in which each run of consecutive events
+    /// that `group` assigns to the same group is replaced by a single event
+    /// (labeled with the group's name) whose subtimeline contains the events
+    /// of the run.  Events that `group` doesn't assign to any group are left
+    /// alone, except that those that fall between two events of the same group
+    /// (e.g., records logged while some handler was running) are included in
+    /// that group rather than splitting it in two.
+    ///
+    /// A run that takes less than `min_duration` in total is not grouped: its
+    /// events are left alone as though `group` hadn't assigned them to any
+    /// group.  This avoids cluttering the timeline with groups that took no
+    /// appreciable time, and lets the groups on either side of such a run
+    /// join up if they're the same.
+    ///
+    pub fn grouped(&self, group : &dyn Fn(&TimelineEvent) -> Option<String>,
+        min_duration : chrono::Duration)
+        -> Timeline
+    {
+        let mut names : Vec<Option<String>> = self.tl_events.iter().map(
+            group).collect();
+
+        //
+        // Find the runs (ignoring ungrouped events between events of the same
+        // group, as below) and drop the names of events in runs that are too
+        // short.  Each run is the index of its first and last event.
+        //
+        let mut runs : Vec<(usize, usize)> = Vec::new();
+        for (i, name) in names.iter().enumerate() {
+            let name = match name {
+                Some(name) => name,
+                None => continue
+            };
+
+            match runs.last_mut() {
+                Some((first, ref mut last)) if
+                    names[*first].as_ref() == Some(name) => *last = i,
+                _ => runs.push((i, i))
+            }
+        }
+        for (first, last) in runs {
+            let start = self.tl_events[first].te_wall_start;
+            let end = self.tl_events[first..=last].iter().map(
+                |e| e.wall_end()).max().unwrap();
+            if end - start < min_duration {
+                for name in &mut names[first..=last] {
+                    *name = None;
+                }
+            }
+        }
+
+        let mut events : Vec<TimelineEvent> = Vec::new();
+        let mut run : Option<(String, Vec<TimelineEvent>)> = None;
+        let mut pending : Vec<TimelineEvent> = Vec::new();
+
+        for (event, name) in self.tl_events.iter().zip(names) {
+            let name = match name {
+                Some(name) => name,
+                None => {
+                    if run.is_some() {
+                        pending.push(event.clone());
+                    } else {
+                        events.push(event.clone());
+                    }
+                    continue;
+                }
+            };
+
+            match run {
+                Some((ref run_name, ref mut members)) if *run_name == name => {
+                    members.append(&mut pending);
+                    members.push(event.clone());
+                    continue;
+                },
+                _ => ()
+            }
+
+            if let Some((run_name, members)) = run.take() {
+                events.push(self.group_event(run_name, members));
+            }
+            events.append(&mut pending);
+            run = Some((name, vec![ event.clone() ]));
+        }
+
+        if let Some((run_name, members)) = run.take() {
+            events.push(self.group_event(run_name, members));
+        }
+        events.append(&mut pending);
+
+        return Timeline {
+            tl_events : events,
+            tl_lanes : self.tl_lanes.clone(),
+            tl_end : self.tl_end,
+            tl_start : self.tl_start
+        };
+    }
+
+    ///
+    /// Returns an event on this timeline labeled `label` whose subtimeline
+    /// contains `members`, which must be non-empty and in order.  See
+    /// `grouped()`.
+    ///
+    fn group_event(&self, label : String, members : Vec<TimelineEvent>)
+        -> TimelineEvent
+    {
+        let first = &members[0];
+        let start = first.te_wall_start;
+        let end = members.iter().map(|e| e.wall_end()).max().unwrap();
+        let mut summary = TimelineEvent {
+            te_wall_start : start,
+            te_earliest_start : first.te_earliest_start,
+            te_latest_start : first.te_latest_start,
+            te_provenance : first.te_provenance,
+            te_relative_start : start - self.tl_start,
+            te_duration : end - start,
+            te_label : label,
+            te_lane : first.te_lane.clone(),
+            te_source : first.te_source.clone(),
+            te_gap : false,
+            te_timeline : None
+        };
+
+        let lanes = self.tl_lanes.iter().filter(|lane| members.iter().any(
+            |e| e.te_lane.as_ref() == Some(*lane))).cloned().collect();
+        summary.te_timeline = Some(Box::new(Timeline {
+            tl_events : members.into_iter().map(|mut e| {
+                e.te_relative_start = e.te_wall_start - start;
+                e
+            }).collect(),
+            tl_lanes : lanes,
+            tl_end : end,
+            tl_start : start
+        }));
+        return summary;
+    }
 }
 
 ///
 /// Represents one event in a timeline.
 ///
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct TimelineEvent {
+    #[serde(rename = "start", serialize_with = "super::json::mri_json_time")]
     te_wall_start : chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "earliest_start",
+        serialize_with = "super::json::mri_json_time")]
+    te_earliest_start : chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "latest_start",
+        serialize_with = "super::json::mri_json_time")]
+    te_latest_start : chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "provenance")]
+    te_provenance : TimelineProvenance,
+    #[serde(rename = "relative_start_ms",
+        serialize_with = "super::json::mri_json_duration")]
     te_relative_start : chrono::Duration,
+    #[serde(rename = "duration_ms",
+        serialize_with = "super::json::mri_json_duration")]
     te_duration : chrono::Duration,
-    te_label : String,
-    te_timeline : Option<Box<Timeline>>
+    #[serde(rename = "label")]      te_label : String,
+    #[serde(rename = "lane")]       te_lane : Option<String>,
+    #[serde(rename = "source")]     te_source : Option<String>,
+    #[serde(rename = "gap")]        te_gap : bool,
+    #[serde(rename = "timeline")]   te_timeline : Option<Box<Timeline>>
 }
 
 impl TimelineEvent {
@@ -94,7 +416,31 @@ impl TimelineEvent {
     pub fn wall_start(&self)
         -> chrono::DateTime<chrono::Utc>
     {
-        return self.te_wall_start.clone();
+        return self.te_wall_start;
+    }
+
+    ///
+    /// Returns the earliest wall clock time when this event could have
+    /// started.
+    ///
+    pub fn earliest_start(&self)
+        -> chrono::DateTime<chrono::Utc>
+    {
+        return self.te_earliest_start;
+    }
+
+    /// Returns the latest wall clock time when this event could have started.
+    pub fn latest_start(&self)
+        -> chrono::DateTime<chrono::Utc>
+    {
+        return self.te_latest_start;
+    }
+
+    /// Returns where this event's start time came from.
+    pub fn provenance(&self)
+        -> TimelineProvenance
+    {
+        return self.te_provenance;
     }
 
     ///
@@ -104,7 +450,7 @@ impl TimelineEvent {
     pub fn duration(&self)
         -> chrono::Duration
     {
-        return self.te_duration.clone();
+        return self.te_duration;
     }
 
     ///
@@ -123,7 +469,7 @@ impl TimelineEvent {
     pub fn relative_start(&self)
         -> chrono::Duration
     {
-        return self.te_relative_start.clone(); // TODO
+        return self.te_relative_start;
     }
 
     ///
@@ -135,24 +481,49 @@ impl TimelineEvent {
         return self.te_label.clone();
     }
 
+    ///
+    /// Returns the name of the component that reported this event (e.g.,
+    /// "muskie"), if known.  See `TimelineBuilder::set_source()`.
+    ///
+    pub fn source(&self)
+        -> Option<&str>
+    {
+        return self.te_source.as_deref();
+    }
+
+    ///
+    /// Returns whether this is a pseudo-event representing time not accounted
+    /// for by any other event (see `Timeline::with_gaps()`).
+    ///
+    pub fn is_gap(&self)
+        -> bool
+    {
+        return self.te_gap;
+    }
+
+    /// Returns the name of the lane this event belongs to, if any.
+    pub fn lane(&self)
+        -> Option<&str>
+    {
+        return self.te_lane.as_deref();
+    }
+
     ///
     /// For events that themselves summarize a number of events in a
     /// subtimeline, returns the subtimeline.  If this is a simple event with no
     /// associated subtimeline, returns `None`.
     ///
     pub fn subtimeline(&self)
-        -> Option<&Box<Timeline>>
+        -> Option<&Timeline>
     {
-        match &self.te_timeline {
-            Some(t) => Some(&t),
-            None => None
-        }
+        self.te_timeline.as_deref()
     }
 }
 
 ///
 /// Consumers use an instance of `TimelineBuilder` to construct a timeline.  See
-/// `TimelineBuilder::new_ending()` to construct a builder.
+/// `TimelineBuilder::new_ending()`, `TimelineBuilder::new_starting()`, and
+/// `TimelineBuilder::new_unanchored()` to construct a builder.
 ///
 /// Some fields in the `Timeline` can only be calculated once all events in the
 /// `Timeline` are known, so separating construction in this way allows us to
@@ -163,63 +534,188 @@ impl TimelineEvent {
 pub struct TimelineBuilder {
     /// The sequence of events in the timeline (still under construction)
     tlb_events : Vec<TimelineBuilderEvent>,
-    /// The end time of the timeline
-    tlb_end : chrono::DateTime<chrono::Utc>,
+    /// The start time of the timeline, if it's anchored at its start
+    tlb_start : Option<chrono::DateTime<chrono::Utc>>,
+    /// The end time of the timeline, if it's anchored at its end
+    tlb_end : Option<chrono::DateTime<chrono::Utc>>,
+    /// The names of lanes used so far, in the order they were first used
+    tlb_lanes : Vec<String>,
+    /// The source assigned to events as they're added (see `set_source()`)
+    tlb_source : Option<String>,
 }
 
 impl TimelineBuilder {
     ///
-    /// This is the interface through which consumers begin constructing a
-    /// timeline.  `end` is a wall-clock time for the end of the timeline.  This
-    /// might seem odd for a general-purpose facility, but it works well for
-    /// this program because we're typically building timelines from logs, and
-    /// the log entry timestamp itself is a natural end point for the timeline.
-    /// In the future, we could also have a `new_starting()` constructor or
-    /// other constructors as make sense.
+    /// Begin constructing a timeline anchored by its end time `end`.  This
+    /// works well for this program because we're typically building timelines
+    /// from logs, and the log entry timestamp itself is a natural end point for
+    /// the timeline.  Events can be added working backwards from the end using
+    /// `prepend()`.
     ///
     pub fn new_ending(end: chrono::DateTime<chrono::Utc>)
         -> TimelineBuilder
     {
         return TimelineBuilder {
             tlb_events : Vec::new(),
-            tlb_end : end.clone()
+            tlb_start : None,
+            tlb_end : Some(end),
+            tlb_lanes : Vec::new(),
+            tlb_source : None
+        }
+    }
+
+    ///
+    /// Begin constructing a timeline anchored by its start time `start`.
+    /// Events can be added working forwards from the start using `append()`.
+    ///
+    pub fn new_starting(start: chrono::DateTime<chrono::Utc>)
+        -> TimelineBuilder
+    {
+        return TimelineBuilder {
+            tlb_events : Vec::new(),
+            tlb_start : Some(start),
+            tlb_end : None,
+            tlb_lanes : Vec::new(),
+            tlb_source : None
+        }
+    }
+
+    ///
+    /// Begin constructing a timeline that isn't anchored at either end.  Its
+    /// start and end are determined entirely by the events added to it.  If no
+    /// events are added, it has neither, so `try_finish()` returns `None` and
+    /// `finish()` panics.
+    ///
+    pub fn new_unanchored()
+        -> TimelineBuilder
+    {
+        return TimelineBuilder {
+            tlb_events : Vec::new(),
+            tlb_start : None,
+            tlb_end : None,
+            tlb_lanes : Vec::new(),
+            tlb_source : None
         }
     }
 
+    ///
+    /// Sets the source (the name of the component that reported them) for
+    /// events added to the timeline from now on.  Gap analysis uses this to
+    /// suggest what might account for time between events.
+    ///
+    pub fn set_source(&mut self, source : &str)
+    {
+        self.tlb_source = Some(String::from(source));
+    }
+
     ///
     /// Add an event to the timeline starting at wall-clock time `start` for
-    /// duration `duration`.  If `subtimeline` is specified, this behaves like
-    /// `add_timeline()`.
+    /// duration `duration`.  The start time is taken to be exactly known.
     ///
     pub fn add(&mut self, label : &str, start : &chrono::DateTime<chrono::Utc>,
-        duration : &chrono::Duration, subtimeline : Option<Box<Timeline>>)
+        duration : &chrono::Duration)
     {
-        //
-        // TODO This interface shouldn't allow a subtimeline having a start time
-        // that differs from `start`.
-        //
-        self.tlb_events.insert(0, TimelineBuilderEvent {
-            tbe_wall_start : start.clone(),
-            tbe_duration : duration.clone(),
-            tbe_label: String::from(label).clone(),
-            tbe_timeline: subtimeline
+        self.insert(TimelineBuilderEvent {
+            tbe_wall_start : *start,
+            tbe_earliest_start : *start,
+            tbe_latest_start : *start,
+            tbe_provenance : TimelineProvenance::Measured,
+            tbe_duration : *duration,
+            tbe_label: String::from(label),
+            tbe_lane: None,
+            tbe_source: self.tlb_source.clone(),
+            tbe_timeline: None
         });
+    }
+
+    ///
+    /// Add an event to lane `lane` of the timeline, starting at wall-clock
+    /// time `start` for duration `duration`.  This event may overlap events in
+    /// other lanes.
+    ///
+    pub fn add_to_lane(&mut self, lane : &str, label : &str,
+        start : &chrono::DateTime<chrono::Utc>, duration : &chrono::Duration)
+    {
+        if !self.tlb_lanes.iter().any(|l| l == lane) {
+            self.tlb_lanes.push(String::from(lane));
+        }
+
+        self.insert(TimelineBuilderEvent {
+            tbe_wall_start : *start,
+            tbe_earliest_start : *start,
+            tbe_latest_start : *start,
+            tbe_provenance : TimelineProvenance::Measured,
+            tbe_duration : *duration,
+            tbe_label: String::from(label),
+            tbe_lane: Some(String::from(lane)),
+            tbe_source: self.tlb_source.clone(),
+            tbe_timeline: None
+        });
+    }
+
+    ///
+    /// Add an event to the timeline whose start time is not known exactly.
+    /// `start` is the nominal start time (e.g., the time reported in a
+    /// header), and the event actually started somewhere between `earliest`
+    /// and `latest`.
+    ///
+    pub fn add_bounded(&mut self, label : &str,
+        start : &chrono::DateTime<chrono::Utc>,
+        earliest : &chrono::DateTime<chrono::Utc>,
+        latest : &chrono::DateTime<chrono::Utc>,
+        duration : &chrono::Duration, provenance : TimelineProvenance)
+    {
+        self.insert(TimelineBuilderEvent {
+            tbe_wall_start : *start,
+            tbe_earliest_start : std::cmp::min(*earliest, *start),
+            tbe_latest_start : std::cmp::max(*latest, *start),
+            tbe_provenance : provenance,
+            tbe_duration : *duration,
+            tbe_label: String::from(label),
+            tbe_lane: None,
+            tbe_source: self.tlb_source.clone(),
+            tbe_timeline: None
+        });
+    }
+
+    fn insert(&mut self, event : TimelineBuilderEvent)
+    {
+        self.tlb_events.insert(0, event);
 
         // TODO doing it like this makes this O(N^2) to insert N events
-        self.tlb_events.sort_by(|a, b|
-            (&a.tbe_wall_start).partial_cmp(&b.tbe_wall_start).unwrap());
+        self.tlb_events.sort_by_key(|e| e.tbe_wall_start);
     }
 
     ///
     /// Add `timeline` as a subtimeline to the current timeline.  This creates
     /// an event on the current timeline that refers to the subtimeline.  This
     /// indicates that all the events on the subtimeline happened at the
-    /// designated time on the current timeline as well.
+    /// designated time on the current timeline as well.  The event's start
+    /// time and duration are always those of the subtimeline, and its start
+    /// time is exactly as certain as that of the subtimeline's first event.
     ///
     pub fn add_timeline(&mut self, label : &str, timeline : Box<Timeline>)
     {
-        self.add(label, &timeline.tl_start.clone(), &timeline.total_elapsed(),
-            Some(timeline));
+        let start = timeline.tl_start;
+        let (earliest, latest, provenance) = match timeline.tl_events.first() {
+            Some(first) if first.te_wall_start == start => {
+                (first.te_earliest_start, first.te_latest_start,
+                    first.te_provenance)
+            },
+            _ => (start, start, TimelineProvenance::Measured)
+        };
+
+        self.insert(TimelineBuilderEvent {
+            tbe_wall_start : start,
+            tbe_earliest_start : earliest,
+            tbe_latest_start : latest,
+            tbe_provenance : provenance,
+            tbe_duration : timeline.total_elapsed(),
+            tbe_label: String::from(label),
+            tbe_lane: None,
+            tbe_source: self.tlb_source.clone(),
+            tbe_timeline: Some(timeline)
+        });
     }
 
     ///
@@ -227,45 +723,128 @@ impl TimelineBuilder {
     /// creates a new event ending at the current start of the timeline having
     /// duration `duration`.
     ///
+    /// The new event's start time is inferred, and its bounds accumulate the
+    /// bounds of the event it precedes.  We assume durations are truncated to
+    /// whole microseconds (as Muskie's handler timers are), so each prepended
+    /// event could have started up to a microsecond earlier than the sum of
+    /// the durations would suggest.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timeline has no events and no anchor (i.e., it was
+    /// created with `new_unanchored()`), since then it has no start.
+    ///
     pub fn prepend(&mut self, label : &str, duration : &chrono::Duration)
     {
-        let end_wall_time = if self.tlb_events.len() == 0 {
-            self.tlb_end
-        } else {
-            self.tlb_events[0].tbe_wall_start
+        let (end_wall_time, end_earliest, end_latest) =
+            match self.tlb_events.first() {
+                Some(first) => (first.tbe_wall_start, first.tbe_earliest_start,
+                    first.tbe_latest_start),
+                None => {
+                    let anchor = self.tlb_start.or(self.tlb_end).expect(
+                        "prepend() on an empty, unanchored timeline");
+                    (anchor, anchor, anchor)
+                }
+            };
+
+        let start = end_wall_time - *duration;
+        let earliest = end_earliest - *duration -
+            chrono::Duration::microseconds(1);
+        let latest = end_latest - *duration;
+        self.add_bounded(label, &start, &earliest, &latest, duration,
+            TimelineProvenance::Inferred);
+    }
+
+    ///
+    /// Append an event of length `duration` to the current timeline.  This
+    /// creates a new event starting when the last event of the timeline ended
+    /// (or at the anchoring start time, if there are no events yet).  The new
+    /// event's start time is inferred, and its bounds accumulate the bounds of
+    /// the event it follows, just as with `prepend()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timeline has no events and no anchor (i.e., it was
+    /// created with `new_unanchored()`), since then it has no end.
+    ///
+    pub fn append(&mut self, label : &str, duration : &chrono::Duration)
+    {
+        let last = self.tlb_events.iter().max_by_key(
+            |e| e.tbe_wall_start + e.tbe_duration);
+        let (start, earliest, latest) = match last {
+            Some(last) => (last.tbe_wall_start + last.tbe_duration,
+                last.tbe_earliest_start + last.tbe_duration,
+                last.tbe_latest_start + last.tbe_duration +
+                chrono::Duration::microseconds(1)),
+            None => {
+                let anchor = self.tlb_start.or(self.tlb_end).expect(
+                    "append() on an empty, unanchored timeline");
+                (anchor, anchor, anchor)
+            }
         };
 
-        self.add(label, &(end_wall_time - *duration), duration, None);
+        self.add_bounded(label, &start, &earliest, &latest, duration,
+            TimelineProvenance::Inferred);
     }
 
     ///
     /// Returns a fully-constructed `Timeline` object, consume the builder
     /// itself in the process.
     ///
-    pub fn finish(mut self)
+    /// # Panics
+    ///
+    /// Panics if the timeline has no events and no anchor (i.e., it was
+    /// created with `new_unanchored()`), since then it has no start or end.
+    /// Use `try_finish()` for such timelines.
+    ///
+    pub fn finish(self)
         -> Timeline
     {
-        if self.tlb_events.len() == 0 {
-            return Timeline {
-                tl_events : Vec::new(),
-                tl_end: self.tlb_end,
-                tl_start: self.tlb_end
-            }
-        }
+        return self.try_finish().expect(
+            "finish() on an empty, unanchored timeline");
+    }
 
-        let basetime = self.tlb_events[0].tbe_wall_start;
-        return Timeline {
+    ///
+    /// Like `finish()`, but returns `None` instead of panicking if the timeline
+    /// has no events and no anchor.
+    ///
+    pub fn try_finish(self)
+        -> Option<Timeline>
+    {
+        let first_start = self.tlb_events.first().map(|e| e.tbe_wall_start);
+        let last_end = self.tlb_events.iter().map(
+            |e| e.tbe_wall_start + e.tbe_duration).max();
+
+        let basetime = match (self.tlb_start, first_start) {
+            (Some(anchor), Some(first)) => std::cmp::min(anchor, first),
+            (Some(anchor), None) => anchor,
+            (None, Some(first)) => first,
+            (None, None) => self.tlb_end?
+        };
+        let endtime = match self.tlb_end {
+            Some(anchor) => anchor,
+            None => std::cmp::max(basetime, last_end.unwrap_or(basetime))
+        };
+
+        return Some(Timeline {
             tl_events: self.tlb_events.into_iter().map(
                 |builder_event| TimelineEvent {
                     te_label: builder_event.tbe_label,
                     te_wall_start: builder_event.tbe_wall_start,
+                    te_earliest_start: builder_event.tbe_earliest_start,
+                    te_latest_start: builder_event.tbe_latest_start,
+                    te_provenance: builder_event.tbe_provenance,
                     te_duration: builder_event.tbe_duration,
+                    te_lane: builder_event.tbe_lane,
+                    te_source: builder_event.tbe_source,
+                    te_gap: false,
                     te_timeline: builder_event.tbe_timeline,
                     te_relative_start: builder_event.tbe_wall_start - basetime
                 }).collect(),
+            tl_lanes: self.tlb_lanes,
             tl_start: basetime,
-            tl_end: self.tlb_end
-        }
+            tl_end: endtime
+        });
     }
 }
 
@@ -277,7 +856,380 @@ impl TimelineBuilder {
 #[derive(Debug)]
 struct TimelineBuilderEvent {
     tbe_wall_start : chrono::DateTime<chrono::Utc>,
+    tbe_earliest_start : chrono::DateTime<chrono::Utc>,
+    tbe_latest_start : chrono::DateTime<chrono::Utc>,
+    tbe_provenance : TimelineProvenance,
     tbe_duration : chrono::Duration,
     tbe_label : String,
+    tbe_lane : Option<String>,
+    tbe_source : Option<String>,
     tbe_timeline : Option<Box<Timeline>>
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn us(n : i64)
+        -> chrono::Duration
+    {
+        return chrono::Duration::microseconds(n);
+    }
+
+    ///
+    /// Returns a timeline of back-to-back events, each described by its label
+    /// and its duration in microseconds.
+    ///
+    fn timeline(events : &[(&str, i64)])
+        -> Timeline
+    {
+        let start = chrono::Utc.ymd(2019, 5, 9).and_hms(21, 34, 23);
+        let mut builder = TimelineBuilder::new_starting(start);
+        let mut when = start;
+        for (label, duration) in events {
+            builder.add(label, &when, &us(*duration));
+            when = when + us(*duration);
+        }
+        return builder.finish();
+    }
+
+    /// Groups events by their label's prefix (up to the first "-").
+    fn by_prefix(event : &TimelineEvent)
+        -> Option<String>
+    {
+        let label = event.label();
+        return label.find('-').map(|i| String::from(&label[..i]));
+    }
+
+    fn summary(timeline : &Timeline)
+        -> Vec<(String, usize)>
+    {
+        return timeline.events().iter().map(|e| (e.label(),
+            e.subtimeline().map(|t| t.events().len()).unwrap_or(0))).collect();
+    }
+
+    #[test]
+    fn grouped_runs()
+    {
+        let grouped = timeline(&[
+            ("a-1", 5000), ("a-2", 5000), ("logged", 1), ("a-3", 5000),
+            ("b-1", 5000), ("other", 5000), ("a-4", 5000)
+        ]).grouped(&by_prefix, chrono::Duration::milliseconds(1));
+
+        assert_eq!(summary(&grouped), vec![
+            (String::from("a"), 4), (String::from("b"), 1),
+            (String::from("other"), 0), (String::from("a"), 1)
+        ]);
+        assert_eq!(grouped.events()[0].duration(), us(15001));
+    }
+
+    ///
+    /// Runs that take less than the minimum duration aren't grouped, and the
+    /// groups on either side of them join up.
+    ///
+    #[test]
+    fn grouped_skips_short_runs()
+    {
+        let grouped = timeline(&[
+            ("s-1", 10), ("a-1", 5), ("a-2", 5000), ("m-1", 8000),
+            ("s-2", 10), ("r-1", 1), ("m-2", 3000), ("r-2", 5),
+            ("t-1", 90000)
+        ]).grouped(&by_prefix, chrono::Duration::milliseconds(1));
+
+        assert_eq!(summary(&grouped), vec![
+            (String::from("s-1"), 0), (String::from("a"), 2),
+            (String::from("m"), 4), (String::from("r-2"), 0),
+            (String::from("t"), 1)
+        ]);
+        assert!(grouped.events().iter().all(
+            |e| e.duration() >= chrono::Duration::milliseconds(1) ||
+            e.subtimeline().is_none()));
+    }
+
+    ///
+    /// An unanchored timeline spans the events added to it, and there's no
+    /// timeline at all if there are no events.
+    ///
+    #[test]
+    fn unanchored()
+    {
+        assert!(TimelineBuilder::new_unanchored().try_finish().is_none());
+
+        let start = chrono::Utc.ymd(2019, 5, 9).and_hms(21, 34, 23);
+        let mut builder = TimelineBuilder::new_unanchored();
+        builder.add_to_lane("b", "second", &(start + us(500)), &us(2000));
+        builder.add_to_lane("a", "first", &start, &us(1000));
+        let timeline = builder.try_finish().unwrap();
+        assert_eq!(timeline.wall_start(), start);
+        assert_eq!(timeline.wall_end(), start + us(2500));
+        assert_eq!(summary(&timeline), vec![
+            (String::from("first"), 0), (String::from("second"), 0)
+        ]);
+
+        let empty = TimelineBuilder::new_ending(start).finish();
+        assert_eq!(empty.wall_start(), start);
+        assert_eq!(empty.wall_end(), start);
+        assert!(empty.events().is_empty());
+    }
+
+    ///
+    /// `finish()` has nothing to anchor an empty, unanchored timeline to.
+    ///
+    #[test]
+    #[should_panic(expected = "finish() on an empty, unanchored timeline")]
+    fn unanchored_finish_empty()
+    {
+        TimelineBuilder::new_unanchored().finish();
+    }
+
+    ///
+    /// Appended events follow one another from the start anchor, and each one
+    /// could have started up to a microsecond later than the one before it
+    /// suggests, since the durations are truncated.
+    ///
+    #[test]
+    fn starting_append()
+    {
+        let start = chrono::Utc.ymd(2019, 5, 9).and_hms(21, 34, 23);
+        let mut builder = TimelineBuilder::new_starting(start);
+        builder.append("first", &us(1000));
+        builder.append("second", &us(2000));
+        builder.append("third", &us(0));
+        let timeline = builder.finish();
+
+        assert_eq!(timeline.wall_start(), start);
+        assert_eq!(timeline.wall_end(), start + us(3000));
+        let events = timeline.events();
+        assert_eq!(events.len(), 3);
+        let expected = [
+            ("first", 0, 0, 0),
+            ("second", 1000, 1000, 1001),
+            ("third", 3000, 3000, 3002)
+        ];
+        for (event, (label, wall, earliest, latest)) in
+            events.iter().zip(expected.iter()) {
+            assert_eq!(event.label(), *label);
+            assert_eq!(event.wall_start(), start + us(*wall));
+            assert_eq!(event.earliest_start(), start + us(*earliest));
+            assert_eq!(event.latest_start(), start + us(*latest));
+            assert_eq!(event.provenance(), TimelineProvenance::Inferred);
+        }
+
+        let empty = TimelineBuilder::new_starting(start).finish();
+        assert_eq!(empty.wall_start(), start);
+        assert_eq!(empty.wall_end(), start);
+    }
+
+    #[test]
+    #[should_panic(expected = "append() on an empty, unanchored timeline")]
+    fn unanchored_append_empty()
+    {
+        TimelineBuilder::new_unanchored().append("first", &us(1000));
+    }
+
+    ///
+    /// Returns a timeline starting at `start` with events at the given offsets
+    /// and durations (in microseconds), and ending when the last one ends.
+    ///
+    fn placed(start : chrono::DateTime<chrono::Utc>,
+        events : &[(&str, i64, i64)])
+        -> Timeline
+    {
+        let mut builder = TimelineBuilder::new_starting(start);
+        for (label, offset, duration) in events {
+            builder.add(label, &(start + us(*offset)), &us(*duration));
+        }
+        return builder.finish();
+    }
+
+    /// Labels a gap with the labels of the events on either side.
+    fn gap_label(before : Option<&TimelineEvent>,
+        after : Option<&TimelineEvent>)
+        -> String
+    {
+        let name = |e : Option<&TimelineEvent>| e.map_or(String::from("-"),
+            |e| e.label());
+        return format!("gap {}/{}", name(before), name(after));
+    }
+
+    fn holes(timeline : &Timeline)
+        -> Vec<(String, i64, i64)>
+    {
+        return timeline.holes().iter().map(|(before, start, duration)| (
+            before.map_or(String::from("-"), |e| e.label()),
+            (*start - timeline.wall_start()).num_microseconds().unwrap(),
+            duration.num_microseconds().unwrap())).collect();
+    }
+
+    ///
+    /// A hole exactly as long as the threshold gets a gap event, and a shorter
+    /// one doesn't, though both count as unaccounted time.
+    ///
+    #[test]
+    fn gaps_threshold()
+    {
+        let threshold = chrono::Duration::milliseconds(
+            crate::MRI_GAP_THRESHOLD_MS);
+        let start = chrono::Utc.ymd(2019, 5, 9).and_hms(21, 34, 23);
+        let timeline = placed(start, &[
+            ("a", 0, 1000), ("b", 11000, 1000), ("c", 21999, 1000)
+        ]);
+        assert_eq!(holes(&timeline), vec![
+            (String::from("a"), 1000, 10000), (String::from("b"), 12000, 9999)
+        ]);
+        assert_eq!(timeline.unaccounted(), us(19999));
+
+        let gapped = timeline.with_gaps(threshold, &gap_label);
+        assert_eq!(summary(&gapped), vec![
+            (String::from("a"), 0), (String::from("gap a/b"), 0),
+            (String::from("b"), 0), (String::from("c"), 0)
+        ]);
+        let gap = &gapped.events()[1];
+        assert!(gap.is_gap());
+        assert_eq!(gap.wall_start(), start + us(1000));
+        assert_eq!(gap.duration(), threshold);
+        assert_eq!(holes(&gapped), vec![ (String::from("b"), 12000, 9999) ]);
+        assert_eq!(gapped.unaccounted(), us(19999));
+
+        let gapped = timeline.with_gaps(threshold + us(1), &gap_label);
+        assert!(gapped.events().iter().all(|e| !e.is_gap()));
+    }
+
+    ///
+    /// Time covered by any event isn't a hole, even when events overlap or one
+    /// is contained in another.
+    ///
+    #[test]
+    fn gaps_overlapping()
+    {
+        let start = chrono::Utc.ymd(2019, 5, 9).and_hms(21, 34, 23);
+        let timeline = placed(start, &[
+            ("a", 0, 50000), ("b", 10000, 20000), ("c", 40000, 40000),
+            ("d", 45000, 1000), ("e", 80000, 0), ("f", 80000, 30000)
+        ]);
+        assert!(holes(&timeline).is_empty());
+        assert_eq!(timeline.unaccounted(), us(0));
+        let gapped = timeline.with_gaps(chrono::Duration::zero(), &gap_label);
+        assert!(gapped.events().iter().all(|e| !e.is_gap()));
+
+        // The hole after a contained event is attributed to the event that
+        // ended last.
+        let timeline = placed(start, &[
+            ("a", 0, 50000), ("b", 10000, 20000), ("c", 70000, 1000)
+        ]);
+        assert_eq!(holes(&timeline), vec![ (String::from("a"), 50000, 20000) ]);
+        let gapped = timeline.with_gaps(chrono::Duration::milliseconds(10),
+            &gap_label);
+        assert_eq!(gapped.events()[2].label(), "gap a/c");
+    }
+
+    ///
+    /// Subtimelines count as single events in their parent, and get gaps of
+    /// their own.
+    ///
+    #[test]
+    fn gaps_nested()
+    {
+        let start = chrono::Utc.ymd(2019, 5, 9).and_hms(21, 34, 23);
+        let inner = placed(start + us(5000), &[
+            ("x", 0, 1000), ("y", 21000, 1000)
+        ]);
+        let mut builder = TimelineBuilder::new_starting(start);
+        builder.add("a", &start, &us(5000));
+        builder.add_timeline("inner", Box::new(inner));
+        builder.add("b", &(start + us(27000)), &us(1000));
+        let timeline = builder.finish();
+
+        assert!(holes(&timeline).is_empty());
+        assert_eq!(timeline.unaccounted(), us(0));
+
+        let gapped = timeline.with_gaps(chrono::Duration::milliseconds(10),
+            &gap_label);
+        assert_eq!(summary(&gapped), vec![
+            (String::from("a"), 0), (String::from("inner"), 3),
+            (String::from("b"), 0)
+        ]);
+        let inner = gapped.events()[1].subtimeline().unwrap();
+        assert_eq!(summary(inner), vec![
+            (String::from("x"), 0), (String::from("gap x/y"), 0),
+            (String::from("y"), 0)
+        ]);
+        assert_eq!(inner.events()[1].wall_start(), start + us(6000));
+        assert_eq!(inner.events()[1].duration(), us(20000));
+        assert_eq!(inner.unaccounted(), us(20000));
+    }
+
+    ///
+    /// Lanes are listed in the order they were first used, and events in
+    /// different lanes may overlap.
+    ///
+    #[test]
+    fn lanes_overlapping()
+    {
+        let start = chrono::Utc.ymd(2019, 5, 9).and_hms(21, 34, 23);
+        let mut builder = TimelineBuilder::new_unanchored();
+        builder.add_to_lane("b", "b-1", &(start + us(500)), &us(2000));
+        builder.add_to_lane("a", "a-1", &start, &us(1000));
+        builder.add_to_lane("b", "b-2", &(start + us(2500)), &us(100));
+        builder.add("unlaned", &(start + us(200)), &us(100));
+        let timeline = builder.try_finish().unwrap();
+
+        assert_eq!(timeline.lanes(), &[ String::from("b"), String::from("a") ]);
+        let events : Vec<(String, Option<&str>)> = timeline.events().iter().map(
+            |e| (e.label(), e.lane())).collect();
+        assert_eq!(events, vec![
+            (String::from("a-1"), Some("a")),
+            (String::from("unlaned"), None),
+            (String::from("b-1"), Some("b")),
+            (String::from("b-2"), Some("b"))
+        ]);
+        assert_eq!(timeline.wall_end(), start + us(2600));
+        assert!(holes(&timeline).is_empty());
+    }
+
+    ///
+    /// Each prepended event could have started up to a microsecond earlier
+    /// than the sum of the (truncated) durations suggests, and that
+    /// uncertainty accumulates as we work backwards.
+    ///
+    #[test]
+    fn prepend_bounds()
+    {
+        let end = chrono::Utc.ymd(2019, 5, 9).and_hms(21, 34, 23);
+        let mut builder = TimelineBuilder::new_ending(end);
+        builder.prepend("third", &us(0));
+        builder.prepend("second", &us(2000));
+        builder.prepend("first", &us(1000));
+        let timeline = builder.finish();
+
+        assert_eq!(timeline.wall_start(), end - us(3000));
+        assert_eq!(timeline.wall_end(), end);
+        let expected = [
+            ("first", -3000, -3003),
+            ("second", -2000, -2002),
+            ("third", 0, -1)
+        ];
+        for (event, (label, wall, earliest)) in
+            timeline.events().iter().zip(expected.iter()) {
+            assert_eq!(event.label(), *label);
+            assert_eq!(event.wall_start(), end + us(*wall));
+            assert_eq!(event.earliest_start(), end + us(*earliest));
+            assert_eq!(event.latest_start(), end + us(*wall));
+            assert_eq!(event.provenance(), TimelineProvenance::Inferred);
+        }
+
+        //
+        // An event for a subtimeline is exactly as uncertain as the
+        // subtimeline's first event.
+        //
+        let mut builder = TimelineBuilder::new_ending(end);
+        builder.add_timeline("sub", Box::new(timeline));
+        let outer = builder.finish();
+        let sub = &outer.events()[0];
+        assert_eq!(sub.wall_start(), end - us(3000));
+        assert_eq!(sub.earliest_start(), end - us(3003));
+        assert_eq!(sub.latest_start(), end - us(3000));
+        assert_eq!(sub.provenance(), TimelineProvenance::Inferred);
+    }
+}
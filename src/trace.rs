@@ -0,0 +1,307 @@
+/*
+ * src/trace.rs: export request timelines in the Chrome trace event format
+ */
+
+//!
+//! # Chrome trace events
+//!
+//! `MantaChromeTraceRenderer` converts a request's timelines into the JSON
+//! object form of the Chrome trace event format, which can be loaded into
+//! Perfetto (https://ui.perfetto.dev) or chrome://tracing.
+//!
+//! The whole request is one process.  Each component that reported events
+//! (the client, the load balancer, Muskie, and so on) gets its own track
+//! (thread), as does each storage node that Muskie contacted.  Events that
+//! take time become complete ("X") events, and subtimelines (e.g., "muskie
+//! handlers" and the handler phases inside it) become complete events
+//! enclosing the events of the subtimeline, so they're shown nested.  Events
+//! that take no time (e.g., "client generated Date header") become instant
+//! ("i") events.  Unaccounted time (see `Timeline::with_gaps()`) goes on a
+//! track of its own.
+//!
+//! Timestamps are microseconds since the start of the request, whose
+//! wall-clock time is recorded in the document's `otherData` along with the
+//! request id.  Each event's `args` include its wall-clock start time and
+//! where that time came from, plus the range of times at which it could have
+//! started if that's uncertain.
+//!
+//! The storage node timeline also has lanes showing what the load balancer and
+//! Muskie were doing at the time, but these are already on the overall
+//! timeline, so only the storage nodes' own events are exported from it.
+//!
+
+use std::io;
+use std::io::Write;
+
+use super::render::MantaRenderer;
+use super::timeline::Timeline;
+use super::timeline::TimelineEvent;
+use super::MantaRequestInfo;
+
+/// process id used for all events (there's only one process: the request)
+const MRI_TRACE_PID : u32 = 1;
+/// track for events that don't say which component reported them
+const MRI_TRACE_TRACK_UNKNOWN : &str = "unknown";
+/// track for unaccounted time
+const MRI_TRACE_TRACK_GAPS : &str = "unaccounted";
+
+#[derive(Serialize)]
+struct MantaTraceDocument {
+    #[serde(rename = "traceEvents")]        mtd_events : Vec<MantaTraceEvent>,
+    #[serde(rename = "displayTimeUnit")]    mtd_time_unit : &'static str,
+    #[serde(rename = "otherData")]          mtd_other : MantaTraceOther,
+}
+
+#[derive(Serialize)]
+struct MantaTraceOther {
+    #[serde(rename = "request_id")]         mto_request_id : Option<String>,
+    #[serde(rename = "start")]              mto_start : String,
+}
+
+#[derive(Serialize)]
+struct MantaTraceEvent {
+    #[serde(rename = "name")]               mte_name : String,
+    #[serde(rename = "cat", skip_serializing_if = "Option::is_none")]
+    mte_category : Option<String>,
+    #[serde(rename = "ph")]                 mte_phase : &'static str,
+    #[serde(rename = "ts", skip_serializing_if = "Option::is_none")]
+    mte_timestamp : Option<i64>,
+    #[serde(rename = "dur", skip_serializing_if = "Option::is_none")]
+    mte_duration : Option<i64>,
+    #[serde(rename = "s", skip_serializing_if = "Option::is_none")]
+    mte_scope : Option<&'static str>,
+    #[serde(rename = "pid")]                mte_pid : u32,
+    #[serde(rename = "tid")]                mte_tid : usize,
+    #[serde(rename = "args")]               mte_args : serde_json::Value,
+}
+
+///
+/// Renders a request's timelines as a Chrome trace event document.  See the
+/// module-level documentation.
+///
+pub struct MantaChromeTraceRenderer;
+
+impl MantaRenderer for MantaChromeTraceRenderer {
+    fn render(&self, mri : &MantaRequestInfo, out : &mut dyn Write)
+        -> io::Result<()>
+    {
+        return mri_dump_trace(out, mri);
+    }
+}
+
+///
+/// Accumulates trace events along with the tracks they're on.
+///
+struct MantaTraceBuilder {
+    /// start of the request, from which all timestamps are measured
+    mtb_base : chrono::DateTime<chrono::Utc>,
+    /// names of the tracks used so far (each track's tid is its index)
+    mtb_tracks : Vec<String>,
+    /// events added so far
+    mtb_events : Vec<MantaTraceEvent>,
+}
+
+impl MantaTraceBuilder {
+    ///
+    /// Returns the tid of the track called `name`, creating it if needed.
+    ///
+    fn track(&mut self, name : &str)
+        -> usize
+    {
+        if let Some(tid) = self.mtb_tracks.iter().position(|t| t == name) {
+            return tid;
+        }
+
+        self.mtb_tracks.push(String::from(name));
+        return self.mtb_tracks.len() - 1;
+    }
+
+    ///
+    /// Adds `event` (and the events of its subtimeline, if any) to the track
+    /// called `track`.
+    ///
+    fn add(&mut self, track : &str, event : &TimelineEvent)
+    {
+        let tid = self.track(track);
+        let mut args = serde_json::Map::new();
+        args.insert(String::from("start"), serde_json::Value::from(
+            mri_trace_time(&event.wall_start())));
+        args.insert(String::from("provenance"), serde_json::Value::from(
+            event.provenance().to_string()));
+        if event.earliest_start() != event.latest_start() {
+            args.insert(String::from("earliest_start"), serde_json::Value::from(
+                mri_trace_time(&event.earliest_start())));
+            args.insert(String::from("latest_start"), serde_json::Value::from(
+                mri_trace_time(&event.latest_start())));
+        }
+
+        let instant = event.duration().is_zero() &&
+            event.subtimeline().is_none();
+        self.mtb_events.push(MantaTraceEvent {
+            mte_name : event.label(),
+            mte_category : Some(String::from(event.source().unwrap_or(
+                if event.is_gap() { MRI_TRACE_TRACK_GAPS }
+                else { MRI_TRACE_TRACK_UNKNOWN }))),
+            mte_phase : if instant { "i" } else { "X" },
+            mte_timestamp : Some(mri_trace_micros(
+                event.wall_start() - self.mtb_base)),
+            mte_duration : if instant { None } else {
+                Some(mri_trace_micros(event.duration())) },
+            mte_scope : if instant { Some("t") } else { None },
+            mte_pid : MRI_TRACE_PID,
+            mte_tid : tid,
+            mte_args : serde_json::Value::Object(args)
+        });
+
+        if let Some(subtimeline) = event.subtimeline() {
+            self.add_timeline(subtimeline, Some(track));
+        }
+    }
+
+    ///
+    /// Adds the events of `timeline`, each on the track for the component that
+    /// reported it.  Events of a subtimeline go on the same track as the event
+    /// containing it (`parent_track`) unless they say otherwise, so that
+    /// they're shown nested inside it.
+    ///
+    fn add_timeline(&mut self, timeline : &Timeline,
+        parent_track : Option<&str>)
+    {
+        for event in timeline.events() {
+            let track = if event.is_gap() {
+                MRI_TRACE_TRACK_GAPS
+            } else {
+                event.source().or(parent_track).unwrap_or(
+                    MRI_TRACE_TRACK_UNKNOWN)
+            };
+            self.add(track, event);
+        }
+    }
+
+    ///
+    /// Returns the metadata events naming each track, followed by all of the
+    /// other events.
+    ///
+    fn finish(self, process_name : String)
+        -> Vec<MantaTraceEvent>
+    {
+        let metadata = |name : &'static str, tid : usize,
+            args : serde_json::Value| MantaTraceEvent {
+            mte_name : String::from(name),
+            mte_category : None,
+            mte_phase : "M",
+            mte_timestamp : None,
+            mte_duration : None,
+            mte_scope : None,
+            mte_pid : MRI_TRACE_PID,
+            mte_tid : tid,
+            mte_args : args
+        };
+
+        //
+        // Tracks are shown in the order the request passed through the
+        // components: the client, then the load balancer, then Muskie, then
+        // the storage nodes.
+        //
+        let mut order : Vec<usize> = (0..self.mtb_tracks.len()).collect();
+        order.sort_by_key(|tid| (mri_trace_track_rank(&self.mtb_tracks[*tid]),
+            *tid));
+
+        let mut events = vec![ metadata("process_name", 0,
+            serde_json::json!({ "name": process_name })) ];
+        for (sort_index, tid) in order.into_iter().enumerate() {
+            events.push(metadata("thread_name", tid,
+                serde_json::json!({ "name": self.mtb_tracks[tid] })));
+            events.push(metadata("thread_sort_index", tid,
+                serde_json::json!({ "sort_index": sort_index })));
+        }
+
+        events.extend(self.mtb_events);
+        return events;
+    }
+}
+
+fn mri_dump_trace(out : &mut dyn Write, mri : &MantaRequestInfo)
+    -> io::Result<()>
+{
+    let overall = &mri.mri_timeline_overall;
+    let base = match mri.mri_timeline_sharks {
+        Some(ref sharks) => std::cmp::min(overall.wall_start(),
+            sharks.wall_start()),
+        None => overall.wall_start()
+    };
+
+    let mut builder = MantaTraceBuilder {
+        mtb_base : base,
+        mtb_tracks : Vec::new(),
+        mtb_events : Vec::new()
+    };
+    builder.add_timeline(overall, None);
+
+    if let Some(ref sharks) = mri.mri_timeline_sharks {
+        for event in sharks.events() {
+            if event.source() != Some("storage node") {
+                continue;
+            }
+
+            let track = match event.lane() {
+                Some(lane) => format!("storage node {}", lane),
+                None => String::from("storage node")
+            };
+            builder.add(&track, event);
+        }
+    }
+
+    let request_id = mri.mri_muskie.request_id();
+    let process_name = format!("{} {}", mri.mri_muskie.mai_req_method,
+        mri.mri_muskie.mai_req_url);
+    let document = MantaTraceDocument {
+        mtd_events : builder.finish(process_name),
+        mtd_time_unit : "ms",
+        mtd_other : MantaTraceOther {
+            mto_request_id : request_id,
+            mto_start : mri_trace_time(&base)
+        }
+    };
+
+    serde_json::to_writer_pretty(&mut *out, &document)?;
+    writeln!(out)?;
+    return Ok(());
+}
+
+///
+/// Returns where the track called `name` goes relative to other tracks.
+///
+fn mri_trace_track_rank(name : &str)
+    -> u8
+{
+    return match name {
+        "client" => 0,
+        "load balancer" => 1,
+        "muskie" => 2,
+        _ if name.starts_with("storage node") => 3,
+        MRI_TRACE_TRACK_GAPS => 5,
+        _ => 4
+    };
+}
+
+///
+/// Returns `duration` in whole microseconds, the unit of timestamps in the
+/// trace event format.
+///
+fn mri_trace_micros(duration : chrono::Duration)
+    -> i64
+{
+    return duration.num_microseconds().unwrap_or_else(
+        || duration.num_milliseconds() * 1000);
+}
+
+///
+/// Formats a wall-clock time for an event's `args`.  This matches the format
+/// of times in the JSON report.
+///
+fn mri_trace_time(time : &chrono::DateTime<chrono::Utc>)
+    -> String
+{
+    return time.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string();
+}
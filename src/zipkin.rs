@@ -0,0 +1,359 @@
+/*
+ * src/zipkin.rs: export a request as Zipkin spans
+ */
+
+//!
+//! # Zipkin spans
+//!
+//! Manta's services don't emit distributed traces themselves, but we can
+//! reconstruct one for a request from its logs.  `MantaZipkinRenderer` writes
+//! a request as a list of spans in the Zipkin v2 JSON format (the body of a
+//! `POST /api/v2/spans` request), which most trace viewers can import.
+//!
+//! * The root span represents Muskie processing the request.  Its trace id
+//!   comes from the request id ("x-request-id"): a request id that's a UUID,
+//!   as Manta's are, is used directly as the 128-bit trace id.  (Anything else
+//!   is hashed to one.)  Its tags describe the request: the route, method,
+//!   path, status code, caller, owner, object id, and metadata shards, plus the
+//!   error, if any.  Point events on the Muskie timeline (e.g., "muskie began
+//!   processing request") become annotations.
+//! * Each Muskie handler that took time becomes a child of the root span,
+//!   tagged with its phase (see the handlers module).
+//! * Each storage node that Muskie contacted becomes a client span that's also
+//!   a child of the root span.  When first byte latency is known, it's an
+//!   annotation.  When the storage node's access log entry was provided, its
+//!   status code and the bytes it sent are tags.
+//!
+//! Span ids are derived from the trace id, so exporting the same request
+//! twice produces the same spans.  All times are as reported by Muskie and so
+//! are not subject to clock skew between the spans.
+//!
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+
+use super::handlers::mri_handler_phase;
+use super::log_mako::MakoLogEntry;
+use super::log_muskie::MuskieAuditInfo;
+use super::log_muskie::MuskieAuditSharkContacted;
+use super::render::MantaRenderer;
+use super::timeline::Timeline;
+use super::timeline::TimelineEvent;
+use super::MantaRequestInfo;
+
+/// service name for spans reported on behalf of Muskie
+const MRI_ZIPKIN_SERVICE_MUSKIE : &str = "muskie";
+/// service name for storage nodes
+const MRI_ZIPKIN_SERVICE_MAKO : &str = "mako";
+
+#[derive(Serialize)]
+struct MantaZipkinSpan {
+    #[serde(rename = "traceId")]        mzs_trace_id : String,
+    #[serde(rename = "id")]             mzs_id : String,
+    #[serde(rename = "parentId", skip_serializing_if = "Option::is_none")]
+    mzs_parent_id : Option<String>,
+    #[serde(rename = "name")]           mzs_name : String,
+    #[serde(rename = "kind", skip_serializing_if = "Option::is_none")]
+    mzs_kind : Option<&'static str>,
+    #[serde(rename = "timestamp")]      mzs_timestamp : i64,
+    #[serde(rename = "duration", skip_serializing_if = "Option::is_none")]
+    mzs_duration : Option<i64>,
+    #[serde(rename = "localEndpoint")]  mzs_local : MantaZipkinEndpoint,
+    #[serde(rename = "remoteEndpoint",
+        skip_serializing_if = "Option::is_none")]
+    mzs_remote : Option<MantaZipkinEndpoint>,
+    #[serde(rename = "annotations", skip_serializing_if = "Vec::is_empty")]
+    mzs_annotations : Vec<MantaZipkinAnnotation>,
+    #[serde(rename = "tags")]
+    mzs_tags : BTreeMap<&'static str, String>,
+}
+
+#[derive(Serialize)]
+struct MantaZipkinEndpoint {
+    #[serde(rename = "serviceName", skip_serializing_if = "Option::is_none")]
+    mze_service_name : Option<&'static str>,
+    #[serde(rename = "ipv4", skip_serializing_if = "Option::is_none")]
+    mze_ipv4 : Option<String>,
+    #[serde(rename = "ipv6", skip_serializing_if = "Option::is_none")]
+    mze_ipv6 : Option<String>,
+}
+
+#[derive(Serialize)]
+struct MantaZipkinAnnotation {
+    #[serde(rename = "timestamp")]      mza_timestamp : i64,
+    #[serde(rename = "value")]          mza_value : String,
+}
+
+///
+/// Renders a request as a list of Zipkin v2 spans.  See the module-level
+/// documentation.
+///
+pub struct MantaZipkinRenderer;
+
+impl MantaRenderer for MantaZipkinRenderer {
+    fn render(&self, mri : &MantaRequestInfo, out : &mut dyn Write)
+        -> io::Result<()>
+    {
+        return mri_dump_zipkin(out, mri);
+    }
+}
+
+fn mri_dump_zipkin(out : &mut dyn Write, mri : &MantaRequestInfo)
+    -> io::Result<()>
+{
+    let muskie_info = &mri.mri_muskie;
+    let request_id = muskie_info.request_id();
+    let trace_id = mri_zipkin_trace_id(&request_id.clone().unwrap_or_else(
+        || format!("{} {} {}", muskie_info.mai_hostname, muskie_info.mai_pid,
+        muskie_info.mai_time.to_rfc3339())));
+    let root_id = mri_zipkin_span_id(&trace_id, 0);
+
+    let mut handlers = Vec::new();
+    mri_zipkin_flatten(&mri.mri_timeline_muskie, &mut handlers);
+
+    let mut spans = vec![ mri_zipkin_root(muskie_info, request_id,
+        &trace_id, &root_id, &mri.mri_timeline_muskie, &handlers) ];
+
+    for event in handlers.iter().filter(|e| !e.duration().is_zero()) {
+        let mut tags = BTreeMap::new();
+        if let Some(phase) = mri_handler_phase(&event.label()) {
+            tags.insert("manta.phase", phase.to_string());
+        }
+
+        spans.push(MantaZipkinSpan {
+            mzs_trace_id : trace_id.clone(),
+            mzs_id : mri_zipkin_span_id(&trace_id, spans.len()),
+            mzs_parent_id : Some(root_id.clone()),
+            mzs_name : event.label(),
+            mzs_kind : None,
+            mzs_timestamp : mri_zipkin_time(&event.wall_start()),
+            mzs_duration : Some(mri_zipkin_duration(event.duration())),
+            mzs_local : mri_zipkin_endpoint(MRI_ZIPKIN_SERVICE_MUSKIE),
+            mzs_remote : None,
+            mzs_annotations : Vec::new(),
+            mzs_tags : tags
+        });
+    }
+
+    if let Some(ref sharks) = muskie_info.mai_sharks_contacted {
+        for (i, shark) in sharks.iter().enumerate() {
+            let mako = mri.mri_mako.get(i).and_then(|m| m.as_ref());
+            let id = mri_zipkin_span_id(&trace_id, spans.len());
+            spans.push(mri_zipkin_shark(shark, mako, &trace_id, &root_id,
+                id));
+        }
+    }
+
+    serde_json::to_writer_pretty(&mut *out, &spans)?;
+    writeln!(out)?;
+    return Ok(());
+}
+
+///
+/// Returns the root span, which represents Muskie processing the request.
+/// `handlers` are the events of the Muskie timeline, whose point events become
+/// annotations.
+///
+fn mri_zipkin_root(muskie_info : &MuskieAuditInfo,
+    request_id : Option<String>, trace_id : &str, root_id : &str,
+    muskie_timeline : &Timeline, handlers : &[&TimelineEvent])
+    -> MantaZipkinSpan
+{
+    let mut tags = BTreeMap::new();
+    tags.insert("http.method", muskie_info.mai_req_method.clone());
+    tags.insert("http.path", muskie_info.mai_req_url.clone());
+    tags.insert("http.status_code",
+        muskie_info.mai_response_status_code.to_string());
+    tags.insert("manta.route", muskie_info.mai_route.clone());
+    tags.insert("manta.operation", muskie_info.mai_operation.clone());
+    tags.insert("manta.caller.login",
+        muskie_info.mai_req_caller_login.clone());
+    tags.insert("manta.caller.uuid", muskie_info.mai_req_caller_uuid.clone());
+    tags.insert("manta.caller.operator",
+        muskie_info.mai_req_caller_operator.to_string());
+    tags.insert("manta.owner.uuid", muskie_info.mai_req_owner_uuid.clone());
+    tags.insert("manta.zone", muskie_info.mai_hostname.clone());
+    tags.insert("manta.pid", muskie_info.mai_pid.clone());
+    if let Some(ref request_id) = request_id {
+        tags.insert("manta.request_id", request_id.clone());
+    }
+    if let Some(ref objectid) = muskie_info.mai_objectid {
+        tags.insert("manta.objectid", objectid.clone());
+    }
+    if let Some(ref shard) = muskie_info.mai_shard_entry {
+        tags.insert("manta.shard.entry", shard.clone());
+    }
+    if let Some(ref shard) = muskie_info.mai_shard_parent {
+        tags.insert("manta.shard.parent", shard.clone());
+    }
+
+    //
+    // By convention, the "error" tag marks a span as failed.
+    //
+    match muskie_info.mai_error {
+        Some(ref error) => {
+            tags.insert("error", format!("{}: {}", error.mle_error_name,
+                error.mle_error_message));
+        },
+        None if muskie_info.mai_response_status_code >= 500 => {
+            tags.insert("error",
+                muskie_info.mai_response_status_code.to_string());
+        },
+        None => ()
+    }
+
+    let remote = muskie_info.mai_remote_address_logical.parse::<
+        std::net::IpAddr>().ok().map(|ip| MantaZipkinEndpoint {
+        mze_service_name : None,
+        mze_ipv4 : if ip.is_ipv4() { Some(ip.to_string()) } else { None },
+        mze_ipv6 : if ip.is_ipv6() { Some(ip.to_string()) } else { None }
+    });
+
+    return MantaZipkinSpan {
+        mzs_trace_id : String::from(trace_id),
+        mzs_id : String::from(root_id),
+        mzs_parent_id : None,
+        mzs_name : muskie_info.mai_route.clone(),
+        mzs_kind : Some("SERVER"),
+        mzs_timestamp : mri_zipkin_time(&muskie_timeline.wall_start()),
+        mzs_duration : Some(mri_zipkin_duration(
+            muskie_timeline.total_elapsed())),
+        mzs_local : mri_zipkin_endpoint(MRI_ZIPKIN_SERVICE_MUSKIE),
+        mzs_remote : remote,
+        mzs_annotations : handlers.iter().filter(
+            |e| e.duration().is_zero()).map(|e| MantaZipkinAnnotation {
+            mza_timestamp : mri_zipkin_time(&e.wall_start()),
+            mza_value : e.label()
+        }).collect(),
+        mzs_tags : tags
+    };
+}
+
+///
+/// Returns a span for Muskie's request to a storage node, whose access log
+/// entry is `mako` (if we have it).
+///
+fn mri_zipkin_shark(shark : &MuskieAuditSharkContacted,
+    mako : Option<&MakoLogEntry>, trace_id : &str, root_id : &str, id : String)
+    -> MantaZipkinSpan
+{
+    let start = shark.mai_shark_time_start;
+    let mut tags = BTreeMap::new();
+    tags.insert("manta.storage_id", shark.mai_shark_storid.clone());
+    tags.insert("manta.shark.success", shark.mai_shark_success.to_string());
+    if !shark.mai_shark_success {
+        tags.insert("error", String::from("request failed"));
+    }
+    if let Some(mako) = mako {
+        tags.insert("http.status_code", mako.mke_status_code.to_string());
+        tags.insert("manta.bytes_sent", mako.mke_bytes_sent.to_string());
+    }
+
+    let mut annotations = Vec::new();
+    if let Some(ttfb) = shark.mai_shark_latency_ttfb {
+        annotations.push(MantaZipkinAnnotation {
+            mza_timestamp : mri_zipkin_time(&(start + ttfb)),
+            mza_value : String::from("first byte")
+        });
+    }
+
+    return MantaZipkinSpan {
+        mzs_trace_id : String::from(trace_id),
+        mzs_id : id,
+        mzs_parent_id : Some(String::from(root_id)),
+        mzs_name : String::from("storage node request"),
+        mzs_kind : Some("CLIENT"),
+        mzs_timestamp : mri_zipkin_time(&start),
+        mzs_duration : shark.mai_shark_latency_total.map(mri_zipkin_duration),
+        mzs_local : mri_zipkin_endpoint(MRI_ZIPKIN_SERVICE_MUSKIE),
+        mzs_remote : Some(mri_zipkin_endpoint(MRI_ZIPKIN_SERVICE_MAKO)),
+        mzs_annotations : annotations,
+        mzs_tags : tags
+    };
+}
+
+///
+/// Appends to `events` the events of `timeline` that aren't subtimelines,
+/// replacing each subtimeline with its own events (recursively).  For the
+/// Muskie timeline, this undoes the grouping of handlers into phases.
+///
+fn mri_zipkin_flatten<'a>(timeline : &'a Timeline,
+    events : &mut Vec<&'a TimelineEvent>)
+{
+    for event in timeline.events() {
+        match event.subtimeline() {
+            Some(subtimeline) => mri_zipkin_flatten(subtimeline, events),
+            None => events.push(event)
+        }
+    }
+}
+
+fn mri_zipkin_endpoint(service_name : &'static str)
+    -> MantaZipkinEndpoint
+{
+    return MantaZipkinEndpoint {
+        mze_service_name : Some(service_name),
+        mze_ipv4 : None,
+        mze_ipv6 : None
+    };
+}
+
+///
+/// Returns the trace id for the request with id `request_id`: the request id
+/// itself, if it's a UUID, or else a hash of it.
+///
+fn mri_zipkin_trace_id(request_id : &str)
+    -> String
+{
+    let hex : String = request_id.chars().filter(|c| *c != '-').collect();
+    if hex.len() == 32 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return hex.to_ascii_lowercase();
+    }
+
+    return format!("{:016x}{:016x}", mri_zipkin_hash(0, request_id),
+        mri_zipkin_hash(1, request_id));
+}
+
+///
+/// Returns the id of the `n`th span in the trace `trace_id`.
+///
+fn mri_zipkin_span_id(trace_id : &str, n : usize)
+    -> String
+{
+    /* Zipkin doesn't allow an id of all zeros. */
+    let id = mri_zipkin_hash(n as u64, trace_id);
+    return format!("{:016x}", if id == 0 { 1 } else { id });
+}
+
+///
+/// Returns the 64-bit FNV-1a hash of `seed` followed by `text`.  We need this
+/// to be the same from one run (and one build) to the next, which isn't true
+/// of the standard library's hasher.
+///
+fn mri_zipkin_hash(seed : u64, text : &str)
+    -> u64
+{
+    return seed.to_le_bytes().iter().chain(text.as_bytes().iter()).fold(
+        0xcbf2_9ce4_8422_2325, |hash : u64, b| (hash ^ u64::from(*b)).
+        wrapping_mul(0x0000_0100_0000_01b3));
+}
+
+/// Returns `time` as microseconds since the Unix epoch.
+fn mri_zipkin_time(time : &chrono::DateTime<chrono::Utc>)
+    -> i64
+{
+    return time.timestamp() * 1_000_000 +
+        i64::from(time.timestamp_subsec_micros());
+}
+
+///
+/// Returns `duration` in microseconds.  Zipkin doesn't allow a duration of
+/// zero, so shorter durations are rounded up to one microsecond.
+///
+fn mri_zipkin_duration(duration : chrono::Duration)
+    -> i64
+{
+    return std::cmp::max(1, duration.num_microseconds().unwrap_or_else(
+        || duration.num_milliseconds() * 1000));
+}
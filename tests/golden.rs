@@ -0,0 +1,275 @@
+/*
+ * tests/golden.rs: compare reports for the files and cases in testdata/ against
+ * their expected output
+ */
+
+//!
+//! For each file "testdata/NAME", we generate the text report, the JSON
+//! report, the Chrome trace, the Zipkin spans, and the Gantt chart (80 columns
+//! wide, with linear and log time axes) for that file alone and compare them
+//! with the contents of "testdata/golden/NAME.txt",
+//! "testdata/golden/NAME.json", "testdata/golden/NAME.trace.json",
+//! "testdata/golden/NAME.zipkin.json", "testdata/golden/NAME.gantt.txt", and
+//! "testdata/golden/NAME.gantt-log.txt", respectively.
+//! If the file can't be processed, the expected output is the error message
+//! instead.
+//!
+//! Reports usually combine several logs, so there are also cases that run the
+//! `mreq` command itself.  Each directory "testdata/cases/NAME" contains:
+//!
+//! * "args": the command-line arguments, one per line.  The command is run in
+//!   the case's directory, so input files are named relative to it.
+//! * "stdin" (optional): the command's standard input
+//! * whatever input files the arguments refer to (which may be compressed or
+//!   archived), unless they're shared with another case
+//!
+//! For each case, we run the command once for each of the text report, the
+//! JSON report, the Chrome trace, and the Gantt chart (80 columns wide), and
+//! compare its output (standard output followed by standard error, and the
+//! exit status if it failed) with the contents of "expected.txt",
+//! "expected.json", "expected.trace.json", and "expected.gantt.txt" in the
+//! case's directory.
+//!
+//! After an intentional change to the output, regenerate the expected output
+//! with:
+//!
+//!     MREQ_UPDATE_GOLDEN=1 cargo test --test golden
+//!
+//! and review the differences before committing them.
+//!
+
+#![allow(clippy::needless_return)]
+
+extern crate manta_mreq;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::process::Stdio;
+
+use manta_mreq::mri_parse_files;
+use manta_mreq::MantaChromeTraceRenderer;
+use manta_mreq::MantaGanttRenderer;
+use manta_mreq::MantaJsonRenderer;
+use manta_mreq::MantaLogParserInput;
+use manta_mreq::MantaRenderer;
+use manta_mreq::MantaRequestFilter;
+use manta_mreq::MantaTextRenderer;
+use manta_mreq::MantaZipkinRenderer;
+
+/* Directory containing input files (relative to the package root) */
+const TESTDATA_DIR : &str = "testdata";
+/* Directory containing expected output */
+const GOLDEN_DIR : &str = "testdata/golden";
+/* Directory containing multi-file cases */
+const CASES_DIR : &str = "testdata/cases";
+/* Environment variable that causes us to update expected output */
+const UPDATE_ENV : &str = "MREQ_UPDATE_GOLDEN";
+
+#[test]
+fn golden_text()
+{
+    golden_check("txt", &MantaTextRenderer);
+}
+
+#[test]
+fn golden_json()
+{
+    golden_check("json", &MantaJsonRenderer);
+}
+
+#[test]
+fn golden_chrome_trace()
+{
+    golden_check("trace.json", &MantaChromeTraceRenderer);
+}
+
+#[test]
+fn golden_zipkin()
+{
+    golden_check("zipkin.json", &MantaZipkinRenderer);
+}
+
+#[test]
+fn golden_gantt()
+{
+    golden_check("gantt.txt", &MantaGanttRenderer::default());
+}
+
+#[test]
+fn golden_gantt_log()
+{
+    golden_check("gantt-log.txt", &MantaGanttRenderer {
+        mgr_log_scale : true,
+        ..MantaGanttRenderer::default()
+    });
+}
+
+#[test]
+fn golden_cases()
+{
+    let outputs : [(&str, &[&str]); 4] = [
+        ("txt", &[ "--output", "text" ]),
+        ("json", &[ "--output", "json" ]),
+        ("trace.json", &[ "--output", "chrome-trace" ]),
+        ("gantt.txt", &[ "--gantt" ])
+    ];
+    let update = std::env::var_os(UPDATE_ENV).is_some();
+    let mut failures = Vec::new();
+
+    for case in golden_cases_list() {
+        for (extension, options) in outputs.iter() {
+            let actual = golden_run(&case, options);
+            let expected_path = case.join(format!("expected.{}", extension));
+
+            if update {
+                fs::write(&expected_path, &actual).unwrap_or_else(|e| panic!(
+                    "write {}: {}", expected_path.display(), e));
+                continue;
+            }
+
+            match fs::read_to_string(&expected_path) {
+                Ok(ref expected) if *expected == actual => (),
+                Ok(_) => failures.push(format!("{}: output differs from {}",
+                    case.display(), expected_path.display())),
+                Err(e) => failures.push(format!("{}: read {}: {}",
+                    case.display(), expected_path.display(), e))
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!("{} case output(s) did not match expected output (set {} to \
+            update it):\n  {}", failures.len(), UPDATE_ENV,
+            failures.join("\n  "));
+    }
+}
+
+///
+/// Renders a report with `renderer` for each input file and compares it to
+/// the expected output in the file with extension `extension`.  Panics with a
+/// list of the files whose output didn't match.
+///
+fn golden_check(extension : &str, renderer : &dyn MantaRenderer)
+{
+    let update = std::env::var_os(UPDATE_ENV).is_some();
+    let mut failures = Vec::new();
+
+    for input in golden_inputs() {
+        let name = input.file_name().unwrap().to_string_lossy().to_string();
+        let actual = golden_render(&input, renderer);
+        let expected_path = Path::new(GOLDEN_DIR).join(
+            format!("{}.{}", name, extension));
+
+        if update {
+            fs::write(&expected_path, &actual).unwrap_or_else(|e| panic!(
+                "write {}: {}", expected_path.display(), e));
+            continue;
+        }
+
+        match fs::read_to_string(&expected_path) {
+            Ok(ref expected) if *expected == actual => (),
+            Ok(_) => failures.push(format!("{}: output differs from {}",
+                input.display(), expected_path.display())),
+            Err(e) => failures.push(format!("{}: read {}: {}",
+                input.display(), expected_path.display(), e))
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!("{} file(s) did not match expected output (set {} to \
+            update it):\n  {}", failures.len(), UPDATE_ENV,
+            failures.join("\n  "));
+    }
+}
+
+///
+/// Returns the input files in the testdata directory, sorted by name.
+///
+fn golden_inputs()
+    -> Vec<std::path::PathBuf>
+{
+    let mut inputs : Vec<std::path::PathBuf> = fs::read_dir(TESTDATA_DIR).
+        unwrap_or_else(|e| panic!("readdir {}: {}", TESTDATA_DIR, e)).
+        map(|entry| entry.expect("readdir entry").path()).
+        filter(|path| path.is_file()).collect();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "no input files found in {}", TESTDATA_DIR);
+    return inputs;
+}
+
+///
+/// Returns the report that `renderer` produces for `input`, or the error
+/// message if the file could not be processed.
+///
+fn golden_render(input : &Path, renderer : &dyn MantaRenderer)
+    -> String
+{
+    let parser_input = MantaLogParserInput {
+        mli_filenames : vec![ input.to_string_lossy().to_string() ],
+        mli_filter : MantaRequestFilter::default(),
+        mli_correct_skew : false
+    };
+
+    let mri = match mri_parse_files(&parser_input) {
+        Ok(mri) => mri,
+        Err(error) => return format!("error: {}\n", error)
+    };
+
+    let mut out = Vec::new();
+    renderer.render(&mri, &mut out).expect("render to memory");
+    return String::from_utf8(out).expect("report is not UTF-8");
+}
+
+///
+/// Returns the case directories in the cases directory, sorted by name.
+///
+fn golden_cases_list()
+    -> Vec<std::path::PathBuf>
+{
+    let mut cases : Vec<std::path::PathBuf> = fs::read_dir(CASES_DIR).
+        unwrap_or_else(|e| panic!("readdir {}: {}", CASES_DIR, e)).
+        map(|entry| entry.expect("readdir entry").path()).
+        filter(|path| path.is_dir()).collect();
+    cases.sort();
+    assert!(!cases.is_empty(), "no cases found in {}", CASES_DIR);
+    return cases;
+}
+
+///
+/// Runs `mreq` for the case in directory `case` with the case's arguments
+/// followed by `options`, and returns what it printed (followed by its exit
+/// status, if it failed).
+///
+fn golden_run(case : &Path, options : &[&str])
+    -> String
+{
+    let args_path = case.join("args");
+    let args = fs::read_to_string(&args_path).unwrap_or_else(
+        |e| panic!("read {}: {}", args_path.display(), e));
+    let stdin_path = case.join("stdin");
+    let stdin = if stdin_path.exists() {
+        Stdio::from(fs::File::open(&stdin_path).unwrap_or_else(
+            |e| panic!("open {}: {}", stdin_path.display(), e)))
+    } else {
+        Stdio::null()
+    };
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mreq")).
+        args(args.lines().filter(|a| !a.is_empty())).
+        args(options).
+        current_dir(case).
+        env("COLUMNS", "80").
+        stdin(stdin).
+        output().
+        unwrap_or_else(|e| panic!("{}: run mreq: {}", case.display(), e));
+
+    let mut result = String::from_utf8(output.stdout).expect(
+        "output is not UTF-8");
+    result.push_str(&String::from_utf8(output.stderr).expect(
+        "error output is not UTF-8"));
+    if !output.status.success() {
+        result.push_str(&format!("{}\n", output.status));
+    }
+    return result;
+}